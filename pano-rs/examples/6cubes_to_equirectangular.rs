@@ -33,18 +33,35 @@ fn main() {
     let headless = glium::backend::glutin::headless::Headless::new(context).unwrap();
 
     println!("image loading");
-    let front_texture = load_png_to_texture(include_bytes!("./6cubes_image/front.png"), &headless);
+    let front_texture = load_png_to_texture(
+        &std::fs::read("examples/6cubes_image/front.png").unwrap(),
+        &headless,
+    );
     println!("front");
-    let back_texture = load_png_to_texture(include_bytes!("./6cubes_image/back.png"), &headless);
+    let back_texture = load_png_to_texture(
+        &std::fs::read("examples/6cubes_image/back.png").unwrap(),
+        &headless,
+    );
     println!("back");
-    let left_texture = load_png_to_texture(include_bytes!("./6cubes_image/left.png"), &headless);
+    let left_texture = load_png_to_texture(
+        &std::fs::read("examples/6cubes_image/left.png").unwrap(),
+        &headless,
+    );
     println!("left");
-    let right_texture = load_png_to_texture(include_bytes!("./6cubes_image/right.png"), &headless);
+    let right_texture = load_png_to_texture(
+        &std::fs::read("examples/6cubes_image/right.png").unwrap(),
+        &headless,
+    );
     println!("right");
-    let top_texture = load_png_to_texture(include_bytes!("./6cubes_image/top.png"), &headless);
+    let top_texture = load_png_to_texture(
+        &std::fs::read("examples/6cubes_image/top.png").unwrap(),
+        &headless,
+    );
     println!("top");
-    let bottom_texture =
-        load_png_to_texture(include_bytes!("./6cubes_image/bottom.png"), &headless);
+    let bottom_texture = load_png_to_texture(
+        &std::fs::read("examples/6cubes_image/bottom.png").unwrap(),
+        &headless,
+    );
     println!("bottom");
     println!("image load done");
 
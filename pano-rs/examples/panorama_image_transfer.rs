@@ -18,7 +18,7 @@ fn main() {
     let headless = glium::backend::glutin::headless::Headless::new(context).unwrap();
 
     let image = image::load(
-        Cursor::new(include_bytes!("../equirectangular.png")),
+        Cursor::new(std::fs::read("equirectangular.png").unwrap()),
         image::ImageFormat::Png,
     )
     .unwrap()
@@ -14,7 +14,7 @@ fn main() {
     let display = glium::Display::new(wb, cb, &event_loop).unwrap();
 
     let image = image::load(
-        Cursor::new(include_bytes!("../panorama_image_transfer.png")),
+        Cursor::new(std::fs::read("panorama_image_transfer.png").unwrap()),
         image::ImageFormat::Png,
     )
     .unwrap()
@@ -0,0 +1,283 @@
+use palette::{Clamp, FromColor, Hsv, Hue, Lab, Srgb};
+use serde::{Deserialize, Serialize};
+
+// number of samples per channel of the 3D lookup table
+pub const LUT_SIZE: usize = 32;
+
+const COLOR_BALANCE_LABELS: [&str; 9] = [
+    "シャドウ シアン-レッド",
+    "シャドウ マゼンタ-グリーン",
+    "シャドウ イエロー-ブルー",
+    "中間調 シアン-レッド",
+    "中間調 マゼンタ-グリーン",
+    "中間調 イエロー-ブルー",
+    "ハイライト シアン-レッド",
+    "ハイライト マゼンタ-グリーン",
+    "ハイライト イエロー-ブルー",
+];
+
+// non-destructive color adjustment applied to the layers below an adjustment layer.
+// every value is 0 when the adjustment does nothing, except the ranges of the levels.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Adjustment {
+    HueSaturation {
+        // degree
+        hue: f32,
+        // -1.0 - 1.0
+        saturation: f32,
+        value: f32,
+    },
+    Levels {
+        input_black: f32,
+        input_white: f32,
+        gamma: f32,
+        output_black: f32,
+        output_white: f32,
+    },
+    Curves {
+        // output for the inputs 0, 0.25, 0.5, 0.75 and 1
+        points: [f32; 5],
+    },
+    ColorBalance {
+        // cyan-red, magenta-green and yellow-blue shifts for each tone range
+        shadows: [f32; 3],
+        midtones: [f32; 3],
+        highlights: [f32; 3],
+    },
+    BrightnessContrast {
+        brightness: f32,
+        contrast: f32,
+    },
+}
+
+impl Adjustment {
+    pub const ALL: [Adjustment; 5] = [
+        Adjustment::HueSaturation {
+            hue: 0.0,
+            saturation: 0.0,
+            value: 0.0,
+        },
+        Adjustment::Levels {
+            input_black: 0.0,
+            input_white: 1.0,
+            gamma: 1.0,
+            output_black: 0.0,
+            output_white: 1.0,
+        },
+        Adjustment::Curves {
+            points: [0.0, 0.25, 0.5, 0.75, 1.0],
+        },
+        Adjustment::ColorBalance {
+            shadows: [0.0; 3],
+            midtones: [0.0; 3],
+            highlights: [0.0; 3],
+        },
+        Adjustment::BrightnessContrast {
+            brightness: 0.0,
+            contrast: 0.0,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Adjustment::HueSaturation { .. } => "色相・彩度",
+            Adjustment::Levels { .. } => "レベル補正",
+            Adjustment::Curves { .. } => "トーンカーブ",
+            Adjustment::ColorBalance { .. } => "カラーバランス",
+            Adjustment::BrightnessContrast { .. } => "明るさ・コントラスト",
+        }
+    }
+
+    // (label, value, min, max) of the editable parameters
+    pub fn params(&self) -> Vec<(&'static str, f32, f32, f32)> {
+        match *self {
+            Adjustment::HueSaturation {
+                hue,
+                saturation,
+                value,
+            } => vec![
+                ("色相", hue, -180.0, 180.0),
+                ("彩度", saturation, -1.0, 1.0),
+                ("明度", value, -1.0, 1.0),
+            ],
+            Adjustment::Levels {
+                input_black,
+                input_white,
+                gamma,
+                output_black,
+                output_white,
+            } => vec![
+                ("入力 黒", input_black, 0.0, 1.0),
+                ("入力 白", input_white, 0.0, 1.0),
+                ("ガンマ", gamma, 0.1, 5.0),
+                ("出力 黒", output_black, 0.0, 1.0),
+                ("出力 白", output_white, 0.0, 1.0),
+            ],
+            Adjustment::Curves { points } => vec![
+                ("0%", points[0], 0.0, 1.0),
+                ("25%", points[1], 0.0, 1.0),
+                ("50%", points[2], 0.0, 1.0),
+                ("75%", points[3], 0.0, 1.0),
+                ("100%", points[4], 0.0, 1.0),
+            ],
+            Adjustment::ColorBalance {
+                shadows,
+                midtones,
+                highlights,
+            } => COLOR_BALANCE_LABELS
+                .iter()
+                .zip(
+                    shadows
+                        .iter()
+                        .chain(midtones.iter())
+                        .chain(highlights.iter()),
+                )
+                .map(|(label, value)| (*label, *value, -1.0, 1.0))
+                .collect(),
+            Adjustment::BrightnessContrast {
+                brightness,
+                contrast,
+            } => vec![
+                ("明るさ", brightness, -1.0, 1.0),
+                ("コントラスト", contrast, -1.0, 1.0),
+            ],
+        }
+    }
+
+    // sets the `idx`-th value of `params`
+    pub fn set_param(&mut self, idx: usize, new_value: f32) {
+        match self {
+            Adjustment::HueSaturation {
+                hue,
+                saturation,
+                value,
+            } => *[hue, saturation, value][idx] = new_value,
+            Adjustment::Levels {
+                input_black,
+                input_white,
+                gamma,
+                output_black,
+                output_white,
+            } => *[input_black, input_white, gamma, output_black, output_white][idx] = new_value,
+            Adjustment::Curves { points } => points[idx] = new_value,
+            Adjustment::ColorBalance {
+                shadows,
+                midtones,
+                highlights,
+            } => [shadows, midtones, highlights][idx / 3][idx % 3] = new_value,
+            Adjustment::BrightnessContrast {
+                brightness,
+                contrast,
+            } => *[brightness, contrast][idx] = new_value,
+        }
+    }
+
+    pub fn apply(&self, color: Srgb) -> Srgb {
+        match *self {
+            Adjustment::HueSaturation {
+                hue,
+                saturation,
+                value,
+            } => {
+                let mut hsv = Hsv::from_color(color).shift_hue(hue);
+                hsv.saturation = scale(hsv.saturation, saturation);
+                hsv.value = scale(hsv.value, value);
+                Srgb::from_color(hsv)
+            }
+            Adjustment::Levels {
+                input_black,
+                input_white,
+                gamma,
+                output_black,
+                output_white,
+            } => map_channels(color, |x| {
+                let x = ((x - input_black) / (input_white - input_black).max(1e-5)).clamp(0.0, 1.0);
+                output_black + x.powf(1.0 / gamma.max(1e-5)) * (output_white - output_black)
+            }),
+            Adjustment::Curves { points } => map_channels(color, |x| curve(&points, x)),
+            Adjustment::ColorBalance {
+                shadows,
+                midtones,
+                highlights,
+            } => {
+                let lightness = Lab::from_color(color).l / 100.0;
+                let shadow = ((0.5 - lightness) * 2.0).clamp(0.0, 1.0);
+                let highlight = ((lightness - 0.5) * 2.0).clamp(0.0, 1.0);
+                let midtone = 1.0 - shadow - highlight;
+                let mut linear = color.into_linear();
+                let shift = |channel: usize| {
+                    (shadows[channel] * shadow
+                        + midtones[channel] * midtone
+                        + highlights[channel] * highlight)
+                        * 0.25
+                };
+                linear.red += shift(0);
+                linear.green += shift(1);
+                linear.blue += shift(2);
+                Srgb::from_linear(linear.clamp())
+            }
+            Adjustment::BrightnessContrast {
+                brightness,
+                contrast,
+            } => {
+                let factor = if contrast >= 0.0 {
+                    1.0 / (1.0 - contrast).max(1e-2)
+                } else {
+                    1.0 + contrast
+                };
+                map_channels(color, |x| (x + brightness - 0.5) * factor + 0.5)
+            }
+        }
+        .clamp()
+    }
+
+    // `LUT_SIZE` slices of `LUT_SIZE` x `LUT_SIZE` (red x green) placed side by side
+    // along x, one slice per blue value, as read by adjustment_layer.frag
+    pub fn lut(&self) -> Vec<u8> {
+        let mut data = vec![0; LUT_SIZE * LUT_SIZE * LUT_SIZE * 4];
+        let step = 1.0 / (LUT_SIZE - 1) as f32;
+        for g in 0..LUT_SIZE {
+            for b in 0..LUT_SIZE {
+                for r in 0..LUT_SIZE {
+                    let color =
+                        self.apply(Srgb::new(r as f32 * step, g as f32 * step, b as f32 * step));
+                    let offset = (g * LUT_SIZE * LUT_SIZE + b * LUT_SIZE + r) * 4;
+                    data[offset] = (color.red * 255.0).round() as u8;
+                    data[offset + 1] = (color.green * 255.0).round() as u8;
+                    data[offset + 2] = (color.blue * 255.0).round() as u8;
+                    data[offset + 3] = 255;
+                }
+            }
+        }
+        data
+    }
+}
+
+// moves `x` (0.0 - 1.0) towards 1 for positive `amount` and towards 0 for negative one
+fn scale(x: f32, amount: f32) -> f32 {
+    if amount >= 0.0 {
+        x + (1.0 - x) * amount
+    } else {
+        x * (1.0 + amount)
+    }
+}
+
+fn map_channels(color: Srgb, f: impl Fn(f32) -> f32) -> Srgb {
+    Srgb::new(f(color.red), f(color.green), f(color.blue))
+}
+
+// Catmull-Rom spline through the equally spaced `points`
+fn curve(points: &[f32; 5], x: f32) -> f32 {
+    let segments = (points.len() - 1) as f32;
+    let position = x.clamp(0.0, 1.0) * segments;
+    let i = (position.floor() as usize).min(points.len() - 2);
+    let t = position - i as f32;
+    let p0 = points[i.saturating_sub(1)];
+    let p1 = points[i];
+    let p2 = points[i + 1];
+    let p3 = points[(i + 2).min(points.len() - 1)];
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+}
@@ -0,0 +1,345 @@
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+
+use crate::error::PanoError;
+use crate::file_io::encode_png;
+use crate::storage::storage;
+
+// animated images of panorama frames, e.g. a numbered equirectangular sequence or a turntable of the view.
+// the image crate does not write APNG, so the chunks of png encoded frames are rearranged into one.
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnimationFormat {
+    // APNG
+    Png,
+    Gif,
+}
+
+impl AnimationFormat {
+    pub fn from_path(path: &Path) -> Result<Self, PanoError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "png" | "apng" => Ok(AnimationFormat::Png),
+            "gif" => Ok(AnimationFormat::Gif),
+            _ => Err(PanoError::UnsupportedFormat(extension)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AnimationSettings {
+    // frames per second
+    pub frame_rate: f32,
+    // times the animation is played, 0 is forever
+    pub play_count: u16,
+    // frames of a turntable, which turns the view once
+    pub turntable_frames: usize,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        AnimationSettings {
+            frame_rate: 12.0,
+            play_count: 0,
+            turntable_frames: 36,
+        }
+    }
+}
+
+impl AnimationSettings {
+    fn delay_ms(&self) -> u32 {
+        (1000.0 / self.frame_rate.max(0.1)).round() as u32
+    }
+}
+
+// the frames have the same size
+pub fn encode_animation(
+    frames: Vec<RgbaImage>,
+    format: AnimationFormat,
+    settings: &AnimationSettings,
+) -> Result<Vec<u8>, PanoError> {
+    if frames.is_empty() {
+        return Err(PanoError::Encode(String::from("no frame")));
+    }
+    let size = frames[0].dimensions();
+    if let Some(frame) = frames.iter().find(|frame| frame.dimensions() != size) {
+        return Err(PanoError::Encode(format!(
+            "a frame is {}x{}, not {}x{}",
+            frame.width(),
+            frame.height(),
+            size.0,
+            size.1
+        )));
+    }
+    match format {
+        AnimationFormat::Png => encode_apng(frames, settings),
+        AnimationFormat::Gif => encode_gif(frames, settings),
+    }
+}
+
+fn encode_gif(frames: Vec<RgbaImage>, settings: &AnimationSettings) -> Result<Vec<u8>, PanoError> {
+    let mut bytes = vec![];
+    {
+        // faster quantization than the default, the frames are large
+        let mut encoder = GifEncoder::new_with_speed(&mut bytes, 10);
+        // gif counts the repetitions after the first play
+        let repeat = match settings.play_count {
+            0 => Repeat::Infinite,
+            count => Repeat::Finite(count - 1),
+        };
+        encoder
+            .set_repeat(repeat)
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+        let delay = Delay::from_numer_denom_ms(settings.delay_ms(), 1);
+        encoder
+            .encode_frames(
+                frames
+                    .into_iter()
+                    .map(|frame| Frame::from_parts(frame, 0, 0, delay)),
+            )
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+    }
+    Ok(bytes)
+}
+
+fn encode_apng(frames: Vec<RgbaImage>, settings: &AnimationSettings) -> Result<Vec<u8>, PanoError> {
+    let (width, height) = frames[0].dimensions();
+    let frame_count = frames.len() as u32;
+    let mut bytes = PNG_SIGNATURE.to_vec();
+    // fcTL and fdAT share the sequence numbers
+    let mut sequence = 0;
+    for (idx, frame) in frames.into_iter().enumerate() {
+        let png = encode_png(frame)?;
+        let chunks = png_chunks(&png)?;
+        if idx == 0 {
+            for (chunk_type, data) in chunks.iter() {
+                if chunk_type == b"IHDR" {
+                    write_chunk(&mut bytes, b"IHDR", data);
+                }
+            }
+            let mut actl = frame_count.to_be_bytes().to_vec();
+            actl.extend_from_slice(&(settings.play_count as u32).to_be_bytes());
+            write_chunk(&mut bytes, b"acTL", &actl);
+        }
+
+        let mut fctl = vec![];
+        for value in [sequence, width, height, 0, 0] {
+            fctl.extend_from_slice(&value.to_be_bytes());
+        }
+        fctl.extend_from_slice(&(settings.delay_ms() as u16).to_be_bytes());
+        fctl.extend_from_slice(&1000u16.to_be_bytes());
+        // no dispose, the frames are drawn over nothing
+        fctl.extend_from_slice(&[0, 0]);
+        write_chunk(&mut bytes, b"fcTL", &fctl);
+        sequence += 1;
+
+        for (chunk_type, data) in chunks
+            .iter()
+            .filter(|(chunk_type, _)| chunk_type == b"IDAT")
+        {
+            if idx == 0 {
+                // the first frame is also the still image
+                write_chunk(&mut bytes, chunk_type, data);
+            } else {
+                let mut fdat = sequence.to_be_bytes().to_vec();
+                fdat.extend_from_slice(data);
+                write_chunk(&mut bytes, b"fdAT", &fdat);
+                sequence += 1;
+            }
+        }
+    }
+    write_chunk(&mut bytes, b"IEND", &[]);
+    Ok(bytes)
+}
+
+// (type, data)
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+fn png_chunks(png: &[u8]) -> Result<Vec<Chunk<'_>>, PanoError> {
+    let broken = || PanoError::Encode(String::from("broken png"));
+    let mut chunks = vec![];
+    let mut position = PNG_SIGNATURE.len();
+    while position + 8 <= png.len() {
+        let length = u32::from_be_bytes([
+            png[position],
+            png[position + 1],
+            png[position + 2],
+            png[position + 3],
+        ]) as usize;
+        let chunk_type = [
+            png[position + 4],
+            png[position + 5],
+            png[position + 6],
+            png[position + 7],
+        ];
+        let data = png
+            .get(position + 8..position + 8 + length)
+            .ok_or_else(broken)?;
+        chunks.push((chunk_type, data));
+        position += 12 + length;
+    }
+    Ok(chunks)
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = bytes.len();
+    bytes.extend_from_slice(chunk_type);
+    bytes.extend_from_slice(data);
+    let crc = crc32fast::hash(&bytes[start..]);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+}
+
+// `first` and the files numbered after it, e.g. equirectangular_0001.png, equirectangular_0002.png, ...
+// until a number is missing in the storage.
+pub async fn frame_sequence(first: &Path) -> Vec<PathBuf> {
+    let stem = first
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    let digits = stem
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let (prefix, number) = stem.split_at(stem.len() - digits);
+    let number = match number.parse::<usize>() {
+        Ok(number) => number,
+        // not numbered
+        Err(_) => return vec![first.to_path_buf()],
+    };
+    let extension = first
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let storage = storage();
+    let mut frames = vec![];
+    for number in number.. {
+        let path = first.with_file_name(format!(
+            "{}{:0width$}{}",
+            prefix,
+            number,
+            extension,
+            width = digits
+        ));
+        if !storage.exists(&path).await {
+            break;
+        }
+        frames.push(path);
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::storage::{set_storage, MemoryStorage, Storage};
+
+    fn frames(count: u8) -> Vec<RgbaImage> {
+        (0..count)
+            .map(|idx| RgbaImage::from_pixel(4, 2, image::Rgba([idx * 40, 0, 255 - idx * 40, 255])))
+            .collect()
+    }
+
+    #[test]
+    fn apng_frames_delays_and_play_count() {
+        let settings = AnimationSettings {
+            frame_rate: 8.0,
+            play_count: 3,
+            turntable_frames: 36,
+        };
+        let bytes = encode_animation(frames(3), AnimationFormat::Png, &settings).unwrap();
+
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!((animation.num_frames, animation.num_plays), (3, 3));
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        for (idx, frame) in frames(3).iter().enumerate() {
+            reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control().unwrap();
+            assert_eq!((control.delay_num, control.delay_den), (125, 1000));
+            assert_eq!((control.width, control.height), (4, 2));
+            assert_eq!(&buffer, frame.as_raw(), "frame {}", idx);
+        }
+        assert!(reader.next_frame(&mut buffer).is_err());
+    }
+
+    // the loop count of the NETSCAPE2.0 application extension, which is not written to play once
+    fn gif_loop_count(bytes: &[u8]) -> Option<u16> {
+        let position = bytes
+            .windows(11)
+            .position(|window| window == b"NETSCAPE2.0")?;
+        // the sub-block size and id precede the count
+        Some(u16::from_le_bytes([
+            bytes[position + 13],
+            bytes[position + 14],
+        ]))
+    }
+
+    #[test]
+    fn gif_repeats_after_the_first_play() {
+        let encode = |play_count| {
+            let settings = AnimationSettings {
+                play_count,
+                ..AnimationSettings::default()
+            };
+            encode_animation(frames(2), AnimationFormat::Gif, &settings).unwrap()
+        };
+        // 0 loops forever
+        assert_eq!(gif_loop_count(&encode(0)), Some(0));
+        assert_eq!(gif_loop_count(&encode(1)), None);
+        assert_eq!(gif_loop_count(&encode(3)), Some(2));
+    }
+
+    #[test]
+    fn frame_sequence_stops_at_a_missing_number() {
+        let memory = MemoryStorage::default();
+        block_on(async {
+            for name in [
+                "frame_0009.png",
+                "frame_0010.png",
+                "frame_0011.png",
+                "frame_0013.png",
+            ] {
+                memory
+                    .write(&Path::new("frames").join(name), vec![])
+                    .await
+                    .unwrap();
+            }
+        });
+        set_storage(Rc::new(memory));
+        assert_eq!(
+            block_on(frame_sequence(Path::new("frames/frame_0009.png"))),
+            vec![
+                PathBuf::from("frames/frame_0009.png"),
+                PathBuf::from("frames/frame_0010.png"),
+                PathBuf::from("frames/frame_0011.png"),
+            ]
+        );
+        assert_eq!(
+            block_on(frame_sequence(Path::new("frames/frame_0013.png"))),
+            vec![PathBuf::from("frames/frame_0013.png")]
+        );
+    }
+
+    #[test]
+    fn frame_sequence_of_an_unnumbered_file() {
+        set_storage(Rc::new(MemoryStorage::default()));
+        assert_eq!(
+            block_on(frame_sequence(Path::new("frames/still.png"))),
+            vec![PathBuf::from("frames/still.png")]
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use std::path::Path;
+use std::time::Duration;
+
+use yew::prelude::*;
+use yew::services::timeout::{TimeoutService, TimeoutTask};
+use yew::{ChangeData, InputData};
+
+use crate::brush_preset::{BrushPreset, BrushPresetLibrary};
+
+pub enum Msg {
+    Select { idx: usize },
+    Create,
+    Duplicate,
+    Delete,
+    Rename { name: String },
+    ChangeColor { color: [f32; 3] },
+    ChangeOpacity { opacity: f32 },
+    ChangeSize { size: f32 },
+    ChangeHardness { hardness: f32 },
+    ChangeIntensity { intensity: f32 },
+    Import,
+    Export,
+    Imported { library: BrushPresetLibrary },
+    Loaded { library: BrushPresetLibrary },
+    SelectProjectPreset { idx: usize },
+    ImportProjectPresets,
+    Save,
+    Saved,
+}
+
+// the sliders change the library on every input, it is saved after they stop
+const SAVE_DELAY_MS: u64 = 500;
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct Props {
+    pub on_change: Callback<BrushPreset>,
+    pub on_error: Callback<String>,
+    // presets coming from an opened project, kept out of the library until imported
+    #[prop_or_default]
+    pub project_presets: Option<BrushPresetLibrary>,
+}
+
+pub struct BrushPresetPanel {
+    link: ComponentLink<Self>,
+    on_change: Callback<BrushPreset>,
+    on_error: Callback<String>,
+    project_presets: Option<BrushPresetLibrary>,
+
+    library: BrushPresetLibrary,
+    selected: usize,
+    // the library can not be edited until the saved one is loaded, it would be overwritten
+    loaded: bool,
+    save_task: Option<TimeoutTask>,
+    // one save runs at a time, a change while saving saves again after it
+    saving: bool,
+    save_pending: bool,
+}
+
+impl Component for BrushPresetPanel {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        // the defaults until the saved presets are loaded
+        let library = BrushPresetLibrary::default();
+        props.on_change.emit(library.presets[0].clone());
+        let loaded = link.clone();
+        let on_error = props.on_error.clone();
+        // stays read-only when the saved library can not be read, saving would overwrite it
+        wasm_bindgen_futures::spawn_local(async move {
+            match BrushPresetLibrary::load_library().await {
+                Ok(library) => loaded.send_message(Msg::Loaded { library }),
+                Err(err) => on_error.emit(err.to_string()),
+            }
+        });
+
+        Self {
+            link,
+            on_change: props.on_change,
+            on_error: props.on_error,
+            project_presets: props.project_presets,
+            library,
+            selected: 0,
+            loaded: false,
+            save_task: None,
+            saving: false,
+            save_pending: false,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        let editing = !matches!(
+            msg,
+            Msg::Loaded { .. }
+                | Msg::SelectProjectPreset { .. }
+                | Msg::Export
+                | Msg::Save
+                | Msg::Saved
+        );
+        if editing && !self.loaded {
+            return false;
+        }
+        match msg {
+            Msg::Select { idx } => {
+                self.selected = idx;
+                self.on_change.emit(self.current().clone());
+                return true;
+            }
+            Msg::Create => {
+                self.library.presets.push(BrushPreset {
+                    name: self.library.unique_name("brush"),
+                    ..BrushPreset::default()
+                });
+                self.selected = self.library.presets.len() - 1;
+            }
+            Msg::Duplicate => {
+                let mut preset = self.current().clone();
+                preset.name = self.library.unique_name(&preset.name);
+                self.library.presets.insert(self.selected + 1, preset);
+                self.selected += 1;
+            }
+            Msg::Delete => {
+                // the last preset is kept, a preset is always selected
+                if self.library.presets.len() <= 1 {
+                    return false;
+                }
+                self.library.presets.remove(self.selected);
+                self.selected = self.selected.min(self.library.presets.len() - 1);
+            }
+            Msg::Rename { name } => {
+                if name.is_empty() || name == self.current().name {
+                    return true;
+                }
+                let name = self.library.unique_name(&name);
+                self.current_mut().name = name;
+            }
+            Msg::ChangeColor { color } => {
+                self.current_mut().color = color;
+            }
+            Msg::ChangeOpacity { opacity } => {
+                self.current_mut().opacity = opacity;
+            }
+            Msg::ChangeSize { size } => {
+                self.current_mut().size = size;
+            }
+            Msg::ChangeHardness { hardness } => {
+                self.current_mut().hardness = hardness;
+            }
+            Msg::ChangeIntensity { intensity } => {
+                self.current_mut().intensity = intensity;
+            }
+            Msg::Import => {
+                let dialog_promise: js_sys::Promise =
+                    crate::wasm_bind::show_open_json_dialog().unwrap().into();
+                let link = self.link.clone();
+                let on_error = self.on_error.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
+                        .await
+                        .unwrap();
+                    if let Some(path) = path_or_undefined.as_string() {
+                        match BrushPresetLibrary::load(Path::new(&path)).await {
+                            Ok(library) => link.send_message(Msg::Imported { library }),
+                            Err(err) => on_error.emit(err.to_string()),
+                        }
+                    }
+                });
+                return false;
+            }
+            Msg::Export => {
+                let dialog_promise: js_sys::Promise =
+                    crate::wasm_bind::show_save_json_dialog().unwrap().into();
+                let library = self.library.clone();
+                let on_error = self.on_error.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
+                        .await
+                        .unwrap();
+                    if let Some(path) = path_or_undefined.as_string() {
+                        if let Err(err) = library.save(Path::new(&path)).await {
+                            on_error.emit(err.to_string());
+                        }
+                    }
+                });
+                return false;
+            }
+            Msg::Imported { library } => {
+                self.library.merge(library);
+            }
+            Msg::Loaded { library } => {
+                self.library = library;
+                self.selected = 0;
+                self.loaded = true;
+                self.on_change.emit(self.current().clone());
+                return true;
+            }
+            Msg::SelectProjectPreset { idx } => {
+                if let Some(preset) = self
+                    .project_presets
+                    .as_ref()
+                    .and_then(|presets| presets.presets.get(idx))
+                {
+                    self.on_change.emit(preset.clone());
+                }
+                return false;
+            }
+            Msg::ImportProjectPresets => match self.project_presets.take() {
+                Some(presets) => self.library.merge(presets),
+                None => return false,
+            },
+            Msg::Save => {
+                self.save_task = None;
+                if self.saving {
+                    self.save_pending = true;
+                } else {
+                    self.start_save();
+                }
+                return false;
+            }
+            Msg::Saved => {
+                self.saving = false;
+                if self.save_pending {
+                    self.save_pending = false;
+                    self.start_save();
+                }
+                return false;
+            }
+        }
+        // only the changes of the library are saved
+        self.schedule_save();
+        self.on_change.emit(self.current().clone());
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.on_change = props.on_change;
+        self.on_error = props.on_error;
+        if props.project_presets != self.project_presets {
+            self.project_presets = props.project_presets;
+            return true;
+        }
+        false
+    }
+
+    fn view(&self) -> Html {
+        let current = self.current();
+        let deletable = self.loaded && self.library.presets.len() > 1;
+        html! {
+            <div id="brush_preset_panel">
+                <select
+                    size="6"
+                    disabled=!self.loaded
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            Some(Msg::Select{idx: select.selected_index() as usize})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for self.library.presets.iter().enumerate().map(|(idx, preset)| html! {
+                            <option selected=idx == self.selected>{ &preset.name }</option>
+                        })
+                    }
+                </select>
+                <br />
+                <button
+                    disabled=!self.loaded
+                    onclick=self.link.callback(|_| Msg::Create)
+                >{ "新規" }</button>
+                <button
+                    disabled=!self.loaded
+                    onclick=self.link.callback(|_| Msg::Duplicate)
+                >{ "複製" }</button>
+                <button
+                    disabled=!deletable
+                    onclick=self.link.callback(|_| Msg::Delete)
+                >{ "削除" }</button>
+                <button
+                    disabled=!self.loaded
+                    onclick=self.link.callback(|_| Msg::Import)
+                >{ "インポート" }</button>
+                <button onclick=self.link.callback(|_| Msg::Export)>{ "エクスポート" }</button>
+                <br />
+                <label>{ "名前" }</label>
+                <input
+                    disabled=!self.loaded
+                    value=current.name.clone()
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            Some(Msg::Rename{name: value})
+                        } else {
+                            None
+                        }})
+                />
+                <br />
+                <label>{ "色" }</label>
+                <input
+                    type="color"
+                    disabled=!self.loaded
+                    value=color_to_hex(current.color)
+                    oninput=self.link.batch_callback(|e: InputData| hex_to_color(&e.value).map(|color| Msg::ChangeColor{color}))
+                />
+                <br />
+                <label>{ format!("不透明度={:.2}", current.opacity) }</label>
+                <input
+                    type="range"
+                    disabled=!self.loaded
+                    min="0"
+                    max="1"
+                    step="0.01"
+                    value=current.opacity.to_string()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeOpacity{opacity: e.value.parse::<f32>().unwrap()})
+                />
+                <br />
+                <label>{ format!("サイズ={:.3}", current.size) }</label>
+                <input
+                    type="range"
+                    disabled=!self.loaded
+                    min="0.002"
+                    max="0.2"
+                    step="0.001"
+                    value=current.size.to_string()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeSize{size: e.value.parse::<f32>().unwrap()})
+                />
+                <br />
+                <label>{ format!("硬さ={:.2}", current.hardness) }</label>
+                <input
+                    type="range"
+                    disabled=!self.loaded
+                    min="0"
+                    max="1"
+                    step="0.01"
+                    value=current.hardness.to_string()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeHardness{hardness: e.value.parse::<f32>().unwrap()})
+                />
+                <br />
+                <label>{ format!("強度 (浮動小数点)={:.1}", current.intensity) }</label>
+                <input
+                    type="range"
+                    disabled=!self.loaded
+                    min="1"
+                    max="16"
+                    step="0.1"
+                    value=current.intensity.to_string()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeIntensity{intensity: e.value.parse::<f32>().unwrap()})
+                />
+                { self.view_project_presets() }
+            </div>
+        }
+    }
+}
+
+impl BrushPresetPanel {
+    fn current(&self) -> &BrushPreset {
+        &self.library.presets[self.selected]
+    }
+
+    fn current_mut(&mut self) -> &mut BrushPreset {
+        &mut self.library.presets[self.selected]
+    }
+
+    // restarts the delay, dropping the previous task cancels it
+    fn schedule_save(&mut self) {
+        self.save_task = Some(TimeoutService::spawn(
+            Duration::from_millis(SAVE_DELAY_MS),
+            self.link.callback(|_| Msg::Save),
+        ));
+    }
+
+    fn start_save(&mut self) {
+        self.saving = true;
+        let library = self.library.clone();
+        let link = self.link.clone();
+        let on_error = self.on_error.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(err) = library.save_library().await {
+                on_error.emit(err.to_string());
+            }
+            link.send_message(Msg::Saved);
+        });
+    }
+
+    // the presets of the opened project can be used without adding them to the library
+    fn view_project_presets(&self) -> Html {
+        let presets = match &self.project_presets {
+            Some(presets) => presets,
+            None => return html! {},
+        };
+        html! {
+            <div>
+                <label>{ "プロジェクトのプリセット" }</label>
+                <br />
+                <select
+                    size="4"
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            Some(Msg::SelectProjectPreset{idx: select.selected_index() as usize})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for presets.presets.iter().map(|preset| html! {
+                            <option>{ &preset.name }</option>
+                        })
+                    }
+                </select>
+                <br />
+                <button
+                    disabled=!self.loaded
+                    onclick=self.link.callback(|_| Msg::ImportProjectPresets)
+                >{ "ライブラリに取り込む" }</button>
+            </div>
+        }
+    }
+}
+
+fn color_to_hex(color: [f32; 3]) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        (color[0] * 255.0).round() as u8,
+        (color[1] * 255.0).round() as u8,
+        (color[2] * 255.0).round() as u8
+    )
+}
+
+fn hex_to_color(hex: &str) -> Option<[f32; 3]> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let mut color = [0.0; 3];
+    for (idx, value) in color.iter_mut().enumerate() {
+        *value = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok()? as f32 / 255.0;
+    }
+    Some(color)
+}
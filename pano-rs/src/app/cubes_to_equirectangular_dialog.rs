@@ -1,12 +1,18 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
-use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{HtmlInputElement, WebGlShader};
 use yew::prelude::*;
 
-use crate::file_io::{read_image, write_image};
+use crate::cpu::equirectangular_to_cubes;
+use crate::cube_face_convention::{
+    read_cube_faces, write_cube_faces, CubeFaceConvention, CubeFacePreset, CUBE_FACE_NAMES,
+};
+use crate::error::PanoError;
+use crate::file_io::{read_image, write_image, ExportFormat};
+use crate::storage::storage;
+use crate::wasm_bind::yield_now;
 use crate::webgl_utils::{compile_shader, get_uniform_locations, link_program};
 use crate::WebGl2RenderingContext;
 
@@ -18,11 +24,40 @@ pub enum Msg {
     OpenAnimationDirectoryDialog,
     Convert,
     ConvertAnimation,
+    ChangeFirstFrame { frame: Option<usize> },
+    ChangeLastFrame { frame: Option<usize> },
+    SwitchSkipExisting,
+    AnimationProgress { done: usize, total: usize },
+    AnimationFinished,
+    ChangeFacePreset { preset: CubeFacePreset },
+    RotateFace { face: usize },
+    SwitchFlipFace { face: usize },
+    OpenEquirectangularDialog,
+    OpenCubesOutputDialog,
+    ExportCubes,
+}
+
+// which frames of an animation are converted
+#[derive(Clone, Copy)]
+struct AnimationOptions {
+    // the numbers of the frame directories, inclusive
+    first_frame: Option<usize>,
+    last_frame: Option<usize>,
+    // frames whose output exists already are not converted again
+    skip_existing: bool,
+}
+
+impl AnimationOptions {
+    fn contains(&self, frame: usize) -> bool {
+        self.first_frame.is_none_or(|first| first <= frame)
+            && self.last_frame.is_none_or(|last| frame <= last)
+    }
 }
 
 #[derive(Properties, Clone, PartialEq)]
 pub struct Props {
     pub open: bool,
+    pub on_error: Callback<String>,
 }
 
 pub struct CubesToEquirectangularDialog {
@@ -31,8 +66,17 @@ pub struct CubesToEquirectangularDialog {
     webgl: Option<Arc<RwLock<ModelWebGL>>>,
     input_ref: NodeRef,
     animation_input_ref: NodeRef,
+    animation_options: AnimationOptions,
+    // (converted, total) frames of the last animation
+    animation_progress: Option<(usize, usize)>,
+    animation_running: bool,
+    // the names and orientations of the face files, of both the import and the export
+    convention: CubeFaceConvention,
+    equirectangular_input_ref: NodeRef,
+    cubes_output_ref: NodeRef,
 
     open: bool,
+    on_error: Callback<String>,
 }
 
 pub struct ModelWebGL {
@@ -55,7 +99,18 @@ impl Component for CubesToEquirectangularDialog {
             webgl: None,
             input_ref,
             animation_input_ref,
+            animation_options: AnimationOptions {
+                first_frame: None,
+                last_frame: None,
+                skip_existing: false,
+            },
+            animation_progress: None,
+            animation_running: false,
+            convention: CubeFaceConvention::default(),
+            equirectangular_input_ref: NodeRef::default(),
+            cubes_output_ref: NodeRef::default(),
             open: props.open,
+            on_error: props.on_error,
         }
     }
 
@@ -136,25 +191,131 @@ impl Component for CubesToEquirectangularDialog {
             }
             Msg::Convert => {
                 if let Some(input) = self.input_ref.cast::<HtmlInputElement>() {
-                    self.webgl
-                        .as_ref()
-                        .unwrap()
-                        .read()
-                        .unwrap()
-                        .convert(Path::new(&input.value()))
-                        .unwrap();
+                    let webgl = self.webgl.as_ref().unwrap().clone();
+                    let path = PathBuf::from(input.value());
+                    let convention = self.convention.clone();
+                    let on_error = self.on_error.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let output_path = path.join("equirectangular.png");
+                        let result = convert_frame(&webgl, &path, &output_path, &convention).await;
+                        if let Err(err) = result {
+                            on_error.emit(err.to_string());
+                        }
+                    });
                 }
                 false
             }
             Msg::ConvertAnimation => {
+                if self.animation_running {
+                    return false;
+                }
                 if let Some(animation_input) = self.animation_input_ref.cast::<HtmlInputElement>() {
-                    self.webgl
-                        .as_ref()
-                        .unwrap()
-                        .read()
+                    let webgl = self.webgl.as_ref().unwrap().clone();
+                    let path = PathBuf::from(animation_input.value());
+                    let options = self.animation_options;
+                    let convention = self.convention.clone();
+                    let link = self.link.clone();
+                    let on_error = self.on_error.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let result =
+                            convert_animation(webgl, &path, options, &convention, |done, total| {
+                                link.send_message(Msg::AnimationProgress { done, total })
+                            })
+                            .await;
+                        if let Err(err) = result {
+                            on_error.emit(err.to_string());
+                        }
+                        link.send_message(Msg::AnimationFinished);
+                    });
+                    self.animation_running = true;
+                    self.animation_progress = None;
+                    return true;
+                }
+                false
+            }
+            Msg::ChangeFirstFrame { frame } => {
+                self.animation_options.first_frame = frame;
+                true
+            }
+            Msg::ChangeLastFrame { frame } => {
+                self.animation_options.last_frame = frame;
+                true
+            }
+            Msg::SwitchSkipExisting => {
+                self.animation_options.skip_existing = !self.animation_options.skip_existing;
+                true
+            }
+            Msg::AnimationProgress { done, total } => {
+                self.animation_progress = Some((done, total));
+                true
+            }
+            Msg::AnimationFinished => {
+                self.animation_running = false;
+                true
+            }
+            Msg::ChangeFacePreset { preset } => {
+                self.convention = CubeFaceConvention::preset(preset);
+                true
+            }
+            Msg::RotateFace { face } => {
+                let orientation = &mut self.convention.orientations[face];
+                orientation.rotation = (orientation.rotation + 1) % 4;
+                true
+            }
+            Msg::SwitchFlipFace { face } => {
+                let orientation = &mut self.convention.orientations[face];
+                orientation.flip = !orientation.flip;
+                true
+            }
+            Msg::OpenEquirectangularDialog => {
+                let dialog_promise: js_sys::Promise =
+                    crate::wasm_bind::show_open_image_dialog().unwrap().into();
+                let input_ref = self.equirectangular_input_ref.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
+                        .await
+                        .unwrap();
+                    if let Some(path) = path_or_undefined.as_string() {
+                        if let Some(input) = input_ref.cast::<HtmlInputElement>() {
+                            input.set_value(path.as_str());
+                        }
+                    }
+                });
+                false
+            }
+            Msg::OpenCubesOutputDialog => {
+                let dialog_promise: js_sys::Promise =
+                    crate::wasm_bind::show_open_directory_dialog()
                         .unwrap()
-                        .convert_animation(Path::new(&animation_input.value()))
+                        .into();
+                let output_ref = self.cubes_output_ref.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
+                        .await
                         .unwrap();
+                    if let Some(path) = path_or_undefined.as_string() {
+                        if let Some(input) = output_ref.cast::<HtmlInputElement>() {
+                            input.set_value(path.as_str());
+                        }
+                    }
+                });
+                false
+            }
+            Msg::ExportCubes => {
+                if let (Some(input), Some(output)) = (
+                    self.equirectangular_input_ref.cast::<HtmlInputElement>(),
+                    self.cubes_output_ref.cast::<HtmlInputElement>(),
+                ) {
+                    let input_path = PathBuf::from(input.value());
+                    let output_path = PathBuf::from(output.value());
+                    let convention = self.convention.clone();
+                    let on_error = self.on_error.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let result = export_cubes(&input_path, &output_path, &convention).await;
+                        if let Err(err) = result {
+                            on_error.emit(err.to_string());
+                        }
+                    });
                 }
                 false
             }
@@ -162,6 +323,7 @@ impl Component for CubesToEquirectangularDialog {
     }
 
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.on_error = props.on_error;
         if self.open != props.open {
             self.open = props.open;
             true
@@ -179,7 +341,14 @@ impl Component for CubesToEquirectangularDialog {
                 >
                     {"6 cubes to equirectangular"}
                     <br />
-                    {"6cubes images: front.png, back.png, left.png, right.png, top.png, botton.pngが入ったディレクトリを指定してください"}
+                    { self.view_convention() }
+                    <br />
+                    {
+                        format!(
+                            "6cubes images: {}が入ったディレクトリを指定してください",
+                            (0..6).map(|face| self.convention.file_name(face)).collect::<Vec<_>>().join(", "),
+                        )
+                    }
                     <br />
                     <input
                         ref={self.input_ref.clone()}
@@ -190,11 +359,86 @@ impl Component for CubesToEquirectangularDialog {
                     <br />
                     {"animation: frame0001/*.png, frame0002/*.pngという形式になっているディレクトリを指定してください"}
                     <br/>
+                    {"各フレームはequirectangular_0001.png, equirectangular_0002.pngとして書き出されます"}
+                    <br/>
                     <input
                         ref={self.animation_input_ref.clone()}
                     />
                     <button onclick=self.link.callback(|_| Msg::OpenAnimationDirectoryDialog)>{ "ファイルを選択" }</button>
-                    <button onclick=self.link.callback(|_| Msg::ConvertAnimation)>{ "変換" }</button>
+                    <button
+                        onclick=self.link.callback(|_| Msg::ConvertAnimation)
+                        disabled=self.animation_running
+                    >
+                        { "変換" }
+                    </button>
+                    <br/>
+                    <label for="first_frame">{ "開始フレーム" }</label>
+                    <input
+                        type="number"
+                        id="first_frame"
+                        min="0"
+                        value=self.animation_options.first_frame.map_or(String::new(), |frame| frame.to_string())
+                        onchange=self.link.batch_callback(|e: ChangeData| {
+                            if let ChangeData::Value(value) = e {
+                                Some(Msg::ChangeFirstFrame{frame: value.parse::<usize>().ok()})
+                            } else {
+                                None
+                            }})
+                    />
+                    <label for="last_frame">{ "終了フレーム" }</label>
+                    <input
+                        type="number"
+                        id="last_frame"
+                        min="0"
+                        value=self.animation_options.last_frame.map_or(String::new(), |frame| frame.to_string())
+                        onchange=self.link.batch_callback(|e: ChangeData| {
+                            if let ChangeData::Value(value) = e {
+                                Some(Msg::ChangeLastFrame{frame: value.parse::<usize>().ok()})
+                            } else {
+                                None
+                            }})
+                    />
+                    <button onclick=self.link.callback(|_| Msg::SwitchSkipExisting)>
+                        {
+                            if self.animation_options.skip_existing {
+                                "既存のフレームを飛ばす"
+                            } else {
+                                "既存のフレームを上書き"
+                            }
+                        }
+                    </button>
+                    {
+                        match self.animation_progress {
+                            Some((done, total)) => html! {
+                                <div>
+                                    {
+                                        format!(
+                                            "{}: {} / {}",
+                                            if self.animation_running { "変換中" } else { "完了" },
+                                            done,
+                                            total,
+                                        )
+                                    }
+                                </div>
+                            },
+                            None if self.animation_running => html! { <div>{ "フレームを探しています" }</div> },
+                            None => html! {},
+                        }
+                    }
+                    <br />
+                    <br />
+                    {"equirectangular to 6 cubes: equirectangularの画像を上の形式のキューブの画像として書き出すディレクトリを指定してください"}
+                    <br />
+                    <input
+                        ref={self.equirectangular_input_ref.clone()}
+                    />
+                    <button onclick=self.link.callback(|_| Msg::OpenEquirectangularDialog)>{ "ファイルを選択" }</button>
+                    <br />
+                    <input
+                        ref={self.cubes_output_ref.clone()}
+                    />
+                    <button onclick=self.link.callback(|_| Msg::OpenCubesOutputDialog)>{ "ファイルを選択" }</button>
+                    <button onclick=self.link.callback(|_| Msg::ExportCubes)>{ "書き出し" }</button>
                     <canvas
                         id="6cubes_canvas"
                         height="1"
@@ -206,21 +450,156 @@ impl Component for CubesToEquirectangularDialog {
     }
 }
 
+impl CubesToEquirectangularDialog {
+    // the preset, and the rotation and the flip of each face on it
+    fn view_convention(&self) -> Html {
+        let preset = self.convention.matching_preset();
+        html! {
+            <>
+                <label for="cube_face_preset">{ "キューブの形式" }</label>
+                <select
+                    id="cube_face_preset"
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            CubeFacePreset::ALL
+                                .get(select.selected_index() as usize)
+                                .map(|preset| Msg::ChangeFacePreset{preset: *preset})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for CubeFacePreset::ALL.iter().map(|option| html! {
+                            <option selected=preset == Some(*option)>{ option.label() }</option>
+                        })
+                    }
+                    {
+                        if preset.is_none() {
+                            html! { <option selected=true>{ "カスタム" }</option> }
+                        } else {
+                            html! {}
+                        }
+                    }
+                </select>
+                {
+                    for CUBE_FACE_NAMES.iter().enumerate().map(|(face, name)| {
+                        let orientation = self.convention.orientations[face];
+                        html! {
+                            <div>
+                                { format!("{}: {}", name, self.convention.file_name(face)) }
+                                <button onclick=self.link.callback(move |_| Msg::RotateFace{face})>
+                                    { format!("回転 {}°", orientation.rotation as u32 * 90) }
+                                </button>
+                                <button onclick=self.link.callback(move |_| Msg::SwitchFlipFace{face})>
+                                    { if orientation.flip { "左右反転" } else { "反転なし" } }
+                                </button>
+                            </div>
+                        }
+                    })
+                }
+            </>
+        }
+    }
+}
+
+// the equirectangular image at `input_path` to the face files in `output_path`,
+// the faces are a quarter of the width
+async fn export_cubes(
+    input_path: &Path,
+    output_path: &Path,
+    convention: &CubeFaceConvention,
+) -> Result<(), PanoError> {
+    let image = read_image(input_path).await?;
+    let cubes = equirectangular_to_cubes(&image, image.width() / 4);
+    write_cube_faces(output_path, &cubes, convention).await
+}
+
+// converts the frame directories `frameNNNN` in `path` to `equirectangular_NNNN.png` in `path`,
+// `progress` is called with (converted, total) frames. the lock is released between the frames.
+async fn convert_animation(
+    webgl: Arc<RwLock<ModelWebGL>>,
+    path: &Path,
+    options: AnimationOptions,
+    convention: &CubeFaceConvention,
+    progress: impl Fn(usize, usize),
+) -> Result<(), PanoError> {
+    let frames = animation_frames(path, &options).await?;
+    progress(0, frames.len());
+    for (idx, (frame, frame_path)) in frames.iter().enumerate() {
+        let output_path = path.join(format!("equirectangular_{:>04}.png", frame));
+        if !(options.skip_existing && storage().exists(&output_path).await) {
+            convert_frame(&webgl, frame_path, &output_path, convention).await?;
+        }
+        progress(idx + 1, frames.len());
+        yield_now().await?;
+    }
+    Ok(())
+}
+
+// (number, path) of the frame directories in the range, in the order of the numbers
+async fn animation_frames(
+    path: &Path,
+    options: &AnimationOptions,
+) -> Result<Vec<(usize, PathBuf)>, PanoError> {
+    let storage = storage();
+    if !storage.is_dir(path).await {
+        return Err(PanoError::Io(format!(
+            "{} is not a directory",
+            path.display()
+        )));
+    }
+    let mut frames: Vec<(usize, PathBuf)> = vec![];
+    for name in storage.list_dir(path).await? {
+        let frame = match name
+            .strip_prefix("frame")
+            .and_then(|frame| frame.parse::<usize>().ok())
+        {
+            Some(frame) if options.contains(frame) => frame,
+            _ => continue,
+        };
+        let frame_path = path.join(&name);
+        if storage.is_dir(&frame_path).await {
+            frames.push((frame, frame_path));
+        }
+    }
+    frames.sort();
+    Ok(frames)
+}
+
+// the cube faces in `path` to an equirectangular png at `output_path`
+async fn convert_frame(
+    webgl: &RwLock<ModelWebGL>,
+    path: &Path,
+    output_path: &Path,
+    convention: &CubeFaceConvention,
+) -> Result<(), PanoError> {
+    let cubes = read_cube_faces(path, convention).await?;
+    let data = webgl.read().unwrap().render_equirectangular(&cubes.faces)?;
+    write_image(output_path, data, ExportFormat::Png).await
+}
+
 impl ModelWebGL {
-    pub fn convert(&self, path: &Path) -> Result<(), JsValue> {
-        let front_image = read_image(Path::new(path.join("front.png").as_path()));
-        let back_image = read_image(Path::new(path.join("back.png").as_path()));
-        let left_image = read_image(Path::new(path.join("left.png").as_path()));
-        let right_image = read_image(Path::new(path.join("right.png").as_path()));
-        let top_image = read_image(Path::new(path.join("top.png").as_path()));
-        let bottom_image = read_image(Path::new(path.join("bottom.png").as_path()));
-
-        let front_texture = self.context.create_texture().unwrap();
-        let back_texture = self.context.create_texture().unwrap();
-        let left_texture = self.context.create_texture().unwrap();
-        let right_texture = self.context.create_texture().unwrap();
-        let top_texture = self.context.create_texture().unwrap();
-        let bottom_texture = self.context.create_texture().unwrap();
+    // `faces` are front, back, left, right, top and bottom
+    pub fn render_equirectangular(
+        &self,
+        faces: &[image::RgbaImage],
+    ) -> Result<image::RgbaImage, PanoError> {
+        let [front_image, back_image, left_image, right_image, top_image, bottom_image] = faces
+        else {
+            return Err(PanoError::Decode(String::from("6 cube faces are needed")));
+        };
+
+        let create_texture = || {
+            self.context
+                .create_texture()
+                .ok_or_else(|| PanoError::Gl(String::from("failed to create a texture")))
+        };
+        let front_texture = create_texture()?;
+        let back_texture = create_texture()?;
+        let left_texture = create_texture()?;
+        let right_texture = create_texture()?;
+        let top_texture = create_texture()?;
+        let bottom_texture = create_texture()?;
 
         for (texture, image) in [
             (&front_texture, &front_image),
@@ -237,8 +616,8 @@ impl ModelWebGL {
                     WebGl2RenderingContext::TEXTURE_2D,
                     0,
                     WebGl2RenderingContext::RGBA as i32,
-                    WORK_TEXTURE_WIDTH as i32,
-                    WORK_TEXTURE_WIDTH as i32,
+                    image.width() as i32,
+                    image.height() as i32,
                     0,
                     WebGl2RenderingContext::RGBA,
                     WebGl2RenderingContext::UNSIGNED_BYTE,
@@ -258,7 +637,7 @@ impl ModelWebGL {
                 .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
         }
 
-        let output_texture = self.context.create_texture().unwrap();
+        let output_texture = create_texture()?;
         self.context
             .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&output_texture));
         self.context
@@ -272,8 +651,7 @@ impl ModelWebGL {
                 WebGl2RenderingContext::RGBA,
                 WebGl2RenderingContext::UNSIGNED_BYTE,
                 None,
-            )
-            .unwrap();
+            )?;
         self.context.tex_parameteri(
             WebGl2RenderingContext::TEXTURE_2D,
             WebGl2RenderingContext::TEXTURE_MAG_FILTER,
@@ -287,7 +665,10 @@ impl ModelWebGL {
         self.context
             .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
 
-        let frame_buffer = self.context.create_framebuffer().unwrap();
+        let frame_buffer = self
+            .context
+            .create_framebuffer()
+            .ok_or_else(|| PanoError::Gl(String::from("failed to create a framebuffer")))?;
         self.context
             .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
 
@@ -359,39 +740,21 @@ impl ModelWebGL {
             .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
         self.context
             .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
-
-        let data =
-            image::RgbaImage::from_vec(WORK_TEXTURE_WIDTH as u32, WORK_TEXTURE_HEIGHT as u32, data)
-                .unwrap();
-        write_image(path.join("equirectangular.png").as_path(), data);
-        Ok(())
-    }
-
-    pub fn convert_animation(&self, path: &Path) -> Result<(), JsValue> {
-        let mut idx = 1;
-        loop {
-            let frame_path = path.join(format!("frame{:>04}", idx));
-            crate::console_log!(
-                "{:?} {:?} {} {} {} {} {}",
-                frame_path,
-                path,
-                crate::wasm_bind::is_directory(path.to_str().unwrap()),
-                frame_path.is_dir(),
-                path.is_dir(),
-                frame_path.exists(),
-                path.exists()
-            );
-            if !frame_path.exists() {
-                crate::console_log!("not exists");
-                break;
-            }
-            if frame_path.is_file() {
-                crate::console_log!("is_file");
-                break;
-            }
-
-            idx += 1;
+        // an animation converts many frames
+        self.context.delete_framebuffer(Some(&frame_buffer));
+        for texture in [
+            &front_texture,
+            &back_texture,
+            &left_texture,
+            &right_texture,
+            &top_texture,
+            &bottom_texture,
+            &output_texture,
+        ] {
+            self.context.delete_texture(Some(texture));
         }
-        Ok(())
+
+        image::RgbaImage::from_vec(WORK_TEXTURE_WIDTH as u32, WORK_TEXTURE_HEIGHT as u32, data)
+            .ok_or_else(|| PanoError::Gl(String::from("the pixels do not fill the image")))
     }
 }
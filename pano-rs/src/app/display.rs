@@ -0,0 +1,68 @@
+use crate::pixel_format::PixelFormat;
+
+// curves bringing the colors above 1.0 of float documents into the range of the display
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ToneMapping {
+    None,
+    Reinhard,
+    AcesFilmic,
+}
+
+impl ToneMapping {
+    pub const ALL: [ToneMapping; 3] = [
+        ToneMapping::None,
+        ToneMapping::Reinhard,
+        ToneMapping::AcesFilmic,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapping::None => "なし",
+            ToneMapping::Reinhard => "Reinhard",
+            ToneMapping::AcesFilmic => "ACES フィルミック",
+        }
+    }
+
+    // `tone_mapping` uniform of show_panorama.frag and show_premultiplied.frag
+    pub fn uniform(&self) -> i32 {
+        match self {
+            ToneMapping::None => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::AcesFilmic => 2,
+        }
+    }
+}
+
+// how the colors of the layers are shown on the canvas, the document is not changed
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct DisplaySettings {
+    // stops, the colors are multiplied by 2^exposure before the tone mapping
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    // the tone mapped colors are raised to 1 / gamma
+    pub gamma: f32,
+    // red from the left eye and cyan from the right eye, in stereo documents
+    pub anaglyph: bool,
+}
+
+impl DisplaySettings {
+    // shows the colors as they are
+    pub const IDENTITY: DisplaySettings = DisplaySettings {
+        exposure: 0.0,
+        tone_mapping: ToneMapping::None,
+        gamma: 1.0,
+        anaglyph: false,
+    };
+
+    // float documents are linear, the gamma brings them close to srgb
+    pub fn for_format(format: PixelFormat) -> Self {
+        if format.is_float() {
+            DisplaySettings {
+                gamma: 2.2,
+                ..DisplaySettings::IDENTITY
+            }
+        } else {
+            DisplaySettings::IDENTITY
+        }
+    }
+}
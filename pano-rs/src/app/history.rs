@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+
+use super::layer::Layer;
+use crate::stereo::Eye;
+
+pub const TILE_SIZE: usize = 128;
+pub const DEFAULT_HISTORY_DEPTH: usize = 50;
+
+// a rectangle of a layer texture before and after an operation, in the format of the layer
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+pub enum HistoryEntry {
+    Pixels {
+        layer_id: u32,
+        // the eye of the texture in stereo documents, always left in the others
+        eye: Eye,
+        tiles: Vec<Tile>,
+    },
+    // `layer` holds the layer while it is out of the stack,
+    // i.e. while an insertion is undone or a deletion is not undone
+    InsertLayer {
+        idx: usize,
+        layer_id: u32,
+        layer: Option<Layer>,
+    },
+    DeleteLayer {
+        idx: usize,
+        layer_id: u32,
+        layer: Option<Layer>,
+    },
+    // undone and redone at once, e.g. an operation on every layer
+    Batch(Vec<HistoryEntry>),
+}
+
+pub struct History {
+    undo_stack: VecDeque<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+    depth: usize,
+    // changes whenever the document is modified through the history, e.g. to skip unneeded autosaves
+    revision: u64,
+}
+
+impl History {
+    pub fn new(depth: usize) -> Self {
+        History {
+            undo_stack: VecDeque::new(),
+            redo_stack: vec![],
+            depth,
+            revision: 0,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // returns the entries which do not fit anymore
+    pub fn set_depth(&mut self, depth: usize) -> Vec<HistoryEntry> {
+        self.depth = depth;
+        let mut dropped = vec![];
+        while self.undo_stack.len() > self.depth {
+            dropped.extend(self.undo_stack.pop_front());
+        }
+        dropped
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    // for the changes which are saved but not undoable, e.g. the opacity of a layer
+    pub fn mark_modified(&mut self) {
+        self.revision += 1;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    // returns the entries which are discarded by this push
+    pub fn push(&mut self, entry: HistoryEntry) -> Vec<HistoryEntry> {
+        self.revision += 1;
+        let mut dropped: Vec<HistoryEntry> = self.redo_stack.drain(..).collect();
+        self.undo_stack.push_back(entry);
+        while self.undo_stack.len() > self.depth {
+            dropped.extend(self.undo_stack.pop_front());
+        }
+        dropped
+    }
+
+    pub fn pop_undo(&mut self) -> Option<HistoryEntry> {
+        self.revision += 1;
+        self.undo_stack.pop_back()
+    }
+
+    pub fn push_redo(&mut self, entry: HistoryEntry) {
+        self.redo_stack.push(entry);
+    }
+
+    pub fn pop_redo(&mut self) -> Option<HistoryEntry> {
+        self.revision += 1;
+        self.redo_stack.pop()
+    }
+
+    // unlike `push`, keeps the redo stack
+    pub fn push_undo(&mut self, entry: HistoryEntry) {
+        self.undo_stack.push_back(entry);
+    }
+
+    // returns every entry, e.g. to release their textures
+    pub fn clear(&mut self) -> Vec<HistoryEntry> {
+        self.revision += 1;
+        let mut dropped: Vec<HistoryEntry> = self.undo_stack.drain(..).collect();
+        dropped.append(&mut self.redo_stack);
+        dropped
+    }
+}
+
+// compares two images of a rectangle at (x0, y0) tile by tile, whose pixels are `pixel_size` bytes.
+// the tiles are placed in the coordinates of the whole layer
+pub fn diff_tiles(
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    pixel_size: usize,
+    before: &[u8],
+    after: &[u8],
+) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            let tile_width = TILE_SIZE.min(width - x);
+            let tile_height = TILE_SIZE.min(height - y);
+            let rows = (y..y + tile_height).map(|row| {
+                let start = (row * width + x) * pixel_size;
+                start..start + tile_width * pixel_size
+            });
+            if rows
+                .clone()
+                .all(|range| before[range.clone()] == after[range])
+            {
+                continue;
+            }
+            tiles.push(Tile {
+                x: x0 + x,
+                y: y0 + y,
+                width: tile_width,
+                height: tile_height,
+                before: rows
+                    .clone()
+                    .flat_map(|range| before[range].to_vec())
+                    .collect(),
+                after: rows.flat_map(|range| after[range].to_vec()).collect(),
+            });
+        }
+    }
+    tiles
+}
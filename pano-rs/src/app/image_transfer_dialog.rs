@@ -1,12 +1,13 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
-use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{HtmlInputElement, WebGlShader};
 use yew::prelude::*;
 
-use crate::file_io::{read_image, write_image};
+use super::selection::{apply_mask, SelectionMask};
+use crate::error::PanoError;
+use crate::file_io::{read_image, write_image, ExportFormat, DEFAULT_QUALITY};
 use crate::webgl_utils::{compile_shader, get_uniform_locations, link_program};
 use crate::WebGl2RenderingContext;
 
@@ -22,6 +23,10 @@ pub enum Msg {
 #[derive(Properties, Clone, PartialEq)]
 pub struct Props {
     pub open: bool,
+    // only the selected area of the output is transferred
+    #[prop_or_default]
+    pub selection: Option<SelectionMask>,
+    pub on_error: Callback<String>,
 }
 
 pub struct ImageTransferDialog {
@@ -32,6 +37,8 @@ pub struct ImageTransferDialog {
     input_of_output_image_ref: NodeRef,
 
     open: bool,
+    selection: Option<SelectionMask>,
+    on_error: Callback<String>,
 }
 
 pub struct ModelWebGL {
@@ -56,6 +63,8 @@ impl Component for ImageTransferDialog {
             input_of_output_image_ref,
 
             open: props.open,
+            selection: props.selection,
+            on_error: props.on_error,
         }
     }
 
@@ -75,19 +84,6 @@ impl Component for ImageTransferDialog {
             context.clear_color(0.0, 0.0, 0.0, 1.0);
             context.enable(WebGl2RenderingContext::BLEND);
 
-            let all_view_vert_shader = crate::webgl_utils::read_shader(
-                Path::new("./pano-rs/src/shaders/all_view.vert"),
-                &context,
-                WebGl2RenderingContext::VERTEX_SHADER,
-            )
-            .unwrap();
-            let image_transfer_frag_shader = crate::webgl_utils::read_shader(
-                Path::new("./pano-rs/src/shaders/image_transfer.frag"),
-                &context,
-                WebGl2RenderingContext::FRAGMENT_SHADER,
-            )
-            .unwrap();
-            /*
             let all_view_vert_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::VERTEX_SHADER,
@@ -100,7 +96,6 @@ impl Component for ImageTransferDialog {
                 include_str!("../shaders/image_transfer.frag"),
             )
             .unwrap();
-            */
 
             self.webgl = Some(Arc::new(RwLock::new(ModelWebGL {
                 context,
@@ -114,7 +109,7 @@ impl Component for ImageTransferDialog {
         match msg {
             Msg::OpenInputImageDialog => {
                 let dialog_promise: js_sys::Promise =
-                    crate::wasm_bind::show_open_png_dialog().unwrap().into();
+                    crate::wasm_bind::show_open_image_dialog().unwrap().into();
                 let input_of_input_image_ref = self.input_of_input_image_ref.clone();
                 wasm_bindgen_futures::spawn_local(async move {
                     let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
@@ -130,7 +125,7 @@ impl Component for ImageTransferDialog {
             }
             Msg::OpenOutputImageDialog => {
                 let dialog_promise: js_sys::Promise =
-                    crate::wasm_bind::show_save_png_dialog().unwrap().into();
+                    crate::wasm_bind::show_save_image_dialog().unwrap().into();
                 let input_of_output_image_ref = self.input_of_output_image_ref.clone();
                 wasm_bindgen_futures::spawn_local(async move {
                     let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
@@ -151,17 +146,23 @@ impl Component for ImageTransferDialog {
                     if let Some(input_of_output_image) =
                         self.input_of_output_image_ref.cast::<HtmlInputElement>()
                     {
-                        let input_image_path = input_of_input_image.value();
-                        let input_image_path = Path::new(&input_image_path);
-                        let output_image_path = input_of_output_image.value();
-                        let output_image_path = Path::new(&output_image_path);
-                        self.webgl
-                            .as_ref()
-                            .unwrap()
-                            .read()
-                            .unwrap()
-                            .transfer(input_image_path, output_image_path)
-                            .unwrap();
+                        let input_image_path = PathBuf::from(input_of_input_image.value());
+                        let output_image_path = PathBuf::from(input_of_output_image.value());
+                        let webgl = self.webgl.as_ref().unwrap().clone();
+                        let selection = self.selection.clone();
+                        let on_error = self.on_error.clone();
+                        wasm_bindgen_futures::spawn_local(async move {
+                            let result = transfer(
+                                &webgl,
+                                &input_image_path,
+                                &output_image_path,
+                                selection.as_ref(),
+                            )
+                            .await;
+                            if let Err(err) = result {
+                                on_error.emit(err.to_string());
+                            }
+                        });
                     }
                 }
                 false
@@ -170,6 +171,8 @@ impl Component for ImageTransferDialog {
     }
 
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.selection = props.selection;
+        self.on_error = props.on_error;
         if self.open != props.open {
             self.open = props.open;
             true
@@ -209,13 +212,45 @@ impl Component for ImageTransferDialog {
     }
 }
 
+async fn transfer(
+    webgl: &RwLock<ModelWebGL>,
+    input_image_path: &Path,
+    output_image_path: &Path,
+    selection: Option<&SelectionMask>,
+) -> Result<(), PanoError> {
+    let output_format = ExportFormat::from_path(output_image_path, DEFAULT_QUALITY)?;
+    let input_image = read_image(input_image_path).await?;
+    let data = webgl.read().unwrap().transfer(&input_image, selection)?;
+    write_image(output_image_path, data, output_format).await
+}
+
 impl ModelWebGL {
     pub fn transfer(
         &self,
-        input_image_path: &Path,
-        output_image_path: &Path,
-    ) -> Result<(), JsValue> {
-        let input_image = read_image(input_image_path);
+        input_image: &image::RgbaImage,
+        selection: Option<&SelectionMask>,
+    ) -> Result<image::RgbaImage, PanoError> {
+        // the input is an equirectangular panorama, resampled to the work size
+        let (width, height) = input_image.dimensions();
+        if width == 0 || width != 2 * height {
+            return Err(PanoError::UnsupportedFormat(format!(
+                "{}x{} (2:1のパノラマ画像を選択してください)",
+                width, height
+            )));
+        }
+        let resized;
+        let input_image =
+            if (width as usize, height as usize) == (WORK_TEXTURE_WIDTH, WORK_TEXTURE_HEIGHT) {
+                input_image
+            } else {
+                resized = image::imageops::resize(
+                    input_image,
+                    WORK_TEXTURE_WIDTH as u32,
+                    WORK_TEXTURE_HEIGHT as u32,
+                    image::imageops::FilterType::Triangle,
+                );
+                &resized
+            };
 
         let input_texture = self.context.create_texture().unwrap();
         let output_texture = self.context.create_texture().unwrap();
@@ -303,10 +338,25 @@ impl ModelWebGL {
         self.context
             .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
 
-        let data =
+        if let Some(selection) = selection {
+            // the mask is in the work size of the document
+            let mask = selection.0.as_ref();
+            if mask.dimensions() == (WORK_TEXTURE_WIDTH as u32, WORK_TEXTURE_HEIGHT as u32) {
+                apply_mask(input_image.as_raw(), &mut data, mask.as_raw());
+            } else {
+                let mask = image::imageops::resize(
+                    mask,
+                    WORK_TEXTURE_WIDTH as u32,
+                    WORK_TEXTURE_HEIGHT as u32,
+                    image::imageops::FilterType::Triangle,
+                );
+                apply_mask(input_image.as_raw(), &mut data, mask.as_raw());
+            }
+        }
+
+        Ok(
             image::RgbaImage::from_vec(WORK_TEXTURE_WIDTH as u32, WORK_TEXTURE_HEIGHT as u32, data)
-                .unwrap();
-        write_image(output_image_path, data);
-        Ok(())
+                .unwrap(),
+        )
     }
 }
@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlTexture;
+
+use super::tiled_texture::TiledTexture;
+use crate::adjustment::{Adjustment, LUT_SIZE};
+use crate::pixel_format::PixelFormat;
+use crate::stereo::Eye;
+use crate::WebGl2RenderingContext;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BlendMode {
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Normal,
+        BlendMode::Add,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "通常",
+            BlendMode::Add => "加算",
+            BlendMode::Multiply => "乗算",
+            BlendMode::Screen => "スクリーン",
+        }
+    }
+
+    // layers are drawn with premultiplied alpha, so every mode keeps the
+    // destination alpha as in the normal mode.
+    pub fn apply(&self, context: &WebGl2RenderingContext) {
+        let (src_rgb, dst_rgb) = match self {
+            BlendMode::Normal => (
+                WebGl2RenderingContext::ONE,
+                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Add => (WebGl2RenderingContext::ONE, WebGl2RenderingContext::ONE),
+            BlendMode::Multiply => (
+                WebGl2RenderingContext::DST_COLOR,
+                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Screen => (
+                WebGl2RenderingContext::ONE,
+                WebGl2RenderingContext::ONE_MINUS_SRC_COLOR,
+            ),
+        };
+        context.enable(WebGl2RenderingContext::BLEND);
+        context.blend_func_separate(
+            src_rgb,
+            dst_rgb,
+            WebGl2RenderingContext::ONE,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+    }
+}
+
+static NEXT_LAYER_ID: AtomicU32 = AtomicU32::new(0);
+
+pub struct Layer {
+    // unique in the session, unlike the index in the stack
+    pub id: u32,
+    pub name: String,
+    pub texture: TiledTexture,
+    // the texture of the other eye in stereo documents, swapped with `texture`
+    // so that `texture` is always of the eye being shown and edited
+    pub other_eye: Option<TiledTexture>,
+    // the eye of `texture`
+    pub eye: Eye,
+    pub visible: bool,
+    pub opacity: f32,
+    pub locked: bool,
+    pub blend_mode: BlendMode,
+    // adjustment layers change the layers below instead of being drawn,
+    // their texture is the mask of the adjustment
+    pub adjustment: Option<Adjustment>,
+    pub lut: Option<WebGlTexture>,
+}
+
+impl Layer {
+    pub fn new(name: String, texture: TiledTexture) -> Self {
+        Layer {
+            id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            texture,
+            other_eye: None,
+            eye: Eye::Left,
+            visible: true,
+            opacity: 1.0,
+            locked: false,
+            blend_mode: BlendMode::Normal,
+            adjustment: None,
+            lut: None,
+        }
+    }
+
+    pub fn set_adjustment(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        adjustment: Adjustment,
+    ) -> Result<(), JsValue> {
+        let lut = create_layer_texture(
+            context,
+            LUT_SIZE * LUT_SIZE,
+            LUT_SIZE,
+            Some(adjustment.lut().as_slice()),
+        )?;
+        if let Some(old_lut) = self.lut.replace(lut) {
+            context.delete_texture(Some(&old_lut));
+        }
+        self.adjustment = Some(adjustment);
+        Ok(())
+    }
+
+    // None for the right eye of mono layers
+    pub fn eye_texture(&self, eye: Eye) -> Option<&TiledTexture> {
+        if self.eye == eye {
+            Some(&self.texture)
+        } else {
+            self.other_eye.as_ref()
+        }
+    }
+
+    // swaps the textures unless `texture` is of `eye` already
+    pub fn set_eye(&mut self, eye: Eye) {
+        if self.eye != eye {
+            if let Some(other_eye) = self.other_eye.as_mut() {
+                std::mem::swap(&mut self.texture, other_eye);
+                self.eye = eye;
+            }
+        }
+    }
+
+    pub fn delete_textures(&self, context: &WebGl2RenderingContext) {
+        self.texture.delete(context);
+        if let Some(other_eye) = self.other_eye.as_ref() {
+            other_eye.delete(context);
+        }
+        if let Some(lut) = self.lut.as_ref() {
+            context.delete_texture(Some(lut));
+        }
+    }
+}
+
+pub fn create_layer_texture(
+    context: &WebGl2RenderingContext,
+    width: usize,
+    height: usize,
+    pixels: Option<&[u8]>,
+) -> Result<WebGlTexture, JsValue> {
+    create_texture(context, PixelFormat::Rgba8, width, height, pixels)
+}
+
+// `pixels` are in `format`
+pub fn create_texture(
+    context: &WebGl2RenderingContext,
+    format: PixelFormat,
+    width: usize,
+    height: usize,
+    pixels: Option<&[u8]>,
+) -> Result<WebGlTexture, JsValue> {
+    let texture = context.create_texture().unwrap();
+
+    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+    context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+        WebGl2RenderingContext::TEXTURE_2D,
+        0,
+        format.internal_format() as i32,
+        width as i32,
+        height as i32,
+        0,
+        WebGl2RenderingContext::RGBA,
+        format.data_type(),
+        pixels.map(|pixels| format.to_array(pixels)).as_ref(),
+    )?;
+    context.tex_parameteri(
+        WebGl2RenderingContext::TEXTURE_2D,
+        WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+        WebGl2RenderingContext::LINEAR as i32,
+    );
+    context.tex_parameteri(
+        WebGl2RenderingContext::TEXTURE_2D,
+        WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+        WebGl2RenderingContext::LINEAR as i32,
+    );
+    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+    Ok(texture)
+}
@@ -1,32 +1,75 @@
 extern crate console_error_panic_hook;
 
+mod brush_preset_panel;
 mod cubes_to_equirectangular_dialog;
+mod display;
+mod history;
 mod image_transfer_dialog;
+mod layer;
+mod recovery_dialog;
+mod reference;
+mod selection;
+mod tiled_texture;
 
+use std::collections::{BTreeSet, HashMap};
 use std::panic;
 use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{HtmlDivElement, WebGlShader, WebGlTexture, WebglColorBufferFloat};
+use web_sys::{
+    HtmlDivElement, WebGlShader, WebGlTexture, WebGlUniformLocation, WebglColorBufferFloat,
+};
 use yew::prelude::*;
 use yew::{html, ChangeData, Html, InputData};
 
-use crate::file_io::{read_image, write_image};
+use crate::adjustment::{Adjustment, LUT_SIZE};
+use crate::animation::{encode_animation, frame_sequence, AnimationFormat, AnimationSettings};
+use crate::brush_preset::{BrushPreset, BrushPresetLibrary};
+use crate::error::PanoError;
+use crate::file_io::{
+    decode_exr, decode_float_image, decode_image, decode_png, encode_exr_rows, encode_float_image,
+    encode_image, encode_png_rows, premultiply, read_binary, read_image, srgb_to_linear,
+    unpremultiply, write_binary, ExportFormat, FloatImage, DEFAULT_QUALITY,
+};
+use crate::gpano::GPano;
+use crate::pixel_format::PixelFormat;
+use crate::project::{GridSettings, LayerManifest, Project, ProjectManifest, ViewState};
+use crate::sphere::{
+    direction_to_tex_coords, dot, level_horizon, level_vertical, mul_vec, normalize,
+    rotation_between, screen_to_direction, tangent_frame, to_gl_matrix, transpose, view_rotation,
+    yaw_pitch_roll, Mat3, Vec3, IDENTITY,
+};
+use crate::stereo::{Eye, StereoLayout, StereoPaintMode};
+use crate::text::rasterize_text;
 use crate::webgl_utils::{compile_shader, get_uniform_locations, link_program};
 use crate::WebGl2RenderingContext;
+use brush_preset_panel::BrushPresetPanel;
 use cubes_to_equirectangular_dialog::CubesToEquirectangularDialog;
+use display::{DisplaySettings, ToneMapping};
+use history::{diff_tiles, History, HistoryEntry, DEFAULT_HISTORY_DEPTH};
 use image_transfer_dialog::ImageTransferDialog;
+use layer::{create_layer_texture, create_texture, BlendMode, Layer};
+use recovery_dialog::RecoveryDialog;
+use reference::{Reference, ReferencePlacement, ReferenceSettings};
+use selection::{
+    polygon_area, selection_polygon, SelectionMask, SelectionShape, DEFAULT_SELECTION_TOLERANCE,
+};
+use tiled_texture::{TileIndex, TilePixels, TiledTexture, TILE_SIZE};
 
 const WORK_TEXTURE_WIDTH: usize = 3840;
 const WORK_TEXTURE_HEIGHT: usize = 1920;
 
+// sizes offered for new documents, the layers are stored in tiles so larger ones fit as well
+const DOCUMENT_SIZES: [(usize, usize); 3] = [(3840, 1920), (7680, 3840), (16384, 8192)];
+
 #[derive(PartialEq, Eq)]
 pub enum Dialog {
     None,
     CubesToEquirectangular,
     ImageTransfer,
+    Recovery,
 }
 
 impl Dialog {
@@ -48,11 +91,46 @@ impl Dialog {
             _ => false,
         }
     }
+    fn recovery_dialog_open(&self) -> bool {
+        match self {
+            Dialog::Recovery => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Tool {
+    Brush,
+    CloneStamp,
+    RectangleSelection,
+    LassoSelection,
+    ColorSelection,
+    Straighten,
+    Text,
+}
+
+// what the two points picked with the straighten tool should lie on
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum StraightenMode {
+    Horizon,
+    Vertical,
+}
+
+impl Tool {
+    fn selection_shape(&self) -> Option<SelectionShape> {
+        match self {
+            Tool::RectangleSelection => Some(SelectionShape::Rectangle),
+            Tool::LassoSelection => Some(SelectionShape::Lasso),
+            _ => None,
+        }
+    }
 }
 
 pub enum Msg {
     MouseDownCanvas {
         button: i16,
+        alt_key: bool,
         offset_x: f32,
         offset_y: f32,
     },
@@ -66,10 +144,145 @@ pub enum Msg {
     RenderCanvas,
     KeyDown {
         key_code: u32,
+        ctrl_key: bool,
+        shift_key: bool,
+        // typing in an input, textarea or select
+        in_form_field: bool,
+    },
+    Undo,
+    Redo,
+    ChangeHistoryDepth {
+        depth: usize,
+    },
+    ExportImage,
+    ImportImage,
+    ImportImageFile {
+        path: String,
+    },
+    ChangeAnimationSettings {
+        settings: AnimationSettings,
+    },
+    ExportSequenceAnimation,
+    ExportTurntableAnimation,
+    ChangeExportQuality {
+        quality: u8,
+    },
+    SaveProject,
+    OpenProject,
+    OpenProjectFile {
+        path: String,
+    },
+    // a file dropped on the app
+    DropFile {
+        path: String,
+    },
+    ChangeSelectionTolerance {
+        tolerance: f32,
+    },
+    ChangePanoramaRotation {
+        axis: usize,
+        degree: f32,
+    },
+    RotatePanorama,
+    ResizeDocument {
+        width: usize,
+        height: usize,
+    },
+    ChangeDocumentFormat {
+        format: PixelFormat,
+    },
+    ChangeDisplay {
+        display: DisplaySettings,
+    },
+    ChangeStereoLayout {
+        layout: StereoLayout,
+    },
+    SwitchEye,
+    ChangeStereoPaintMode {
+        mode: StereoPaintMode,
+    },
+    ChangeDisparity {
+        disparity: f32,
+    },
+    ChangeStraightenMode {
+        mode: StraightenMode,
+    },
+    ChangeText {
+        text: String,
+    },
+    ChangeTextSize {
+        size: f32,
+    },
+    ImportReference,
+    DeleteReference,
+    ChangeReference {
+        settings: ReferenceSettings,
+    },
+    ClearSelection,
+    InvertSelection,
+    FillSelection,
+    Autosave,
+    // the recovery file of `revision` is written
+    Autosaved {
+        revision: u64,
+    },
+    AutosaveFailed {
+        message: String,
+    },
+    RecoveryFound {
+        session: String,
+    },
+    RestoreRecovery,
+    DiscardRecovery,
+    ContextLost,
+    ShowError {
+        message: String,
+    },
+    DismissError,
+    ProjectOpened {
+        view: ViewState,
+        grid: GridSettings,
+        brush_presets: Option<BrushPresetLibrary>,
+    },
+    ChangeView {
+        view: ViewState,
     },
-    ExportPng,
-    ImportPng,
     SwitchEnableGrid,
+    SelectTool {
+        tool: Tool,
+    },
+    ChangeBrush {
+        brush: BrushPreset,
+    },
+    AddLayer,
+    DeleteLayer,
+    SelectLayer {
+        idx: usize,
+    },
+    MoveLayer {
+        idx: usize,
+        up: bool,
+    },
+    SwitchLayerVisible {
+        idx: usize,
+    },
+    SwitchLayerLock {
+        idx: usize,
+    },
+    ChangeLayerOpacity {
+        opacity: f32,
+    },
+    ChangeLayerBlendMode {
+        blend_mode: BlendMode,
+    },
+    AddAdjustmentLayer {
+        adjustment: Adjustment,
+    },
+    ChangeAdjustmentParam {
+        idx: usize,
+        value: f32,
+    },
+    LayersChanged,
     ChangeMainCanvasSize {
         height: f32,
         width: f32,
@@ -94,17 +307,65 @@ pub struct Model {
     dialog: Dialog,
     enable_grid: bool,
 
+    tool: Tool,
+    brush: BrushPreset,
+    project_brush_presets: Option<BrushPresetLibrary>,
+    // direction on the sphere picked with alt + click
+    clone_source: Option<Vec3>,
+    // rotation from the destination to the source, fixed by the first stroke after picking the source
+    clone_rotation: Option<Mat3>,
+    // canvas points dragged with the rectangle or lasso selection tool
+    selection_points: Vec<(f32, f32)>,
+    selection_tolerance: f32,
+    // read back when the image transfer dialog is opened
+    selection_mask: Option<SelectionMask>,
+    // yaw, pitch and roll (degree) of the rotate panorama command
+    panorama_rotation: [f32; 3],
+    straighten_mode: StraightenMode,
+    straighten_points: Vec<Vec3>,
+    reference: ReferenceSettings,
+    text: String,
+    // pixels on the canvas when the text is placed
+    text_size: f32,
+    // quality of the jpeg and webp exports
+    export_quality: u8,
+    animation_settings: AnimationSettings,
+    display: DisplaySettings,
+    stereo_paint_mode: StereoPaintMode,
+    // degree between the strokes of the eyes when painting both eyes
+    disparity: f32,
+
     app_height: f32,
     app_width: f32,
     main_canvas_size: f32,
     fov: f32,
     yew_root_ref: NodeRef,
 
+    // history revision written to the recovery file by the last autosave
+    autosaved_revision: u64,
+    // a failing autosave is reported once until it succeeds again
+    autosave_failed: bool,
+    // the crashed session offered by the recovery dialog
+    recovery_session: Option<String>,
+    // nothing can be read back from the textures after the context is lost
+    context_lost: bool,
+    // message of the last failed file or WebGL operation, shown until dismissed
+    error: Option<String>,
+
     render_canvas_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
     key_down_f: Arc<RwLock<Option<Closure<dyn FnMut(web_sys::KeyboardEvent)>>>>,
     export_png_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
     import_png_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    save_project_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    undo_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    redo_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    open_project_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    drop_file_f: Arc<RwLock<Option<Closure<dyn FnMut(String)>>>>,
     resize_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    autosave_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    heartbeat_f: Option<Closure<dyn FnMut()>>,
+    unload_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
+    context_lost_f: Arc<RwLock<Option<Closure<dyn FnMut()>>>>,
 }
 
 impl Component for Model {
@@ -124,17 +385,52 @@ impl Component for Model {
             dialog: Dialog::None,
             enable_grid: false,
 
+            tool: Tool::Brush,
+            brush: BrushPreset::default(),
+            project_brush_presets: None,
+            clone_source: None,
+            clone_rotation: None,
+            selection_points: vec![],
+            selection_tolerance: DEFAULT_SELECTION_TOLERANCE,
+            selection_mask: None,
+            panorama_rotation: [0.0; 3],
+            straighten_mode: StraightenMode::Horizon,
+            straighten_points: vec![],
+            reference: ReferenceSettings::default(),
+            text: "Text".to_string(),
+            text_size: 48.0,
+            export_quality: DEFAULT_QUALITY,
+            animation_settings: AnimationSettings::default(),
+            display: DisplaySettings::IDENTITY,
+            stereo_paint_mode: StereoPaintMode::CurrentEye,
+            disparity: 0.0,
+
             app_height: 960.0,
             app_width: 960.0,
             main_canvas_size: 950.0,
             fov: 60.0,
             yew_root_ref: NodeRef::default(),
 
+            autosaved_revision: 0,
+            autosave_failed: false,
+            recovery_session: None,
+            context_lost: false,
+            error: None,
+
             render_canvas_f: Arc::new(RwLock::new(None)),
             key_down_f: Arc::new(RwLock::new(None)),
             export_png_f: Arc::new(RwLock::new(None)),
             import_png_f: Arc::new(RwLock::new(None)),
+            save_project_f: Arc::new(RwLock::new(None)),
+            undo_f: Arc::new(RwLock::new(None)),
+            redo_f: Arc::new(RwLock::new(None)),
+            open_project_f: Arc::new(RwLock::new(None)),
+            drop_file_f: Arc::new(RwLock::new(None)),
             resize_f: Arc::new(RwLock::new(None)),
+            autosave_f: Arc::new(RwLock::new(None)),
+            heartbeat_f: None,
+            unload_f: Arc::new(RwLock::new(None)),
+            context_lost_f: Arc::new(RwLock::new(None)),
         }
     }
 
@@ -173,6 +469,10 @@ impl Component for Model {
                 move |event: web_sys::KeyboardEvent| {
                     link.send_message(Msg::KeyDown {
                         key_code: event.key_code(),
+                        // meta for the command key on mac
+                        ctrl_key: event.ctrl_key() || event.meta_key(),
+                        shift_key: event.shift_key(),
+                        in_form_field: is_form_field(event.target()),
                     });
                 },
             )
@@ -199,47 +499,55 @@ impl Component for Model {
                 .unwrap()
                 .dyn_into::<WebGl2RenderingContext>()
                 .unwrap();
+            let link = self.link.clone();
+            *self.context_lost_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
+                link.send_message(Msg::ContextLost)
+            })));
+            canvas
+                .add_event_listener_with_callback(
+                    "webglcontextlost",
+                    self.context_lost_f
+                        .read()
+                        .unwrap()
+                        .as_ref()
+                        .unwrap()
+                        .as_ref()
+                        .unchecked_ref(),
+                )
+                .unwrap();
+
             let _ext = context.get_extension("EXT_color_buffer_float").unwrap();
             let _ext = context.get_extension("OES_texture_float").unwrap();
 
             context.clear_color(0.0, 0.0, 0.0, 1.0);
             context.enable(WebGl2RenderingContext::BLEND);
 
-            // when development, we can use read_shader with path
-            /*
-            let show_panorama_vert_shader = crate::webgl_utils::read_shader(
-                Path::new("./pano-rs/src/shaders/show_panorama.vert"),
-                &context,
-                WebGl2RenderingContext::VERTEX_SHADER,
-            )
-            .unwrap();
-            */
+            // the shaders are built in, so that they are found outside Electron too.
+            // when development, `crate::webgl_utils::read_shader` reads one from the storage
             let all_view_vert_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::VERTEX_SHADER,
                 include_str!("../shaders/all_view.vert"),
             )
             .unwrap();
-            let drawing_canvas_vert_shader = compile_shader(
+            let tile_view_vert_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::VERTEX_SHADER,
-                include_str!("../shaders/drawing_canvas.vert"),
+                include_str!("../shaders/tile_view.vert"),
             )
             .unwrap();
-            let brush_vert_shader = crate::webgl_utils::read_shader(
-                Path::new("./pano-rs/src/shaders/brush.vert"),
+            let drawing_canvas_vert_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::VERTEX_SHADER,
+                include_str!("../shaders/drawing_canvas.vert"),
             )
             .unwrap();
-            /*
             let brush_vert_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::VERTEX_SHADER,
                 include_str!("../shaders/brush.vert"),
             )
             .unwrap();
-            */
 
             let show_panorama_frag_shader = compile_shader(
                 &context,
@@ -265,56 +573,124 @@ impl Component for Model {
                 include_str!("../shaders/grid.frag"),
             )
             .unwrap();
-            let brush_frag_shader = crate::webgl_utils::read_shader(
-                Path::new("./pano-rs/src/shaders/brush.frag"),
+            let brush_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/brush.frag"),
+            )
+            .unwrap();
+            let panorama_rotation_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/panorama_rotation.frag"),
+            )
+            .unwrap();
+            let clone_stamp_frag_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/clone_stamp.frag"),
             )
             .unwrap();
-            let panorama_rotation_frag_shader = crate::webgl_utils::read_shader(
-                Path::new("./pano-rs/src/shaders/panorama_rotation.frag"),
+            let layer_composite_frag_shader = compile_shader(
                 &context,
                 WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/layer_composite.frag"),
             )
             .unwrap();
 
-            let work_texture = context.create_texture().unwrap();
+            let adjustment_layer_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/adjustment_layer.frag"),
+            )
+            .unwrap();
+            let show_premultiplied_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/show_premultiplied.frag"),
+            )
+            .unwrap();
 
-            context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&work_texture));
-            context
-                .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-                    WebGl2RenderingContext::TEXTURE_2D,
-                    0,
-                    WebGl2RenderingContext::RGBA as i32,
-                    WORK_TEXTURE_WIDTH as i32,
-                    WORK_TEXTURE_HEIGHT as i32,
-                    0,
-                    WebGl2RenderingContext::RGBA,
-                    WebGl2RenderingContext::UNSIGNED_BYTE,
-                    None,
-                )
-                .unwrap();
-            context.tex_parameteri(
-                WebGl2RenderingContext::TEXTURE_2D,
-                WebGl2RenderingContext::TEXTURE_MAG_FILTER,
-                WebGl2RenderingContext::LINEAR as i32,
-            );
-            context.tex_parameteri(
-                WebGl2RenderingContext::TEXTURE_2D,
-                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-                WebGl2RenderingContext::LINEAR as i32,
+            let rotate_panorama_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/rotate_panorama.frag"),
+            )
+            .unwrap();
+            let reference_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/reference.frag"),
+            )
+            .unwrap();
+            let project_image_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/project_image.frag"),
+            )
+            .unwrap();
+            let selection_polygon_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/selection_polygon.frag"),
+            )
+            .unwrap();
+            let selection_color_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/selection_color.frag"),
+            )
+            .unwrap();
+            let selection_invert_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/selection_invert.frag"),
+            )
+            .unwrap();
+            let selection_overlay_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/selection_overlay.frag"),
+            )
+            .unwrap();
+            let fill_frag_shader = compile_shader(
+                &context,
+                WebGl2RenderingContext::FRAGMENT_SHADER,
+                include_str!("../shaders/fill.frag"),
+            )
+            .unwrap();
+
+            let background_texture = TiledTexture::new(
+                WORK_TEXTURE_WIDTH,
+                WORK_TEXTURE_HEIGHT,
+                PixelFormat::Rgba8,
+                [0; 4],
             );
-            context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+            let white_texture =
+                create_layer_texture(&context, 1, 1, Some(&[255, 255, 255, 255])).unwrap();
 
             self.webgl = Some(Arc::new(RwLock::new(ModelWebGL {
                 context,
-                work_texture: Arc::new(Mutex::new(work_texture)),
+                width: WORK_TEXTURE_WIDTH,
+                height: WORK_TEXTURE_HEIGHT,
+                format: PixelFormat::Rgba8,
+                stereo: StereoLayout::Mono,
+                eye: Eye::Left,
+                layers: vec![Layer::new("背景".to_string(), background_texture)],
+                active_layer: 0,
+                history: History::new(DEFAULT_HISTORY_DEPTH),
+                reference: None,
+                selection: None,
+                white_texture,
                 brush_texture: Arc::new(Mutex::new(None)),
+                canvas_targets: Mutex::new(None),
 
                 all_view_vert_shader,
+                tile_view_vert_shader,
                 drawing_canvas_vert_shader,
                 brush_vert_shader,
                 brush_dist: 0.0,
+                stroke: vec![],
 
                 show_panorama_frag_shader,
                 draw_circle_frag_shader,
@@ -322,6 +698,18 @@ impl Component for Model {
                 grid_frag_shader,
                 brush_frag_shader,
                 panorama_rotation_frag_shader,
+                clone_stamp_frag_shader,
+                layer_composite_frag_shader,
+                adjustment_layer_frag_shader,
+                show_premultiplied_frag_shader,
+                rotate_panorama_frag_shader,
+                reference_frag_shader,
+                project_image_frag_shader,
+                selection_polygon_frag_shader,
+                selection_color_frag_shader,
+                selection_invert_frag_shader,
+                selection_overlay_frag_shader,
+                fill_frag_shader,
             })));
 
             let link = self.link.clone();
@@ -332,7 +720,7 @@ impl Component for Model {
 
             let link = self.link.clone();
             *self.export_png_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
-                link.send_message(Msg::ExportPng)
+                link.send_message(Msg::ExportImage)
             })));
             crate::wasm_bind::set_on_click_export_png(
                 self.export_png_f
@@ -346,7 +734,7 @@ impl Component for Model {
 
             let link = self.link.clone();
             *self.import_png_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
-                link.send_message(Msg::ImportPng)
+                link.send_message(Msg::ImportImage)
             })));
             crate::wasm_bind::set_on_click_import_png(
                 self.import_png_f
@@ -358,23 +746,138 @@ impl Component for Model {
                     .unchecked_ref(),
             );
 
-            /*
-            self.webgl
-                .as_ref()
-                .unwrap()
-                .read()
-                .unwrap()
-                .import_exp_to_work_texture(Path::new("C:/Users/earne/Desktop/blender work/building/20210721_off grid house/moonless_golf_4k.exr"))
+            let link = self.link.clone();
+            *self.save_project_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
+                link.send_message(Msg::SaveProject)
+            })));
+            crate::wasm_bind::set_on_click_save_project(
+                self.save_project_f
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .unwrap()
+                    .as_ref()
+                    .unchecked_ref(),
+            );
+
+            let link = self.link.clone();
+            *self.open_project_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
+                link.send_message(Msg::OpenProject)
+            })));
+            crate::wasm_bind::set_on_click_open_project(
+                self.open_project_f
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .unwrap()
+                    .as_ref()
+                    .unchecked_ref(),
+            );
+
+            let link = self.link.clone();
+            *self.drop_file_f.write().unwrap() = Some(Closure::wrap(Box::new(move |path| {
+                link.send_message(Msg::DropFile { path })
+            })));
+            crate::wasm_bind::set_on_drop_file(
+                self.drop_file_f
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .unwrap()
+                    .as_ref()
+                    .unchecked_ref(),
+            );
+
+            *self.unload_f.write().unwrap() =
+                Some(Closure::wrap(Box::new(crate::recovery::end_session)));
+            window
+                .add_event_listener_with_callback(
+                    "beforeunload",
+                    self.unload_f
+                        .read()
+                        .unwrap()
+                        .as_ref()
+                        .unwrap()
+                        .as_ref()
+                        .unchecked_ref(),
+                )
                 .unwrap();
-            */
 
-            self.webgl
-                .as_ref()
-                .unwrap()
-                .read()
-                .unwrap()
-                .show(self.rotation_x, self.rotation_y, self.fov, self.enable_grid)
+            let link = self.link.clone();
+            *self.autosave_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
+                link.send_message(Msg::Autosave)
+            })));
+            window
+                .set_interval_with_callback_and_timeout_and_arguments_0(
+                    self.autosave_f
+                        .read()
+                        .unwrap()
+                        .as_ref()
+                        .unwrap()
+                        .as_ref()
+                        .unchecked_ref(),
+                    crate::recovery::AUTOSAVE_INTERVAL_MS,
+                )
+                .unwrap();
+
+            let heartbeat_f: Closure<dyn FnMut()> =
+                Closure::wrap(Box::new(crate::recovery::heartbeat));
+            window
+                .set_interval_with_callback_and_timeout_and_arguments_0(
+                    heartbeat_f.as_ref().unchecked_ref(),
+                    crate::recovery::HEARTBEAT_INTERVAL_MS,
+                )
                 .unwrap();
+            self.heartbeat_f = Some(heartbeat_f);
+
+            if let Some(session) = crate::recovery::start_session() {
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if crate::recovery::has_recovery(&session).await {
+                        link.send_message(Msg::RecoveryFound { session });
+                    } else if let Err(err) = crate::recovery::discard_recovery(&session).await {
+                        // crashed before its first autosave
+                        crate::console_log!("failed to discard the session: {}", err);
+                    }
+                });
+            }
+
+            let link = self.link.clone();
+            *self.undo_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
+                link.send_message(Msg::Undo)
+            })));
+            crate::wasm_bind::set_on_click_undo(
+                self.undo_f
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .unwrap()
+                    .as_ref()
+                    .unchecked_ref(),
+            );
+
+            let link = self.link.clone();
+            *self.redo_f.write().unwrap() = Some(Closure::wrap(Box::new(move || {
+                link.send_message(Msg::Redo)
+            })));
+            crate::wasm_bind::set_on_click_redo(
+                self.redo_f
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .unwrap()
+                    .as_ref()
+                    .unchecked_ref(),
+            );
+
+            self.report(self.webgl.as_ref().unwrap().write().unwrap().show(
+                self.rotation_x,
+                self.rotation_y,
+                self.fov,
+                self.enable_grid,
+                &self.reference,
+                &self.display,
+            ));
         }
     }
 
@@ -382,19 +885,121 @@ impl Component for Model {
         match msg {
             Msg::MouseDownCanvas {
                 button,
+                alt_key,
                 offset_x,
                 offset_y,
             } => {
+                if button == 0 && self.tool == Tool::ColorSelection {
+                    let direction = screen_to_direction(
+                        offset_x,
+                        offset_y,
+                        self.main_canvas_size,
+                        self.fov,
+                        self.rotation_x,
+                        self.rotation_y,
+                    );
+                    self.report(self.webgl.as_ref().unwrap().write().unwrap().select_color(
+                        direction_to_tex_coords(direction),
+                        self.selection_tolerance,
+                    ));
+                    return false;
+                }
+                if button == 0 && self.tool == Tool::Text {
+                    if let Some(image) =
+                        rasterize_text(&self.text, self.text_size, self.brush.color)
+                    {
+                        let direction = screen_to_direction(
+                            offset_x,
+                            offset_y,
+                            self.main_canvas_size,
+                            self.fov,
+                            self.rotation_x,
+                            self.rotation_y,
+                        );
+                        // keeps the size on the canvas at the center of the text
+                        let factor = (self.fov / 2.0 / 180.0 * std::f32::consts::PI).tan()
+                            / (self.main_canvas_size / 2.0);
+                        let half_size = (
+                            image.width() as f32 / 2.0 * factor,
+                            image.height() as f32 / 2.0 * factor,
+                        );
+                        self.report(
+                            self.webgl
+                                .as_ref()
+                                .unwrap()
+                                .write()
+                                .unwrap()
+                                .draw_image_on_sphere(
+                                    &image,
+                                    &tangent_frame(direction, self.rotation_x, self.rotation_y),
+                                    half_size,
+                                ),
+                        );
+                    }
+                    return true;
+                }
+                if button == 0 && self.tool == Tool::Straighten {
+                    self.straighten_points.push(screen_to_direction(
+                        offset_x,
+                        offset_y,
+                        self.main_canvas_size,
+                        self.fov,
+                        self.rotation_x,
+                        self.rotation_y,
+                    ));
+                    if self.straighten_points.len() == 2 {
+                        let (a, b) = (self.straighten_points[0], self.straighten_points[1]);
+                        self.straighten_points.clear();
+                        let rotation = match self.straighten_mode {
+                            StraightenMode::Horizon => level_horizon(a, b),
+                            StraightenMode::Vertical => level_vertical(a, b),
+                        };
+                        self.report(
+                            self.webgl
+                                .as_ref()
+                                .unwrap()
+                                .write()
+                                .unwrap()
+                                .rotate_panorama(&rotation),
+                        );
+                    }
+                    return true;
+                }
+                if button == 0 && self.tool.selection_shape().is_some() {
+                    self.left_mouse_on = true;
+                    self.selection_points = vec![(offset_x, offset_y)];
+                    return true;
+                }
+                if button == 0 && self.tool == Tool::CloneStamp {
+                    let direction = screen_to_direction(
+                        offset_x,
+                        offset_y,
+                        self.main_canvas_size,
+                        self.fov,
+                        self.rotation_x,
+                        self.rotation_y,
+                    );
+                    if alt_key {
+                        self.clone_source = Some(direction);
+                        self.clone_rotation = None;
+                        return false;
+                    }
+                    if let Some(clone_source) = self.clone_source {
+                        if self.clone_rotation.is_none() {
+                            self.clone_rotation = Some(rotation_between(direction, clone_source));
+                        }
+                    } else {
+                        return self.update(Msg::ShowError {
+                            message: String::from(
+                                "コピー元が設定されていません (Alt+クリックで設定)",
+                            ),
+                        });
+                    }
+                }
                 if button == 0 {
                     self.left_mouse_on = true;
                     self.prev_mouse_point = Some((offset_x, offset_y));
-                    self.webgl
-                        .as_ref()
-                        .unwrap()
-                        .write()
-                        .unwrap()
-                        .start_brush()
-                        .unwrap();
+                    self.report(self.webgl.as_ref().unwrap().write().unwrap().start_brush());
                 } else if button == 1 {
                     self.scroll_mouse_on = true;
                 }
@@ -410,29 +1015,30 @@ impl Component for Model {
                     self.rotation_y += 0.3 * movement_x;
                     self.rotation_x -= 0.3 * movement_y;
                 }
+                if self.left_mouse_on && self.tool.selection_shape().is_some() {
+                    if self.tool == Tool::RectangleSelection {
+                        // only the start and the current corner
+                        self.selection_points.truncate(1);
+                    }
+                    self.selection_points.push((offset_x, offset_y));
+                    return true;
+                }
                 if self.left_mouse_on {
                     let prev_mouse_point = self.prev_mouse_point.unwrap();
                     let factor = (self.fov / 2.0 / 180.0 * std::f32::consts::PI).tan();
-                    self.webgl
-                        .as_ref()
-                        .unwrap()
-                        .write()
-                        .unwrap()
-                        .draw_brush(
-                            (
-                                (2.0 * prev_mouse_point.0 / self.main_canvas_size as f32 - 1.0)
-                                    * factor,
-                                (1.0 - 2.0 * (prev_mouse_point.1 / self.main_canvas_size as f32))
-                                    * factor,
-                                1.0,
-                            ),
-                            (
-                                (2.0 * offset_x / self.main_canvas_size as f32 - 1.0) * factor,
-                                (1.0 - 2.0 * offset_y / self.main_canvas_size as f32) * factor,
-                                1.0,
-                            ),
-                        )
-                        .unwrap();
+                    self.report(self.webgl.as_ref().unwrap().write().unwrap().draw_brush(
+                        &self.brush,
+                        (
+                            (2.0 * prev_mouse_point.0 / self.main_canvas_size - 1.0) * factor,
+                            (1.0 - 2.0 * (prev_mouse_point.1 / self.main_canvas_size)) * factor,
+                            1.0,
+                        ),
+                        (
+                            (2.0 * offset_x / self.main_canvas_size - 1.0) * factor,
+                            (1.0 - 2.0 * offset_y / self.main_canvas_size) * factor,
+                            1.0,
+                        ),
+                    ));
                     self.prev_mouse_point = Some((offset_x, offset_y));
                 }
                 false
@@ -441,13 +1047,45 @@ impl Component for Model {
                 if self.left_mouse_on {
                     self.left_mouse_on = false;
                     self.prev_mouse_point = None;
-                    self.webgl
-                        .as_ref()
-                        .unwrap()
-                        .write()
-                        .unwrap()
-                        .stop_brush(self.rotation_x, self.rotation_y)
-                        .unwrap();
+                    let selection_points = std::mem::take(&mut self.selection_points);
+                    let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                    let result = match self.tool {
+                        Tool::Brush => webgl.stop_brush(
+                            self.rotation_x,
+                            self.rotation_y,
+                            self.stereo_disparity(),
+                        ),
+                        Tool::CloneStamp => match self.clone_rotation.as_ref() {
+                            Some(clone_rotation) => webgl.stop_clone_stamp(
+                                self.rotation_x,
+                                self.rotation_y,
+                                clone_rotation,
+                            ),
+                            None => return false,
+                        },
+                        Tool::RectangleSelection | Tool::LassoSelection => {
+                            let polygon = selection_polygon(
+                                self.tool.selection_shape().unwrap(),
+                                &selection_points,
+                                self.main_canvas_size,
+                            );
+                            if polygon.len() >= 3 && polygon_area(&polygon) > 1e-6 {
+                                webgl.select_polygon(
+                                    &polygon,
+                                    self.rotation_x,
+                                    self.rotation_y,
+                                    self.fov,
+                                )
+                            } else {
+                                webgl.clear_selection();
+                                Ok(())
+                            }
+                        }
+                        Tool::ColorSelection | Tool::Straighten | Tool::Text => Ok(()),
+                    };
+                    self.report(result);
+                    // the stroke is now in the history
+                    return true;
                 } else if self.scroll_mouse_on {
                     self.scroll_mouse_on = false;
                     self.modify_rotation();
@@ -455,20 +1093,39 @@ impl Component for Model {
                 false
             }
             Msg::RenderCanvas => {
-                self.webgl
-                    .as_ref()
-                    .unwrap()
-                    .read()
-                    .unwrap()
-                    .show(self.rotation_x, self.rotation_y, self.fov, self.enable_grid)
-                    .unwrap();
+                self.report(self.webgl.as_ref().unwrap().write().unwrap().show(
+                    self.rotation_x,
+                    self.rotation_y,
+                    self.fov,
+                    self.enable_grid,
+                    &self.reference,
+                    &self.display,
+                ));
                 request_animation_frame(self.render_canvas_f.read().unwrap().as_ref().unwrap());
                 false
             }
-            Msg::KeyDown { key_code } => {
+            Msg::KeyDown {
+                key_code,
+                ctrl_key,
+                shift_key,
+                in_form_field,
+            } => {
                 // crate::console_log!("key down {}", key_code);
-                if key_code == 54 {
-                    // '6' key
+                if in_form_field {
+                    // the keys are typed into the field, which has its own undo
+                    false
+                } else if ctrl_key && key_code == 90 {
+                    // ctrl + 'z' key, ctrl + shift + 'z' key
+                    if shift_key {
+                        self.update(Msg::Redo)
+                    } else {
+                        self.update(Msg::Undo)
+                    }
+                } else if ctrl_key && key_code == 89 {
+                    // ctrl + 'y' key
+                    self.update(Msg::Redo)
+                } else if key_code == 54 {
+                    // '6' key
                     if self.dialog == Dialog::CubesToEquirectangular {
                         self.dialog = Dialog::None;
                     } else {
@@ -480,6 +1137,17 @@ impl Component for Model {
                     if self.dialog == Dialog::ImageTransfer {
                         self.dialog = Dialog::None;
                     } else {
+                        let selection_mask = self
+                            .webgl
+                            .as_ref()
+                            .unwrap()
+                            .read()
+                            .unwrap()
+                            .selection_pixels();
+                        self.selection_mask = self
+                            .report(selection_mask)
+                            .flatten()
+                            .map(|mask| SelectionMask(Arc::new(mask)));
                         self.dialog = Dialog::ImageTransfer;
                     }
                     true
@@ -487,42 +1155,593 @@ impl Component for Model {
                     false
                 }
             }
-            Msg::ExportPng => {
-                let dialog_promise: js_sys::Promise =
-                    crate::wasm_bind::show_save_png_dialog().unwrap().into();
+            Msg::Undo => {
+                if self.left_mouse_on {
+                    return false;
+                }
+                self.report(self.webgl.as_ref().unwrap().write().unwrap().undo());
+                true
+            }
+            Msg::Redo => {
+                if self.left_mouse_on {
+                    return false;
+                }
+                self.report(self.webgl.as_ref().unwrap().write().unwrap().redo());
+                true
+            }
+            Msg::ChangeHistoryDepth { depth } => {
+                self.webgl
+                    .as_ref()
+                    .unwrap()
+                    .write()
+                    .unwrap()
+                    .set_history_depth(depth);
+                true
+            }
+            Msg::ExportImage => {
+                let dialog = crate::wasm_bind::show_save_image_dialog();
                 let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                let quality = self.export_quality;
+                let view = self.view_state();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Some(path) = chosen_path(dialog, &link).await {
+                        let path = Path::new(&path);
+                        let result = match ExportFormat::from_path(path, quality)
+                            .and_then(|format| webgl.write().unwrap().export_image(format, &view))
+                        {
+                            Ok(bytes) => write_binary(path, bytes).await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = result {
+                            link.send_message(Msg::ShowError {
+                                message: err.to_string(),
+                            });
+                        }
+                    }
+                });
+                false
+            }
+            Msg::ChangeAnimationSettings { settings } => {
+                self.animation_settings = settings;
+                true
+            }
+            Msg::ExportSequenceAnimation => {
+                // the first frame of the sequence, the following numbers are found next to it
+                let open_dialog = crate::wasm_bind::show_open_image_dialog();
+                let link = self.link.clone();
+                let settings = self.animation_settings;
                 wasm_bindgen_futures::spawn_local(async move {
-                    let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
-                        .await
-                        .unwrap();
-                    if let Some(path) = path_or_undefined.as_string() {
-                        webgl.read().unwrap().save_png(Path::new(&path)).unwrap();
+                    let first = match chosen_path(open_dialog, &link).await {
+                        Some(first) => first,
+                        None => return,
+                    };
+                    let save_dialog = crate::wasm_bind::show_save_animation_dialog();
+                    if let Some(path) = chosen_path(save_dialog, &link).await {
+                        let result = export_sequence_animation(
+                            Path::new(&first),
+                            Path::new(&path),
+                            &settings,
+                        )
+                        .await;
+                        if let Err(err) = result {
+                            link.send_message(Msg::ShowError {
+                                message: err.to_string(),
+                            });
+                        }
                     }
                 });
                 false
             }
-            Msg::ImportPng => {
-                let dialog_promise: js_sys::Promise =
-                    crate::wasm_bind::show_open_png_dialog().unwrap().into();
+            Msg::ExportTurntableAnimation => {
+                let dialog = crate::wasm_bind::show_save_animation_dialog();
                 let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                let settings = self.animation_settings;
+                let view = self.view_state();
+                let display = self.display;
                 wasm_bindgen_futures::spawn_local(async move {
-                    let path_or_undefined = wasm_bindgen_futures::JsFuture::from(dialog_promise)
-                        .await
-                        .unwrap();
-                    if let Some(path) = path_or_undefined.as_string() {
-                        webgl
-                            .read()
-                            .unwrap()
-                            .import_png_to_work_texture(Path::new(&path))
-                            .unwrap();
+                    if let Some(path) = chosen_path(dialog, &link).await {
+                        let path = Path::new(&path);
+                        let bytes = AnimationFormat::from_path(path).and_then(|format| {
+                            let frames = webgl.write().unwrap().turntable_frames(
+                                view.rotation_x,
+                                view.rotation_y,
+                                view.fov,
+                                &display,
+                                settings.turntable_frames,
+                            )?;
+                            encode_animation(frames, format, &settings)
+                        });
+                        let result = match bytes {
+                            Ok(bytes) => write_binary(path, bytes).await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = result {
+                            link.send_message(Msg::ShowError {
+                                message: err.to_string(),
+                            });
+                        }
+                    }
+                });
+                false
+            }
+            Msg::ChangeExportQuality { quality } => {
+                self.export_quality = quality;
+                true
+            }
+            Msg::ImportImage => {
+                let dialog = crate::wasm_bind::show_open_image_dialog();
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Some(path) = chosen_path(dialog, &link).await {
+                        link.send_message(Msg::ImportImageFile { path });
+                    }
+                });
+                false
+            }
+            Msg::ImportImageFile { path } => {
+                let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let path = Path::new(&path);
+                    let result = match read_binary(path).await {
+                        Ok(bytes) => webgl.write().unwrap().import_image_as_layer(path, &bytes),
+                        Err(err) => Err(err),
+                    };
+                    match result {
+                        Ok(gpano) => {
+                            // the initial view of a photo sphere
+                            if let Some(gpano) = gpano {
+                                link.send_message(Msg::ChangeView {
+                                    view: gpano.initial_view(),
+                                });
+                            }
+                            link.send_message(Msg::LayersChanged);
+                        }
+                        Err(err) => link.send_message(Msg::ShowError {
+                            message: err.to_string(),
+                        }),
+                    }
+                });
+                false
+            }
+            Msg::SaveProject => {
+                let dialog = crate::wasm_bind::show_save_project_dialog();
+                let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                let view = self.view_state();
+                let grid = GridSettings {
+                    enabled: self.enable_grid,
+                };
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Some(path) = chosen_path(dialog, &link).await {
+                        let brush_presets = BrushPresetLibrary::load_or_default().await;
+                        let project = webgl.read().unwrap().to_project(view, grid, brush_presets);
+                        let result = match project {
+                            Ok(project) => project.save(Path::new(&path)).await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = result {
+                            link.send_message(Msg::ShowError {
+                                message: err.to_string(),
+                            });
+                        }
+                    }
+                });
+                false
+            }
+            Msg::OpenProject => {
+                let dialog = crate::wasm_bind::show_open_project_dialog();
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Some(path) = chosen_path(dialog, &link).await {
+                        link.send_message(Msg::OpenProjectFile { path });
+                    }
+                });
+                false
+            }
+            Msg::OpenProjectFile { path } => {
+                let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result = Project::load(Path::new(&path)).await.and_then(|project| {
+                        webgl.write().unwrap().load_project(&project)?;
+                        Ok(project)
+                    });
+                    match result {
+                        Ok(project) => link.send_message(Msg::ProjectOpened {
+                            view: project.manifest.view,
+                            grid: project.manifest.grid,
+                            brush_presets: project.manifest.brush_presets,
+                        }),
+                        Err(err) => link.send_message(Msg::ShowError {
+                            message: err.to_string(),
+                        }),
+                    }
+                });
+                false
+            }
+            Msg::DropFile { path } => {
+                if Path::new(&path)
+                    .extension()
+                    .is_some_and(|extension| extension == "pano")
+                {
+                    self.update(Msg::OpenProjectFile { path })
+                } else {
+                    self.update(Msg::ImportImageFile { path })
+                }
+            }
+            Msg::ChangeSelectionTolerance { tolerance } => {
+                self.selection_tolerance = tolerance;
+                true
+            }
+            Msg::ChangePanoramaRotation { axis, degree } => {
+                self.panorama_rotation[axis] = degree;
+                true
+            }
+            Msg::RotatePanorama => {
+                let [yaw, pitch, roll] = self.panorama_rotation;
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .rotate_panorama(&yaw_pitch_roll(yaw, pitch, roll)),
+                );
+                self.panorama_rotation = [0.0; 3];
+                true
+            }
+            Msg::ResizeDocument { width, height } => {
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .resize_document(width, height),
+                );
+                true
+            }
+            Msg::ChangeDocumentFormat { format } => {
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .convert_format(format),
+                );
+                self.display = DisplaySettings::for_format(format);
+                true
+            }
+            Msg::ChangeDisplay { display } => {
+                self.display = display;
+                true
+            }
+            Msg::ChangeStereoLayout { layout } => {
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .set_stereo_layout(layout),
+                );
+                true
+            }
+            Msg::SwitchEye => {
+                let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                let eye = webgl.eye.other();
+                webgl.set_eye(eye);
+                true
+            }
+            Msg::ChangeStereoPaintMode { mode } => {
+                self.stereo_paint_mode = mode;
+                true
+            }
+            Msg::ChangeDisparity { disparity } => {
+                self.disparity = disparity;
+                true
+            }
+            Msg::ChangeStraightenMode { mode } => {
+                self.straighten_mode = mode;
+                self.straighten_points.clear();
+                true
+            }
+            Msg::ChangeText { text } => {
+                self.text = text;
+                false
+            }
+            Msg::ChangeTextSize { size } => {
+                self.text_size = size;
+                true
+            }
+            Msg::ImportReference => {
+                let dialog = crate::wasm_bind::show_open_image_dialog();
+                let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Some(path) = chosen_path(dialog, &link).await {
+                        let result = match read_image(Path::new(&path)).await {
+                            Ok(image) => webgl.write().unwrap().load_reference(&image),
+                            Err(err) => Err(err),
+                        };
+                        match result {
+                            Ok(()) => link.send_message(Msg::LayersChanged),
+                            Err(err) => link.send_message(Msg::ShowError {
+                                message: err.to_string(),
+                            }),
+                        }
+                    }
+                });
+                false
+            }
+            Msg::DeleteReference => {
+                self.webgl
+                    .as_ref()
+                    .unwrap()
+                    .write()
+                    .unwrap()
+                    .delete_reference();
+                true
+            }
+            Msg::ChangeReference { settings } => {
+                self.reference = settings;
+                true
+            }
+            Msg::ClearSelection => {
+                self.webgl
+                    .as_ref()
+                    .unwrap()
+                    .write()
+                    .unwrap()
+                    .clear_selection();
+                false
+            }
+            Msg::InvertSelection => {
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .invert_selection(),
+                );
+                false
+            }
+            Msg::FillSelection => {
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .fill_selection(&self.brush),
+                );
+                true
+            }
+            Msg::Autosave => {
+                if self.left_mouse_on || self.context_lost {
+                    return false;
+                }
+                let webgl = self.webgl.as_ref().unwrap().clone();
+                let revision = webgl.read().unwrap().history.revision();
+                if revision == self.autosaved_revision {
+                    return false;
+                }
+                let link = self.link.clone();
+                let view = self.view_state();
+                let grid = GridSettings {
+                    enabled: self.enable_grid,
+                };
+                wasm_bindgen_futures::spawn_local(async move {
+                    let brush_presets = BrushPresetLibrary::load_or_default().await;
+                    let project = webgl.read().unwrap().to_project(view, grid, brush_presets);
+                    let result = match project {
+                        Ok(project) => crate::recovery::save_recovery(&project).await,
+                        Err(err) => Err(err),
+                    };
+                    match result {
+                        Ok(()) => link.send_message(Msg::Autosaved { revision }),
+                        Err(err) => link.send_message(Msg::AutosaveFailed {
+                            message: err.to_string(),
+                        }),
                     }
                 });
                 false
             }
+            Msg::Autosaved { revision } => {
+                self.autosaved_revision = revision;
+                self.autosave_failed = false;
+                false
+            }
+            Msg::AutosaveFailed { message } => {
+                if self.autosave_failed {
+                    crate::console_log!("failed to autosave: {}", message);
+                    return false;
+                }
+                self.autosave_failed = true;
+                self.update(Msg::ShowError {
+                    message: format!("自動保存に失敗しました: {}", message),
+                })
+            }
+            Msg::RecoveryFound { session } => {
+                self.recovery_session = Some(session);
+                self.dialog = Dialog::Recovery;
+                true
+            }
+            Msg::RestoreRecovery => {
+                self.dialog = Dialog::None;
+                let session = match self.recovery_session.take() {
+                    Some(session) => session,
+                    None => return true,
+                };
+                let webgl = self.webgl.as_ref().unwrap().clone();
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result =
+                        crate::recovery::load_recovery(&session)
+                            .await
+                            .and_then(|project| {
+                                webgl.write().unwrap().load_project(&project)?;
+                                Ok(project)
+                            });
+                    match result {
+                        // the restored document is autosaved by this session from now on
+                        Ok(project) => {
+                            crate::recovery::close_session(&session);
+                            link.send_message(Msg::ProjectOpened {
+                                view: project.manifest.view,
+                                grid: project.manifest.grid,
+                                brush_presets: project.manifest.brush_presets,
+                            })
+                        }
+                        Err(err) => link.send_message(Msg::ShowError {
+                            message: err.to_string(),
+                        }),
+                    }
+                });
+                true
+            }
+            Msg::DiscardRecovery => {
+                self.dialog = Dialog::None;
+                let session = match self.recovery_session.take() {
+                    Some(session) => session,
+                    None => return true,
+                };
+                let link = self.link.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(err) = crate::recovery::discard_recovery(&session).await {
+                        link.send_message(Msg::ShowError {
+                            message: err.to_string(),
+                        });
+                    }
+                });
+                true
+            }
+            Msg::ContextLost => {
+                self.context_lost = true;
+                crate::recovery::abandon_session();
+                crate::wasm_bind::alert(
+                    "WebGLのコンテキストが失われました。再起動すると自動保存から復元できます。",
+                );
+                false
+            }
+            Msg::ShowError { message } => {
+                crate::console_log!("{}", message);
+                self.error = Some(message);
+                true
+            }
+            Msg::DismissError => {
+                self.error = None;
+                true
+            }
+            Msg::ProjectOpened {
+                view,
+                grid,
+                brush_presets,
+            } => {
+                self.rotation_x = view.rotation_x;
+                self.rotation_y = view.rotation_y;
+                self.fov = view.fov;
+                self.enable_grid = grid.enabled;
+                self.project_brush_presets = brush_presets;
+                self.display = DisplaySettings::for_format(
+                    self.webgl.as_ref().unwrap().read().unwrap().format,
+                );
+                true
+            }
+            Msg::ChangeView { view } => {
+                self.rotation_x = view.rotation_x;
+                self.rotation_y = view.rotation_y;
+                self.fov = view.fov;
+                true
+            }
             Msg::SwitchEnableGrid => {
                 self.enable_grid = !self.enable_grid;
                 true
             }
+            Msg::SelectTool { tool } => {
+                self.tool = tool;
+                self.straighten_points.clear();
+                true
+            }
+            Msg::ChangeBrush { brush } => {
+                self.brush = brush;
+                false
+            }
+            Msg::AddLayer => {
+                self.report(self.webgl.as_ref().unwrap().write().unwrap().add_layer());
+                true
+            }
+            Msg::DeleteLayer => {
+                self.webgl
+                    .as_ref()
+                    .unwrap()
+                    .write()
+                    .unwrap()
+                    .delete_active_layer();
+                true
+            }
+            Msg::SelectLayer { idx } => {
+                self.webgl.as_ref().unwrap().write().unwrap().active_layer = idx;
+                true
+            }
+            Msg::MoveLayer { idx, up } => {
+                self.webgl
+                    .as_ref()
+                    .unwrap()
+                    .write()
+                    .unwrap()
+                    .move_layer(idx, up);
+                true
+            }
+            Msg::SwitchLayerVisible { idx } => {
+                let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                webgl.layers[idx].visible = !webgl.layers[idx].visible;
+                webgl.history.mark_modified();
+                true
+            }
+            Msg::SwitchLayerLock { idx } => {
+                let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                webgl.layers[idx].locked = !webgl.layers[idx].locked;
+                webgl.history.mark_modified();
+                true
+            }
+            Msg::ChangeLayerOpacity { opacity } => {
+                let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                webgl.active_layer_mut().opacity = opacity;
+                webgl.history.mark_modified();
+                true
+            }
+            Msg::AddAdjustmentLayer { adjustment } => {
+                self.report(
+                    self.webgl
+                        .as_ref()
+                        .unwrap()
+                        .write()
+                        .unwrap()
+                        .add_adjustment_layer(adjustment),
+                );
+                true
+            }
+            Msg::ChangeAdjustmentParam { idx, value } => {
+                let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                if let Some(mut adjustment) = webgl.active_layer().adjustment {
+                    adjustment.set_param(idx, value);
+                    self.report(webgl.set_active_adjustment(adjustment));
+                }
+                true
+            }
+            Msg::ChangeLayerBlendMode { blend_mode } => {
+                let mut webgl = self.webgl.as_ref().unwrap().write().unwrap();
+                webgl.active_layer_mut().blend_mode = blend_mode;
+                webgl.history.mark_modified();
+                true
+            }
+            Msg::LayersChanged => true,
             Msg::ChangeMainCanvasSize { height, width } => {
                 self.app_height = height;
                 self.app_width = width;
@@ -557,7 +1776,7 @@ impl Component for Model {
                     id="main_canvas"
                     height=self.main_canvas_size.to_string()
                     width=self.main_canvas_size.to_string()
-                    onmousedown=self.link.callback(|e: web_sys::MouseEvent| Msg::MouseDownCanvas{button: e.button(), offset_x: e.offset_x() as f32,
+                    onmousedown=self.link.callback(|e: web_sys::MouseEvent| Msg::MouseDownCanvas{button: e.button(), alt_key: e.alt_key(), offset_x: e.offset_x() as f32,
                         offset_y: e.offset_y() as f32})
                     onmouseup=self.link.callback(|_| Msg::MouseUpCanvas)
                     onmouseout=self.link.callback(|_| Msg::MouseUpCanvas)
@@ -571,8 +1790,23 @@ impl Component for Model {
 
                     )
                 />
+                { self.view_selection_outline() }
                 <div id="tool">
+                    { self.view_file_tool() }
                     <button onclick=self.link.callback(|_| Msg::SwitchEnableGrid)>{ "グリッド" }</button>
+                    { self.view_history_tool() }
+                    { self.view_document_tool() }
+                    <button
+                        disabled=self.tool == Tool::Brush
+                        onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::Brush})
+                    >{ "ブラシ" }</button>
+                    <button
+                        disabled=self.tool == Tool::CloneStamp
+                        onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::CloneStamp})
+                    >{ "クローン (Alt+クリックでコピー元を指定)" }</button>
+                    { self.view_selection_tool() }
+                    { self.view_rotation_tool() }
+                    { self.view_text_tool() }
                     <input
                         type="range"
                         id="volume"
@@ -590,6 +1824,14 @@ impl Component for Model {
                     />
                     <label for="volume">{format!("FOV={:?}", self.fov)}</label>
                 </div>
+                <BrushPresetPanel
+                    on_change=self.link.callback(|brush| Msg::ChangeBrush{brush})
+                    on_error=self.link.callback(|message| Msg::ShowError{message})
+                    project_presets=self.project_brush_presets.clone()
+                />
+                { self.view_layer_panel() }
+                { self.view_reference_panel() }
+                { self.view_error_notification() }
                 <div id="dialog">
                     {
                         if self.dialog.open() {
@@ -603,9 +1845,17 @@ impl Component for Model {
                     }
                     <CubesToEquirectangularDialog
                         open=self.dialog.cubes_to_equirectangular_dialog_open()
+                        on_error=self.link.callback(|message| Msg::ShowError{message})
                     />
                     <ImageTransferDialog
                         open=self.dialog.image_transfer_dialog_open()
+                        selection=self.selection_mask.clone()
+                        on_error=self.link.callback(|message| Msg::ShowError{message})
+                    />
+                    <RecoveryDialog
+                        open=self.dialog.recovery_dialog_open()
+                        on_restore=self.link.callback(|_| Msg::RestoreRecovery)
+                        on_discard=self.link.callback(|_| Msg::DiscardRecovery)
                     />
                 </div>
             </div>
@@ -614,32 +1864,847 @@ impl Component for Model {
 }
 
 impl Model {
-    fn modify_rotation(&mut self) {
-        let mut rotation_x = self.rotation_x;
-        let mut rotation_y = self.rotation_y;
-
-        rotation_x = (rotation_x + 180.0) % 360.0 - 180.0;
-        if rotation_x > 90.0 {
-            rotation_x = 180.0 - rotation_x;
-            rotation_y = rotation_y + 180.0;
-        }
-        if rotation_x < -90.0 {
-            rotation_x = -180.0 - rotation_x;
-            rotation_y = rotation_y + 180.0;
+    fn view_selection_tool(&self) -> Html {
+        html! {
+            <>
+                <button
+                    disabled=self.tool == Tool::RectangleSelection
+                    onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::RectangleSelection})
+                >{ "矩形選択" }</button>
+                <button
+                    disabled=self.tool == Tool::LassoSelection
+                    onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::LassoSelection})
+                >{ "投げ縄選択" }</button>
+                <button
+                    disabled=self.tool == Tool::ColorSelection
+                    onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::ColorSelection})
+                >{ "色域選択" }</button>
+                <input
+                    type="range"
+                    id="selection_tolerance"
+                    min="0"
+                    max="1"
+                    step="0.01"
+                    value=self.selection_tolerance.to_string()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeSelectionTolerance{tolerance: e.value.parse::<f32>().unwrap()})
+                />
+                <label for="selection_tolerance">{ format!("許容値={:.2}", self.selection_tolerance) }</label>
+                <button onclick=self.link.callback(|_| Msg::ClearSelection)>{ "選択解除" }</button>
+                <button onclick=self.link.callback(|_| Msg::InvertSelection)>{ "選択範囲を反転" }</button>
+                <button onclick=self.link.callback(|_| Msg::FillSelection)>{ "塗りつぶし" }</button>
+            </>
         }
-
-        self.rotation_x = rotation_x;
-        self.rotation_y = rotation_y;
     }
-}
 
-pub struct ModelWebGL {
-    context: WebGl2RenderingContext,
-    work_texture: Arc<Mutex<WebGlTexture>>,
-    brush_texture: Arc<Mutex<Option<WebGlTexture>>>,
-    brush_dist: f32,
+    fn view_text_tool(&self) -> Html {
+        html! {
+            <>
+                <button
+                    disabled=self.tool == Tool::Text
+                    onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::Text})
+                >{ "テキスト" }</button>
+                <input
+                    type="text"
+                    value=self.text.clone()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeText{text: e.value})
+                />
+                <label for="text_size">{ "文字サイズ" }</label>
+                <input
+                    type="number"
+                    id="text_size"
+                    min="4"
+                    max="512"
+                    value=self.text_size.to_string()
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            value.parse::<f32>().ok().filter(|size| *size > 0.0).map(|size| Msg::ChangeTextSize{size})
+                        } else {
+                            None
+                        }})
+                />
+            </>
+        }
+    }
+
+    fn view_rotation_tool(&self) -> Html {
+        let axis_input = |axis: usize, label: &str| {
+            html! {
+                <>
+                    <label>{ label }</label>
+                    <input
+                        type="number"
+                        step="0.1"
+                        value=self.panorama_rotation[axis].to_string()
+                        onchange=self.link.batch_callback(move |e: ChangeData| {
+                            if let ChangeData::Value(value) = e {
+                                value.parse::<f32>().ok().map(|degree| Msg::ChangePanoramaRotation{axis, degree})
+                            } else {
+                                None
+                            }})
+                    />
+                </>
+            }
+        };
+        html! {
+            <>
+                { axis_input(0, "ヨー") }
+                { axis_input(1, "ピッチ") }
+                { axis_input(2, "ロール") }
+                <button onclick=self.link.callback(|_| Msg::RotatePanorama)>{ "パノラマを回転" }</button>
+                <button
+                    disabled=self.tool == Tool::Straighten
+                    onclick=self.link.callback(|_| Msg::SelectTool{tool: Tool::Straighten})
+                >{ "傾き補正 (2点をクリック)" }</button>
+                <select
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            match select.selected_index() {
+                                0 => Some(Msg::ChangeStraightenMode{mode: StraightenMode::Horizon}),
+                                1 => Some(Msg::ChangeStraightenMode{mode: StraightenMode::Vertical}),
+                                _ => None,
+                            }
+                        } else {
+                            None
+                        }})
+                >
+                    <option selected=self.straighten_mode == StraightenMode::Horizon>{ "水平線" }</option>
+                    <option selected=self.straighten_mode == StraightenMode::Vertical>{ "垂直線" }</option>
+                </select>
+                {
+                    if self.straighten_points.len() == 1 {
+                        html! { <label>{ "もう1点をクリック" }</label> }
+                    } else {
+                        html! {}
+                    }
+                }
+            </>
+        }
+    }
+
+    // outline of the rectangle or lasso while dragging
+    fn view_selection_outline(&self) -> Html {
+        if self.selection_points.is_empty() {
+            return html! {};
+        }
+        let points = match self.tool.selection_shape() {
+            Some(SelectionShape::Rectangle) => {
+                let (x0, y0) = self.selection_points[0];
+                let (x1, y1) = *self.selection_points.last().unwrap();
+                vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+            }
+            _ => self.selection_points.clone(),
+        };
+        let points = points
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<String>>()
+            .join(" ");
+        html! {
+            <svg
+                id="selection_outline"
+                width=self.main_canvas_size.to_string()
+                height=self.main_canvas_size.to_string()
+            >
+                <polygon
+                    points=points
+                    fill="none"
+                    stroke="white"
+                    stroke-dasharray="4"
+                />
+            </svg>
+        }
+    }
+
+    fn view_error_notification(&self) -> Html {
+        if let Some(message) = self.error.as_ref() {
+            html! {
+                <div id="error_notification">
+                    { message }
+                    <button onclick=self.link.callback(|_| Msg::DismissError)>{ "閉じる" }</button>
+                </div>
+            }
+        } else {
+            html! {}
+        }
+    }
+
+    fn view_reference_panel(&self) -> Html {
+        let loaded = if let Some(webgl) = self.webgl.as_ref() {
+            webgl.read().unwrap().reference.is_some()
+        } else {
+            false
+        };
+        // each control sends the whole settings with one value changed
+        let change = |f: fn(&mut ReferenceSettings, f32)| {
+            let settings = self.reference.clone();
+            self.link.callback(move |e: InputData| {
+                let mut settings = settings.clone();
+                f(&mut settings, e.value.parse::<f32>().unwrap());
+                Msg::ChangeReference { settings }
+            })
+        };
+        let settings = self.reference.clone();
+        let switch_visible = self.link.callback(move |_| Msg::ChangeReference {
+            settings: ReferenceSettings {
+                visible: !settings.visible,
+                ..settings.clone()
+            },
+        });
+        let settings = self.reference.clone();
+        let change_placement = self.link.batch_callback(move |e: ChangeData| {
+            if let ChangeData::Select(select) = e {
+                let placement = match select.selected_index() {
+                    0 => ReferencePlacement::Screen,
+                    _ => ReferencePlacement::Sphere,
+                };
+                Some(Msg::ChangeReference {
+                    settings: ReferenceSettings {
+                        placement,
+                        ..settings.clone()
+                    },
+                })
+            } else {
+                None
+            }
+        });
+        let settings = self.reference.clone();
+        let (rotation_x, rotation_y) = (self.rotation_x, self.rotation_y);
+        let pin_to_view = self.link.callback(move |_| Msg::ChangeReference {
+            settings: ReferenceSettings {
+                placement: ReferencePlacement::Sphere,
+                rotation_x,
+                rotation_y,
+                ..settings.clone()
+            },
+        });
+        html! {
+            <div id="reference_panel">
+                <label>{ "参照画像" }</label>
+                <button onclick=self.link.callback(|_| Msg::ImportReference)>{ "読み込み" }</button>
+                <button
+                    disabled=!loaded
+                    onclick=self.link.callback(|_| Msg::DeleteReference)
+                >{ "削除" }</button>
+                <button onclick=switch_visible>
+                    { if self.reference.visible { "表示" } else { "非表示" } }
+                </button>
+                <br />
+                <label>{ format!("不透明度={:.2}", self.reference.opacity) }</label>
+                <input
+                    type="range"
+                    min="0"
+                    max="1"
+                    step="0.01"
+                    value=self.reference.opacity.to_string()
+                    oninput=change(|settings, value| settings.opacity = value)
+                />
+                <br />
+                <select onchange=change_placement>
+                    <option selected=self.reference.placement == ReferencePlacement::Screen>{ "画面に固定" }</option>
+                    <option selected=self.reference.placement == ReferencePlacement::Sphere>{ "球面に固定" }</option>
+                </select>
+                <button onclick=pin_to_view>{ "現在の視点に固定" }</button>
+                <br />
+                {
+                    match self.reference.placement {
+                        ReferencePlacement::Screen => html! {
+                            <>
+                                <label>{ format!("X={:.2}", self.reference.screen_x) }</label>
+                                <input
+                                    type="range"
+                                    min="-1"
+                                    max="1"
+                                    step="0.01"
+                                    value=self.reference.screen_x.to_string()
+                                    oninput=change(|settings, value| settings.screen_x = value)
+                                />
+                                <label>{ format!("Y={:.2}", self.reference.screen_y) }</label>
+                                <input
+                                    type="range"
+                                    min="-1"
+                                    max="1"
+                                    step="0.01"
+                                    value=self.reference.screen_y.to_string()
+                                    oninput=change(|settings, value| settings.screen_y = value)
+                                />
+                                <br />
+                                <label>{ format!("サイズ={:.2}", self.reference.screen_size) }</label>
+                                <input
+                                    type="range"
+                                    min="0.05"
+                                    max="1"
+                                    step="0.01"
+                                    value=self.reference.screen_size.to_string()
+                                    oninput=change(|settings, value| settings.screen_size = value)
+                                />
+                            </>
+                        },
+                        ReferencePlacement::Sphere => html! {
+                            <>
+                                <label>{ format!("水平={:.1}", self.reference.rotation_y) }</label>
+                                <input
+                                    type="range"
+                                    min="-180"
+                                    max="180"
+                                    step="0.1"
+                                    value=self.reference.rotation_y.to_string()
+                                    oninput=change(|settings, value| settings.rotation_y = value)
+                                />
+                                <label>{ format!("垂直={:.1}", self.reference.rotation_x) }</label>
+                                <input
+                                    type="range"
+                                    min="-90"
+                                    max="90"
+                                    step="0.1"
+                                    value=self.reference.rotation_x.to_string()
+                                    oninput=change(|settings, value| settings.rotation_x = value)
+                                />
+                                <br />
+                                <label>{ format!("画角={:.1}", self.reference.angular_size) }</label>
+                                <input
+                                    type="range"
+                                    min="5"
+                                    max="170"
+                                    step="0.1"
+                                    value=self.reference.angular_size.to_string()
+                                    oninput=change(|settings, value| settings.angular_size = value)
+                                />
+                            </>
+                        },
+                    }
+                }
+            </div>
+        }
+    }
+
+    // the menu of Electron, which the standalone browser build does not have
+    fn view_file_tool(&self) -> Html {
+        if crate::wasm_bind::is_electron() {
+            return html! {};
+        }
+        html! {
+            <>
+                <button onclick=self.link.callback(|_| Msg::OpenProject)>{ "プロジェクトを開く" }</button>
+                <button onclick=self.link.callback(|_| Msg::SaveProject)>{ "プロジェクトを保存" }</button>
+                <button onclick=self.link.callback(|_| Msg::ImportImage)>{ "画像を読み込む" }</button>
+                <button onclick=self.link.callback(|_| Msg::ExportImage)>{ "画像を書き出し" }</button>
+            </>
+        }
+    }
+
+    fn view_history_tool(&self) -> Html {
+        let webgl = if let Some(webgl) = self.webgl.as_ref() {
+            webgl.read().unwrap()
+        } else {
+            return html! {};
+        };
+        html! {
+            <>
+                <button
+                    disabled=!webgl.history.can_undo()
+                    onclick=self.link.callback(|_| Msg::Undo)
+                >{ "元に戻す" }</button>
+                <button
+                    disabled=!webgl.history.can_redo()
+                    onclick=self.link.callback(|_| Msg::Redo)
+                >{ "やり直す" }</button>
+                <label for="history_depth">{ "履歴数" }</label>
+                <input
+                    type="number"
+                    id="history_depth"
+                    min="1"
+                    max="500"
+                    value=webgl.history.depth().to_string()
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            value.parse::<usize>().ok().filter(|depth| *depth > 0).map(|depth| Msg::ChangeHistoryDepth{depth})
+                        } else {
+                            None
+                        }})
+                />
+            </>
+        }
+    }
+
+    fn view_document_tool(&self) -> Html {
+        let webgl = if let Some(webgl) = self.webgl.as_ref() {
+            webgl.read().unwrap()
+        } else {
+            return html! {};
+        };
+        html! {
+            <>
+                <label for="document_size">{ "解像度" }</label>
+                <select
+                    id="document_size"
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            DOCUMENT_SIZES
+                                .get(select.selected_index() as usize)
+                                .map(|&(width, height)| Msg::ResizeDocument{width, height})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for DOCUMENT_SIZES.iter().map(|&(width, height)| html! {
+                            <option selected=(webgl.width, webgl.height) == (width, height)>
+                                { format!("{}x{}", width, height) }
+                            </option>
+                        })
+                    }
+                </select>
+                <label for="document_format">{ "色深度" }</label>
+                <select
+                    id="document_format"
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            PixelFormat::ALL
+                                .get(select.selected_index() as usize)
+                                .map(|&format| Msg::ChangeDocumentFormat{format})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for PixelFormat::ALL.iter().map(|&format| html! {
+                            <option selected=webgl.format == format>{ format.name() }</option>
+                        })
+                    }
+                </select>
+                <br />
+                { self.view_stereo_settings() }
+                <br />
+                { self.view_display_settings() }
+                <br />
+                <label for="export_quality">{ "書き出し品質" }</label>
+                <input
+                    type="number"
+                    id="export_quality"
+                    min="1"
+                    max="100"
+                    value=self.export_quality.to_string()
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            value
+                                .parse::<u8>()
+                                .ok()
+                                .map(|quality| Msg::ChangeExportQuality{quality: quality.clamp(1, 100)})
+                        } else {
+                            None
+                        }})
+                />
+                <br />
+                { self.view_animation_settings() }
+            </>
+        }
+    }
+
+    fn view_animation_settings(&self) -> Html {
+        let settings = self.animation_settings;
+        html! {
+            <>
+                <label for="animation_frame_rate">{ "フレームレート" }</label>
+                <input
+                    type="number"
+                    id="animation_frame_rate"
+                    min="1"
+                    max="60"
+                    value=settings.frame_rate.to_string()
+                    onchange=self.link.batch_callback(move |e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            value
+                                .parse::<f32>()
+                                .ok()
+                                .map(|frame_rate| Msg::ChangeAnimationSettings{
+                                    settings: AnimationSettings { frame_rate: frame_rate.clamp(1.0, 60.0), ..settings }
+                                })
+                        } else {
+                            None
+                        }})
+                />
+                <label for="animation_play_count">{ "ループ回数 (0 で無限)" }</label>
+                <input
+                    type="number"
+                    id="animation_play_count"
+                    min="0"
+                    max="100"
+                    value=settings.play_count.to_string()
+                    onchange=self.link.batch_callback(move |e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            value
+                                .parse::<u16>()
+                                .ok()
+                                .map(|play_count| Msg::ChangeAnimationSettings{
+                                    settings: AnimationSettings { play_count: play_count.min(100), ..settings }
+                                })
+                        } else {
+                            None
+                        }})
+                />
+                <br />
+                <label for="turntable_frames">{ "ターンテーブルのフレーム数" }</label>
+                <input
+                    type="number"
+                    id="turntable_frames"
+                    min="2"
+                    max="360"
+                    value=settings.turntable_frames.to_string()
+                    onchange=self.link.batch_callback(move |e: ChangeData| {
+                        if let ChangeData::Value(value) = e {
+                            value
+                                .parse::<usize>()
+                                .ok()
+                                .map(|turntable_frames| Msg::ChangeAnimationSettings{
+                                    settings: AnimationSettings { turntable_frames: turntable_frames.clamp(2, 360), ..settings }
+                                })
+                        } else {
+                            None
+                        }})
+                />
+                <br />
+                <button onclick=self.link.callback(|_| Msg::ExportSequenceAnimation)>
+                    { "連番画像をアニメーションに" }
+                </button>
+                <button onclick=self.link.callback(|_| Msg::ExportTurntableAnimation)>
+                    { "ターンテーブルを書き出し" }
+                </button>
+            </>
+        }
+    }
+
+    fn view_stereo_settings(&self) -> Html {
+        let webgl = if let Some(webgl) = self.webgl.as_ref() {
+            webgl.read().unwrap()
+        } else {
+            return html! {};
+        };
+        let stereo = webgl.stereo;
+        let display = self.display;
+        html! {
+            <>
+                <label for="stereo_layout">{ "ステレオ" }</label>
+                <select
+                    id="stereo_layout"
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            StereoLayout::ALL
+                                .get(select.selected_index() as usize)
+                                .map(|&layout| Msg::ChangeStereoLayout{layout})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for StereoLayout::ALL.iter().map(|&layout| html! {
+                            <option selected=stereo == layout>{ layout.name() }</option>
+                        })
+                    }
+                </select>
+                {
+                    if stereo.is_stereo() {
+                        html! {
+                            <>
+                                <button onclick=self.link.callback(|_| Msg::SwitchEye)>
+                                    { webgl.eye.name() }
+                                </button>
+                                <button onclick=self.link.callback(move |_| Msg::ChangeDisplay{
+                                    display: DisplaySettings { anaglyph: !display.anaglyph, ..display }
+                                })>
+                                    { if display.anaglyph { "アナグリフ" } else { "片目" } }
+                                </button>
+                                <br />
+                                <label for="stereo_paint_mode">{ "描画先" }</label>
+                                <select
+                                    id="stereo_paint_mode"
+                                    onchange=self.link.batch_callback(|e: ChangeData| {
+                                        if let ChangeData::Select(select) = e {
+                                            StereoPaintMode::ALL
+                                                .get(select.selected_index() as usize)
+                                                .map(|&mode| Msg::ChangeStereoPaintMode{mode})
+                                        } else {
+                                            None
+                                        }})
+                                >
+                                    {
+                                        for StereoPaintMode::ALL.iter().map(|&mode| html! {
+                                            <option selected=self.stereo_paint_mode == mode>{ mode.name() }</option>
+                                        })
+                                    }
+                                </select>
+                                <label for="disparity">{ "視差" }</label>
+                                <input
+                                    type="number"
+                                    id="disparity"
+                                    min="-10"
+                                    max="10"
+                                    step="0.1"
+                                    value=self.disparity.to_string()
+                                    onchange=self.link.batch_callback(|e: ChangeData| {
+                                        if let ChangeData::Value(value) = e {
+                                            value
+                                                .parse::<f32>()
+                                                .ok()
+                                                .map(|disparity| Msg::ChangeDisparity{disparity: disparity.clamp(-10.0, 10.0)})
+                                        } else {
+                                            None
+                                        }})
+                                />
+                            </>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+            </>
+        }
+    }
+
+    fn view_display_settings(&self) -> Html {
+        let display = self.display;
+        html! {
+            <>
+                <label>{ format!("露出={:.1}", display.exposure) }</label>
+                <input
+                    type="range"
+                    min="-8"
+                    max="8"
+                    step="0.1"
+                    value=display.exposure.to_string()
+                    oninput=self.link.callback(move |e: InputData| Msg::ChangeDisplay{
+                        display: DisplaySettings {
+                            exposure: e.value.parse::<f32>().unwrap(),
+                            ..display
+                        }
+                    })
+                />
+                <label for="tone_mapping">{ "トーンマッピング" }</label>
+                <select
+                    id="tone_mapping"
+                    onchange=self.link.batch_callback(move |e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            ToneMapping::ALL
+                                .get(select.selected_index() as usize)
+                                .map(|&tone_mapping| Msg::ChangeDisplay{
+                                    display: DisplaySettings { tone_mapping, ..display }
+                                })
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for ToneMapping::ALL.iter().map(|&tone_mapping| html! {
+                            <option selected=display.tone_mapping == tone_mapping>
+                                { tone_mapping.name() }
+                            </option>
+                        })
+                    }
+                </select>
+                <label>{ format!("ガンマ={:.2}", display.gamma) }</label>
+                <input
+                    type="range"
+                    min="0.2"
+                    max="4"
+                    step="0.01"
+                    value=display.gamma.to_string()
+                    oninput=self.link.callback(move |e: InputData| Msg::ChangeDisplay{
+                        display: DisplaySettings {
+                            gamma: e.value.parse::<f32>().unwrap(),
+                            ..display
+                        }
+                    })
+                />
+            </>
+        }
+    }
+
+    fn view_layer_panel(&self) -> Html {
+        let webgl = if let Some(webgl) = self.webgl.as_ref() {
+            webgl.read().unwrap()
+        } else {
+            return html! {};
+        };
+        let active_layer = webgl.active_layer();
+        html! {
+            <div id="layer_panel">
+                <button onclick=self.link.callback(|_| Msg::AddLayer)>{ "レイヤー追加" }</button>
+                <button onclick=self.link.callback(|_| Msg::DeleteLayer)>{ "レイヤー削除" }</button>
+                <select
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            let idx = select.selected_index() as usize;
+                            // back to the label, so that the same kind can be added again
+                            select.set_selected_index(0);
+                            idx.checked_sub(1).map(|idx| Msg::AddAdjustmentLayer{adjustment: Adjustment::ALL[idx]})
+                        } else {
+                            None
+                        }})
+                >
+                    <option selected=true>{ "調整レイヤー追加" }</option>
+                    {
+                        for Adjustment::ALL.iter().map(|adjustment| html! {
+                            <option>{ adjustment.name() }</option>
+                        })
+                    }
+                </select>
+                <br />
+                <label>{ format!("不透明度={:.2}", active_layer.opacity) }</label>
+                <input
+                    type="range"
+                    min="0"
+                    max="1"
+                    step="0.01"
+                    value=active_layer.opacity.to_string()
+                    oninput=self.link.callback(|e: InputData| Msg::ChangeLayerOpacity{opacity: e.value.parse::<f32>().unwrap()})
+                />
+                <br />
+                <select
+                    onchange=self.link.batch_callback(|e: ChangeData| {
+                        if let ChangeData::Select(select) = e {
+                            Some(Msg::ChangeLayerBlendMode{blend_mode: BlendMode::ALL[select.selected_index() as usize]})
+                        } else {
+                            None
+                        }})
+                >
+                    {
+                        for BlendMode::ALL.iter().map(|blend_mode| html! {
+                            <option selected=*blend_mode == active_layer.blend_mode>{ blend_mode.name() }</option>
+                        })
+                    }
+                </select>
+                { self.view_adjustment_params(active_layer) }
+                {
+                    // the top layer is listed first
+                    for webgl.layers.iter().enumerate().rev().map(|(idx, layer)| html! {
+                        <div class=if idx == webgl.active_layer { "layer active_layer" } else { "layer" }>
+                            <button onclick=self.link.callback(move |_| Msg::SwitchLayerVisible{idx})>
+                                { if layer.visible { "表示" } else { "非表示" } }
+                            </button>
+                            <button onclick=self.link.callback(move |_| Msg::SwitchLayerLock{idx})>
+                                { if layer.locked { "ロック中" } else { "ロック" } }
+                            </button>
+                            <button onclick=self.link.callback(move |_| Msg::MoveLayer{idx, up: true})>{ "↑" }</button>
+                            <button onclick=self.link.callback(move |_| Msg::MoveLayer{idx, up: false})>{ "↓" }</button>
+                            <span onclick=self.link.callback(move |_| Msg::SelectLayer{idx})>{ &layer.name }</span>
+                        </div>
+                    })
+                }
+            </div>
+        }
+    }
+
+    fn view_adjustment_params(&self, layer: &Layer) -> Html {
+        let adjustment = if let Some(adjustment) = layer.adjustment.as_ref() {
+            adjustment
+        } else {
+            return html! {};
+        };
+        html! {
+            <div id="adjustment_params">
+                {
+                    for adjustment.params().into_iter().enumerate().map(|(idx, (label, value, min, max))| html! {
+                        <>
+                            <label>{ format!("{}={:.2}", label, value) }</label>
+                            <input
+                                type="range"
+                                min=min.to_string()
+                                max=max.to_string()
+                                step=((max - min) / 200.0).to_string()
+                                value=value.to_string()
+                                oninput=self.link.callback(move |e: InputData| Msg::ChangeAdjustmentParam{idx, value: e.value.parse::<f32>().unwrap()})
+                            />
+                            <br />
+                        </>
+                    })
+                }
+            </div>
+        }
+    }
+
+    // the disparity of the strokes painted on both eyes
+    fn stereo_disparity(&self) -> Option<f32> {
+        if self.stereo_paint_mode == StereoPaintMode::BothEyes {
+            Some(self.disparity)
+        } else {
+            None
+        }
+    }
+
+    // shows the error of an operation instead of panicking
+    fn report<T, E: Into<PanoError>>(&self, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.link.send_message(Msg::ShowError {
+                    message: err.into().to_string(),
+                });
+                None
+            }
+        }
+    }
+
+    fn view_state(&self) -> ViewState {
+        ViewState {
+            rotation_x: self.rotation_x,
+            rotation_y: self.rotation_y,
+            fov: self.fov,
+        }
+    }
+
+    fn modify_rotation(&mut self) {
+        let mut rotation_x = self.rotation_x;
+        let mut rotation_y = self.rotation_y;
+
+        rotation_x = (rotation_x + 180.0) % 360.0 - 180.0;
+        if rotation_x > 90.0 {
+            rotation_x = 180.0 - rotation_x;
+            rotation_y = rotation_y + 180.0;
+        }
+        if rotation_x < -90.0 {
+            rotation_x = -180.0 - rotation_x;
+            rotation_y = rotation_y + 180.0;
+        }
+
+        self.rotation_x = rotation_x;
+        self.rotation_y = rotation_y;
+    }
+}
+
+// (width, height, format, textures)
+type CanvasTargets = (u32, u32, PixelFormat, [WebGlTexture; 2]);
+
+pub struct ModelWebGL {
+    context: WebGl2RenderingContext,
+    // size of the document, every layer has this size
+    width: usize,
+    height: usize,
+    // of every layer and of the textures the layers are composited on
+    format: PixelFormat,
+    // how the eyes are packed on import and export, the layers of mono documents have no other eye
+    stereo: StereoLayout,
+    // the eye shown and edited
+    eye: Eye,
+    // layers[0] is the bottom of the stack
+    layers: Vec<Layer>,
+    active_layer: usize,
+    history: History,
+    reference: Option<Reference>,
+    // mask in the r channel, everything is selected when None
+    selection: Option<WebGlTexture>,
+    // 1x1 white texture bound instead of the mask when nothing is selected,
+    // and instead of the unallocated tiles of the white textures
+    white_texture: WebGlTexture,
+    // the stroke in the canvas space, whose tiles are allocated where the brush passes
+    brush_texture: Arc<Mutex<Option<TiledTexture>>>,
+    brush_dist: f32,
+    // segments (start, end, width) given to `draw_brush` since `start_brush`
+    stroke: Vec<(Vec3, Vec3, f32)>,
+    // canvas sized textures to composite adjustment and float layers on,
+    // reused while the size and the format are the same
+    canvas_targets: Mutex<Option<CanvasTargets>>,
 
     all_view_vert_shader: WebGlShader,
+    tile_view_vert_shader: WebGlShader,
     drawing_canvas_vert_shader: WebGlShader,
     brush_vert_shader: WebGlShader,
 
@@ -649,530 +2714,2600 @@ pub struct ModelWebGL {
     grid_frag_shader: WebGlShader,
     brush_frag_shader: WebGlShader,
     panorama_rotation_frag_shader: WebGlShader,
+    clone_stamp_frag_shader: WebGlShader,
+    layer_composite_frag_shader: WebGlShader,
+    adjustment_layer_frag_shader: WebGlShader,
+    show_premultiplied_frag_shader: WebGlShader,
+    rotate_panorama_frag_shader: WebGlShader,
+    reference_frag_shader: WebGlShader,
+    project_image_frag_shader: WebGlShader,
+    selection_polygon_frag_shader: WebGlShader,
+    selection_color_frag_shader: WebGlShader,
+    selection_invert_frag_shader: WebGlShader,
+    selection_overlay_frag_shader: WebGlShader,
+    fill_frag_shader: WebGlShader,
 }
 
 impl ModelWebGL {
-    pub fn import_png_to_work_texture(&self, path: &Path) -> Result<(), JsValue> {
-        let image = read_image(path);
-        assert_eq!(image.height(), WORK_TEXTURE_HEIGHT as u32);
-        assert_eq!(image.width(), WORK_TEXTURE_WIDTH as u32);
+    pub fn active_layer(&self) -> &Layer {
+        &self.layers[self.active_layer]
+    }
+
+    pub fn active_layer_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.active_layer]
+    }
+
+    pub fn add_layer(&mut self) -> Result<(), JsValue> {
+        let texture = TiledTexture::new(self.width, self.height, self.format, [0; 4]);
+        let name = format!("レイヤー {}", self.layers.len() + 1);
+        self.insert_layer(Layer::new(name, texture));
+        Ok(())
+    }
+
+    // the mask of a new adjustment layer applies it everywhere
+    pub fn add_adjustment_layer(&mut self, adjustment: Adjustment) -> Result<(), JsValue> {
+        let texture = TiledTexture::new(self.width, self.height, self.format, [255; 4]);
+        let mut layer = Layer::new(adjustment.name().to_string(), texture);
+        layer.set_adjustment(&self.context, adjustment)?;
+        self.insert_layer(layer);
+        Ok(())
+    }
+
+    pub fn set_active_adjustment(&mut self, adjustment: Adjustment) -> Result<(), JsValue> {
+        let context = self.context.clone();
+        self.history.mark_modified();
+        self.active_layer_mut().set_adjustment(&context, adjustment)
+    }
+
+    // inserts above the active layer, undoably.
+    // the other eye of a layer without one is transparent in stereo documents.
+    fn insert_layer(&mut self, mut layer: Layer) {
+        if self.stereo.is_stereo() && layer.other_eye.is_none() {
+            layer.other_eye = Some(TiledTexture::new(
+                self.width,
+                self.height,
+                self.format,
+                layer.texture.fill,
+            ));
+            layer.eye = self.eye;
+        }
+        self.active_layer += 1;
+        let entry = HistoryEntry::InsertLayer {
+            idx: self.active_layer,
+            layer_id: layer.id,
+            layer: None,
+        };
+        self.layers.insert(self.active_layer, layer);
+        self.push_history(entry);
+    }
+
+    pub fn delete_active_layer(&mut self) {
+        if self.layers.len() > 1 {
+            let idx = self.active_layer;
+            let layer = self.layers.remove(idx);
+            self.active_layer = self.active_layer.min(self.layers.len() - 1);
+            self.push_history(HistoryEntry::DeleteLayer {
+                idx,
+                layer_id: layer.id,
+                layer: Some(layer),
+            });
+        }
+    }
+
+    pub fn move_layer(&mut self, idx: usize, up: bool) {
+        let target = if up {
+            if idx + 1 >= self.layers.len() {
+                return;
+            }
+            idx + 1
+        } else {
+            if idx == 0 {
+                return;
+            }
+            idx - 1
+        };
+        self.layers.swap(idx, target);
+        self.history.mark_modified();
+        if self.active_layer == idx {
+            self.active_layer = target;
+        } else if self.active_layer == target {
+            self.active_layer = idx;
+        }
+    }
+
+    // returns the photo sphere metadata of the image if any.
+    // a partial panorama is placed at its cropped area and the rest of the layer is transparent.
+    // the image of a stereo document holds both eyes packed in its layout.
+    // `bytes` is the file at `path`, whose extension tells the format when the content does not
+    pub fn import_image_as_layer(
+        &mut self,
+        path: &Path,
+        bytes: &[u8],
+    ) -> Result<Option<GPano>, PanoError> {
+        // the metadata describes a single panorama
+        let gpano = if self.stereo.is_stereo() {
+            None
+        } else {
+            GPano::read(bytes)
+        };
+        let rect = gpano.map_or((0, 0, self.width, self.height), |gpano| {
+            gpano.cropped_rect(self.width, self.height)
+        });
+        let (_, _, width, height) = rect;
+        let eyes = if self.stereo.is_stereo() {
+            vec![self.eye, self.eye.other()]
+        } else {
+            vec![self.eye]
+        };
+        // the images are converted to the format of the layers one tile at a time
+        let mut textures = vec![];
+        if self.format.is_float() {
+            let image = decode_float_image(bytes, path)?;
+            let image = image::ImageBuffer::<image::Rgba<f32>, Vec<f32>>::from_vec(
+                image.width as u32,
+                image.height as u32,
+                image.pixels.concat(),
+            )
+            .ok_or_else(|| PanoError::Decode(String::from("image size")))?;
+            for &eye in eyes.iter() {
+                // the premultiplied colors do not bleed from the transparent pixels when resized
+                let image = self.eye_image(&image, eye, width, height);
+                textures.push(self.place_image(rect, |x, y, count| {
+                    let start = (y * width + x) * 4;
+                    self.format
+                        .f32_to_pixels(&unpremultiply(&image.as_raw()[start..start + count * 4]))
+                })?);
+            }
+        } else {
+            let image = decode_image(bytes, path)?;
+            for &eye in eyes.iter() {
+                let image = self.eye_image(&image, eye, width, height);
+                textures.push(self.place_image(rect, |x, y, count| {
+                    let start = (y * width + x) * 4;
+                    image.as_raw()[start..start + count * 4].to_vec()
+                })?);
+            }
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| format!("レイヤー {}", self.layers.len() + 1));
+        let mut textures = textures.into_iter();
+        let mut layer = Layer::new(name, textures.next().unwrap());
+        layer.other_eye = textures.next();
+        layer.eye = self.eye;
+        self.insert_layer(layer);
+
+        Ok(gpano)
+    }
+
+    // the part of `image` of `eye` in the stereo layout, resized to `width` x `height`
+    fn eye_image<P>(
+        &self,
+        image: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+        eye: Eye,
+        width: usize,
+        height: usize,
+    ) -> image::ImageBuffer<P, Vec<P::Subpixel>>
+    where
+        P: image::Pixel + 'static,
+        P::Subpixel: 'static,
+    {
+        let (x, y, eye_width, eye_height) =
+            self.stereo
+                .eye_rect(eye, image.width() as usize, image.height() as usize);
+        let image = image::imageops::crop_imm(
+            image,
+            x as u32,
+            y as u32,
+            eye_width as u32,
+            eye_height as u32,
+        );
+        if (eye_width, eye_height) == (width, height) {
+            image.to_image()
+        } else {
+            image::imageops::resize(
+                &image,
+                width as u32,
+                height as u32,
+                image::imageops::FilterType::Triangle,
+            )
+        }
+    }
+
+    // a layer with an image at `rect`, which wraps around horizontally.
+    // `row_pixels(x, y, count)` returns `count` pixels of the image from (x, y) in the format
+    // of the layers. only the tiles under the image are allocated.
+    fn place_image(
+        &self,
+        rect: (usize, usize, usize, usize),
+        row_pixels: impl Fn(usize, usize, usize) -> Vec<u8>,
+    ) -> Result<TiledTexture, JsValue> {
+        let (left, top, width, height) = rect;
+        let pixel_size = self.format.pixel_size();
+        TiledTexture::from_tiles(
+            &self.context,
+            self.width,
+            self.height,
+            self.format,
+            [0; 4],
+            |(tile_x, tile_y, tile_width, tile_height)| {
+                let mut pixels: Option<Vec<u8>> = None;
+                // the image from `left` and its part wrapped around to the left edge
+                for offset in [left as isize, left as isize - self.width as isize] {
+                    let x0 = (tile_x as isize).max(offset);
+                    let x1 = ((tile_x + tile_width) as isize).min(offset + width as isize);
+                    let y0 = tile_y.max(top);
+                    let y1 = (tile_y + tile_height).min(top + height);
+                    if x0 >= x1 || y0 >= y1 {
+                        continue;
+                    }
+                    let pixels = pixels.get_or_insert_with(|| {
+                        self.format.fill_pixels([0; 4], tile_width * tile_height)
+                    });
+                    for y in y0..y1 {
+                        let row = row_pixels((x0 - offset) as usize, y - top, (x1 - x0) as usize);
+                        let start = ((y - tile_y) * tile_width + x0 as usize - tile_x) * pixel_size;
+                        pixels[start..start + row.len()].copy_from_slice(&row);
+                    }
+                }
+                Ok(pixels)
+            },
+        )
+    }
+
+    // converts every layer, which clears the history as its tiles are in the old format
+    pub fn convert_format(&mut self, format: PixelFormat) -> Result<(), JsValue> {
+        if format == self.format {
+            return Ok(());
+        }
+        self.for_each_eye(|webgl| {
+            for layer in webgl.layers.iter_mut() {
+                // the unallocated tiles keep reading as the fill
+                let old = &layer.texture;
+                let texture = TiledTexture::from_tiles(
+                    &webgl.context,
+                    webgl.width,
+                    webgl.height,
+                    format,
+                    old.fill,
+                    |(x, y, _, _)| {
+                        let index = (x / TILE_SIZE, y / TILE_SIZE);
+                        if old.tile(index).is_none() {
+                            return Ok(None);
+                        }
+                        let pixels = old.read_tile(&webgl.context, index)?;
+                        Ok(Some(format.rgba8_to_pixels(&old.format.to_rgba8(&pixels))))
+                    },
+                )?;
+                std::mem::replace(&mut layer.texture, texture).delete(&webgl.context);
+            }
+            Ok(())
+        })?;
+        let dropped = self.history.clear();
+        self.release_history_entries(dropped);
+        self.format = format;
+        Ok(())
+    }
+
+    // going stereo starts both eyes as the eye shown so far, going mono keeps the eye shown.
+    // the history is cleared as it may refer to the other eye.
+    pub fn set_stereo_layout(&mut self, layout: StereoLayout) -> Result<(), JsValue> {
+        if layout.is_stereo() != self.stereo.is_stereo() {
+            for layer in self.layers.iter_mut() {
+                let other_eye = if layout.is_stereo() {
+                    Some(layer.texture.copy(&self.context)?)
+                } else {
+                    None
+                };
+                if let Some(old) = std::mem::replace(&mut layer.other_eye, other_eye) {
+                    old.delete(&self.context);
+                }
+                layer.eye = Eye::Left;
+            }
+            self.eye = Eye::Left;
+            let dropped = self.history.clear();
+            self.release_history_entries(dropped);
+        }
+        self.stereo = layout;
+        Ok(())
+    }
+
+    // shows and edits the textures of `eye` in every layer, nothing changes in mono documents
+    pub fn set_eye(&mut self, eye: Eye) {
+        if !self.stereo.is_stereo() {
+            return;
+        }
+        for layer in self.layers.iter_mut() {
+            layer.set_eye(eye);
+        }
+        self.eye = eye;
+    }
+
+    // runs `f` with each eye shown, once in mono documents
+    fn for_each_eye<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, JsValue>,
+    ) -> Result<Vec<T>, JsValue> {
+        let shown = self.eye;
+        let mut results = vec![f(self)?];
+        if self.stereo.is_stereo() {
+            self.set_eye(shown.other());
+            let result = f(self);
+            self.set_eye(shown);
+            results.push(result?);
+        }
+        Ok(results)
+    }
+
+    // the brush colors are srgb, float documents take linear ones which may be intensified above 1.0
+    fn paint_color(&self, brush: &BrushPreset) -> [f32; 3] {
+        if self.format.is_float() {
+            brush
+                .color
+                .map(|value| srgb_to_linear(value) * brush.intensity)
+        } else {
+            brush.color
+        }
+    }
+
+    fn layer_index(&self, layer_id: u32) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.id == layer_id)
+    }
+
+    fn push_history(&mut self, entry: HistoryEntry) {
+        let dropped = self.history.push(entry);
+        self.release_history_entries(dropped);
+    }
+
+    // deletes the textures of layers which are only referenced by dropped entries
+    fn release_history_entries(&mut self, entries: Vec<HistoryEntry>) {
+        for entry in entries {
+            match entry {
+                HistoryEntry::InsertLayer {
+                    layer: Some(layer), ..
+                }
+                | HistoryEntry::DeleteLayer {
+                    layer: Some(layer), ..
+                } => {
+                    layer.delete_textures(&self.context);
+                }
+                HistoryEntry::Batch(entries) => self.release_history_entries(entries),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn set_history_depth(&mut self, depth: usize) {
+        let dropped = self.history.set_depth(depth);
+        self.release_history_entries(dropped);
+    }
+
+    // pixels of `tiles` of the active layer before it is modified
+    fn begin_layer_edit(&self, tiles: &[TileIndex]) -> Result<TilePixels, JsValue> {
+        self.read_layer_tiles(self.active_layer, tiles)
+    }
+
+    fn read_layer_tiles(&self, idx: usize, tiles: &[TileIndex]) -> Result<TilePixels, JsValue> {
+        let texture = &self.layers[idx].texture;
+        tiles
+            .iter()
+            .map(|&index| Ok((index, texture.read_tile(&self.context, index)?)))
+            .collect()
+    }
+
+    // records the tiles of the active layer changed since `begin_layer_edit`
+    fn commit_layer_edit(&mut self, before: TilePixels) -> Result<(), JsValue> {
+        if let Some(entry) = self.layer_edit_entry(self.active_layer, before)? {
+            self.push_history(entry);
+        }
+        Ok(())
+    }
+
+    // parts of the tiles of the layer changed from `before`, None when nothing has changed
+    fn layer_edit_entry(
+        &self,
+        idx: usize,
+        before: TilePixels,
+    ) -> Result<Option<HistoryEntry>, JsValue> {
+        let texture = &self.layers[idx].texture;
+        let mut tiles = vec![];
+        for (index, before) in before {
+            let after = texture.read_tile(&self.context, index)?;
+            let (x, y, width, height) = texture.rect(index);
+            tiles.append(&mut diff_tiles(
+                x,
+                y,
+                width,
+                height,
+                texture.format.pixel_size(),
+                &before,
+                &after,
+            ));
+        }
+        if tiles.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(HistoryEntry::Pixels {
+                layer_id: self.layers[idx].id,
+                eye: self.eye,
+                tiles,
+            }))
+        }
+    }
+
+    fn write_tiles(
+        &mut self,
+        layer_id: u32,
+        tiles: &[history::Tile],
+        before: bool,
+    ) -> Result<(), JsValue> {
+        let idx = if let Some(idx) = self.layer_index(layer_id) {
+            idx
+        } else {
+            return Ok(());
+        };
+        let texture = &mut self.layers[idx].texture;
+        for tile in tiles {
+            let pixels = if before { &tile.before } else { &tile.after };
+            // the history tiles are found within a tile of the layer
+            texture.write_rect(
+                &self.context,
+                (tile.x / TILE_SIZE, tile.y / TILE_SIZE),
+                (
+                    tile.x % TILE_SIZE,
+                    tile.y % TILE_SIZE,
+                    tile.width,
+                    tile.height,
+                ),
+                pixels,
+            )?;
+        }
+        Ok(())
+    }
+
+    // applies `entry` backward (undo) or forward (redo) in place, for the opposite stack.
+    // applying it again after a failure gives the same layers
+    fn apply_history_entry(
+        &mut self,
+        entry: &mut HistoryEntry,
+        backward: bool,
+    ) -> Result<(), PanoError> {
+        match entry {
+            HistoryEntry::Pixels {
+                layer_id,
+                eye,
+                tiles,
+            } => {
+                // the tiles of the other eye are written while it is shown
+                let shown = self.eye;
+                self.set_eye(*eye);
+                let result = self.write_tiles(*layer_id, tiles, backward);
+                self.set_eye(shown);
+                result?;
+            }
+            HistoryEntry::Batch(entries) => {
+                if backward {
+                    for entry in entries.iter_mut().rev() {
+                        self.apply_history_entry(entry, backward)?;
+                    }
+                } else {
+                    for entry in entries.iter_mut() {
+                        self.apply_history_entry(entry, backward)?;
+                    }
+                }
+            }
+            HistoryEntry::InsertLayer {
+                idx,
+                layer_id,
+                layer,
+            } => {
+                *layer = self.swap_layer(*idx, *layer_id, layer.take(), backward);
+            }
+            HistoryEntry::DeleteLayer {
+                idx,
+                layer_id,
+                layer,
+            } => {
+                *layer = self.swap_layer(*idx, *layer_id, layer.take(), !backward);
+            }
+        }
+        Ok(())
+    }
+
+    // takes the layer out of the stack when `remove`, puts it back otherwise
+    fn swap_layer(
+        &mut self,
+        idx: usize,
+        layer_id: u32,
+        layer: Option<Layer>,
+        remove: bool,
+    ) -> Option<Layer> {
+        if remove {
+            match self.layer_index(layer_id) {
+                Some(idx) if self.layers.len() > 1 => {
+                    let layer = self.layers.remove(idx);
+                    self.active_layer = self.active_layer.min(self.layers.len() - 1);
+                    Some(layer)
+                }
+                _ => layer,
+            }
+        } else if let Some(mut layer) = layer {
+            layer.set_eye(self.eye);
+            let idx = idx.min(self.layers.len());
+            self.layers.insert(idx, layer);
+            self.active_layer = idx;
+            None
+        } else {
+            None
+        }
+    }
+
+    // the entry stays on its stack when it fails, so that it can be undone again
+    pub fn undo(&mut self) -> Result<(), PanoError> {
+        if let Some(mut entry) = self.history.pop_undo() {
+            match self.apply_history_entry(&mut entry, true) {
+                Ok(()) => self.history.push_redo(entry),
+                Err(err) => {
+                    self.history.push_undo(entry);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn redo(&mut self) -> Result<(), PanoError> {
+        if let Some(mut entry) = self.history.pop_redo() {
+            match self.apply_history_entry(&mut entry, false) {
+                Ok(()) => self.history.push_undo(entry),
+                Err(err) => {
+                    self.history.push_redo(entry);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn draw_circle(
+        &mut self,
+        scale: f32,
+        position: (f32, f32, f32),
+        circle_color: (f32, f32, f32, f32),
+    ) -> Result<(), JsValue> {
+        let program = link_program(
+            &self.context,
+            &self.tile_view_vert_shader,
+            &self.draw_circle_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "target_rect".to_string(),
+                "scale".to_string(),
+                "position".to_string(),
+                "circle_color".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        self.context.uniform1f(Some(&uniforms["scale"]), scale);
+        self.context.uniform3f(
+            Some(&uniforms["position"]),
+            position.0,
+            position.1,
+            position.2,
+        );
+        self.context.uniform4f(
+            Some(&uniforms["circle_color"]),
+            circle_color.0,
+            circle_color.1,
+            circle_color.2,
+            circle_color.3,
+        );
+        self.context.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+        let tiles = self.active_layer().texture.all_tiles();
+        self.draw_on_active_layer(&tiles, &uniforms["target_rect"])
+    }
+
+    // draws the used program, whose vertex shader is tile_view.vert, on `tiles` of the active layer
+    fn draw_on_active_layer(
+        &mut self,
+        tiles: &[TileIndex],
+        target_rect: &WebGlUniformLocation,
+    ) -> Result<(), JsValue> {
+        let context = &self.context;
+        self.layers[self.active_layer]
+            .texture
+            .draw(context, tiles, target_rect, |_| {
+                context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+                Ok(())
+            })
+    }
+
+    pub fn show(
+        &mut self,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+        enable_grid: bool,
+        reference: &ReferenceSettings,
+        display: &DisplaySettings,
+    ) -> Result<(), PanoError> {
+        if !(display.anaglyph && self.stereo.is_stereo()) {
+            return self.show_eye(rotation_x, rotation_y, fov, enable_grid, reference, display);
+        }
+        // each eye is drawn on the channels of its color in turn
+        let shown = self.eye;
+        let mut result = Ok(());
+        for eye in Eye::ALL {
+            self.set_eye(eye);
+            result = result.and_then(|_| {
+                self.show_eye(rotation_x, rotation_y, fov, enable_grid, reference, display)
+            });
+        }
+        self.set_eye(shown);
+        self.context.color_mask(true, true, true, true);
+        result
+    }
+
+    // draws the eye shown, on the channels of its color in the anaglyph
+    fn show_eye(
+        &self,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+        enable_grid: bool,
+        reference: &ReferenceSettings,
+        display: &DisplaySettings,
+    ) -> Result<(), PanoError> {
+        let color_mask = if display.anaglyph && self.stereo.is_stereo() {
+            self.eye.anaglyph_mask()
+        } else {
+            [true; 4]
+        };
+        let [red, green, blue, alpha] = color_mask;
+        self.context.color_mask(red, green, blue, alpha);
+        self.show_alpha_grid(rotation_x, rotation_y, fov)?;
+        // float layers are composited before the tone mapping, not to be clipped on the canvas
+        if self.format.is_float()
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.visible && layer.adjustment.is_some())
+        {
+            self.show_adjusted_layers(rotation_x, rotation_y, fov, display, color_mask)?;
+        } else {
+            for (idx, layer) in self.layers.iter().enumerate() {
+                if layer.visible {
+                    self.show_texture(layer, rotation_x, rotation_y, fov, display)?;
+                }
+                if idx == self.active_layer {
+                    self.show_brush_texture(fov, display)?;
+                }
+            }
+        }
+        if self.selection.is_some() {
+            self.show_selection(rotation_x, rotation_y, fov)?;
+        }
+        if reference.visible {
+            self.show_reference(reference, rotation_x, rotation_y, fov)?;
+        }
+        if enable_grid {
+            self.show_grid(rotation_x, rotation_y, fov)?;
+        }
+        Ok(())
+    }
+
+    // adjustment layers need the composite below them, so the layers are drawn on
+    // canvas sized textures in turn and the result is drawn on the canvas at last
+    // with `display` and `color_mask`
+    fn show_adjusted_layers(
+        &self,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+        display: &DisplaySettings,
+        color_mask: [bool; 4],
+    ) -> Result<(), JsValue> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+
+        let mut canvas_targets = self.canvas_targets.lock().unwrap();
+        if !matches!(canvas_targets.as_ref(), Some((width, height, format, _)) if *width == canvas.width() && *height == canvas.height() && *format == self.format)
+        {
+            if let Some((_, _, _, targets)) = canvas_targets.take() {
+                for target in targets.iter() {
+                    self.context.delete_texture(Some(target));
+                }
+            }
+            let create_target = || {
+                create_texture(
+                    &self.context,
+                    self.format,
+                    canvas.width() as usize,
+                    canvas.height() as usize,
+                    None,
+                )
+            };
+            *canvas_targets = Some((
+                canvas.width(),
+                canvas.height(),
+                self.format,
+                [create_target()?, create_target()?],
+            ));
+        }
+        let targets = &canvas_targets.as_ref().unwrap().3;
+
+        let frame_buffer = self.context.create_framebuffer().unwrap();
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+        let mut current = 0;
+        self.context.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&targets[current]),
+            0,
+        );
+        // every channel of the targets is needed by the adjustments
+        self.context.color_mask(true, true, true, true);
+        self.context.clear_color(0.0, 0.0, 0.0, 0.0);
+        self.context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+
+        for (idx, layer) in self.layers.iter().enumerate() {
+            if layer.visible {
+                if layer.adjustment.is_some() {
+                    self.context.framebuffer_texture_2d(
+                        WebGl2RenderingContext::FRAMEBUFFER,
+                        WebGl2RenderingContext::COLOR_ATTACHMENT0,
+                        WebGl2RenderingContext::TEXTURE_2D,
+                        Some(&targets[1 - current]),
+                        0,
+                    );
+                    self.show_adjustment(layer, &targets[current], rotation_x, rotation_y, fov)?;
+                    current = 1 - current;
+                } else {
+                    self.show_texture(
+                        layer,
+                        rotation_x,
+                        rotation_y,
+                        fov,
+                        &DisplaySettings::IDENTITY,
+                    )?;
+                }
+            }
+            if idx == self.active_layer {
+                self.show_brush_texture(fov, &DisplaySettings::IDENTITY)?;
+            }
+        }
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.context.delete_framebuffer(Some(&frame_buffer));
+        let [red, green, blue, alpha] = color_mask;
+        self.context.color_mask(red, green, blue, alpha);
+
+        self.context
+            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+        let program = link_program(
+            &self.context,
+            &self.all_view_vert_shader,
+            &self.show_premultiplied_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "tex".to_string(),
+                "exposure".to_string(),
+                "tone_mapping".to_string(),
+                "gamma".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&targets[current]));
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.set_display_uniforms(&uniforms, display);
+        BlendMode::Normal.apply(&self.context);
+        self.context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+        Ok(())
+    }
+
+    // links adjustment_layer.frag and sets the uniforms shared by the tiles of the mask, `view` is
+    // (rotation_x, rotation_y, fov) when drawing in the canvas space, None when drawing in the tiles.
+    // `below` is bound to TEXTURE0 and the mask to TEXTURE1 afterwards.
+    fn use_adjustment_program(
+        &self,
+        layer: &Layer,
+        view: Option<(f32, f32, f32)>,
+    ) -> Result<HashMap<String, WebGlUniformLocation>, JsValue> {
+        let vert_shader = if view.is_some() {
+            &self.drawing_canvas_vert_shader
+        } else {
+            &self.tile_view_vert_shader
+        };
+        let program = link_program(
+            &self.context,
+            vert_shader,
+            &self.adjustment_layer_frag_shader,
+        )?;
+        let mut keys = vec![
+            "below".to_string(),
+            "mask".to_string(),
+            "mask_rect".to_string(),
+            "lut".to_string(),
+            "lut_size".to_string(),
+            "opacity".to_string(),
+            "on_canvas".to_string(),
+            "rotation".to_string(),
+            "linear_colors".to_string(),
+        ];
+        if view.is_some() {
+            keys.push("fov".to_string());
+        } else {
+            keys.push("target_rect".to_string());
+        }
+        let uniforms = get_uniform_locations(&self.context, &program, keys)?;
+        self.context.use_program(Some(&program));
+
+        let (rotation_x, rotation_y, fov) = view.unwrap_or((0.0, 0.0, 0.0));
+        self.context.uniform1i(Some(&uniforms["below"]), 0);
+        self.context.uniform1i(Some(&uniforms["mask"]), 1);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE2);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, layer.lut.as_ref());
+        self.context.uniform1i(Some(&uniforms["lut"]), 2);
+        self.context
+            .uniform1f(Some(&uniforms["lut_size"]), LUT_SIZE as f32);
+        self.context
+            .uniform1i(Some(&uniforms["on_canvas"]), view.is_some() as i32);
+        self.context.uniform1i(
+            Some(&uniforms["linear_colors"]),
+            self.format.is_float() as i32,
+        );
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(&uniforms["rotation"]),
+            false,
+            &to_gl_matrix(&view_rotation(rotation_x, rotation_y)),
+        );
+        if view.is_some() {
+            self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        }
+
+        // the result replaces the target
+        self.context.disable(WebGl2RenderingContext::BLEND);
+        Ok(uniforms)
+    }
+
+    fn bind_mask_tile(
+        &self,
+        layer: &Layer,
+        index: TileIndex,
+        uniforms: &HashMap<String, WebGlUniformLocation>,
+    ) {
+        let mask = layer.texture.tile_or_white(index, &self.white_texture);
+        let rect = layer.texture.tex_rect(index);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(mask.unwrap_or(&self.white_texture)),
+        );
+        // a transparent tile leaves the pixels as they are
+        self.context.uniform1f(
+            Some(&uniforms["opacity"]),
+            if mask.is_some() { layer.opacity } else { 0.0 },
+        );
+        self.context.uniform4f(
+            Some(&uniforms["mask_rect"]),
+            rect[0],
+            rect[1],
+            rect[2],
+            rect[3],
+        );
+    }
+
+    fn unbind_adjustment_textures(&self) {
+        for unit in (0..3).rev() {
+            self.context
+                .active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+            self.context
+                .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        }
+    }
+
+    // draws the adjusted `below`, as large as the canvas, on the bound framebuffer
+    fn show_adjustment(
+        &self,
+        layer: &Layer,
+        below: &WebGlTexture,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+    ) -> Result<(), JsValue> {
+        let uniforms = self.use_adjustment_program(layer, Some((rotation_x, rotation_y, fov)))?;
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(below));
+        // every pixel of the view has to be written, so no tile is skipped
+        for index in layer.texture.tiles_in_view(rotation_x, rotation_y, fov) {
+            self.bind_mask_tile(layer, index, &uniforms);
+            self.context
+                .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+        }
+        self.unbind_adjustment_textures();
+        Ok(())
+    }
+
+    // adjusts the tiles of `composite` in place, `scratch` is a texture of `TILE_SIZE`
+    fn composite_adjustment(
+        &self,
+        layer: &Layer,
+        composite: &mut TiledTexture,
+        scratch: &WebGlTexture,
+    ) -> Result<(), JsValue> {
+        let uniforms = self.use_adjustment_program(layer, None)?;
+        let (width, height) = (composite.width, composite.height);
+        // transparent pixels are kept, so the unallocated tiles are skipped
+        let tiles = composite.allocated_tiles();
+        composite.draw(
+            &self.context,
+            &tiles,
+            &uniforms["target_rect"],
+            |(column, row)| {
+                // the tile cannot be sampled while rendering into it
+                self.context
+                    .active_texture(WebGl2RenderingContext::TEXTURE0);
+                self.context
+                    .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(scratch));
+                self.context.copy_tex_sub_image_2d(
+                    WebGl2RenderingContext::TEXTURE_2D,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    TILE_SIZE.min(width - column * TILE_SIZE) as i32,
+                    TILE_SIZE.min(height - row * TILE_SIZE) as i32,
+                );
+                self.bind_mask_tile(layer, (column, row), &uniforms);
+                self.context
+                    .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+                Ok(())
+            },
+        )?;
+        self.unbind_adjustment_textures();
+        Ok(())
+    }
+
+    pub fn show_texture(
+        &self,
+        layer: &Layer,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+        display: &DisplaySettings,
+    ) -> Result<(), JsValue> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+
+        self.context
+            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+        let program = link_program(
+            &self.context,
+            &self.drawing_canvas_vert_shader,
+            &self.show_panorama_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "fov".to_string(),
+                "tex".to_string(),
+                "tile_rect".to_string(),
+                "rotation_x".to_string(),
+                "rotation_y".to_string(),
+                "opacity".to_string(),
+                "exposure".to_string(),
+                "tone_mapping".to_string(),
+                "gamma".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        self.context
+            .uniform1f(Some(&uniforms["opacity"]), layer.opacity);
+        self.set_display_uniforms(&uniforms, display);
+
+        layer.blend_mode.apply(&self.context);
+        // only the tiles in the view are drawn
+        for index in layer.texture.tiles_in_view(rotation_x, rotation_y, fov) {
+            if let Some(tile) = layer.texture.tile_or_white(index, &self.white_texture) {
+                let rect = layer.texture.tex_rect(index);
+                self.context
+                    .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(tile));
+                self.context.uniform4f(
+                    Some(&uniforms["tile_rect"]),
+                    rect[0],
+                    rect[1],
+                    rect[2],
+                    rect[3],
+                );
+                self.context
+                    .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+            }
+        }
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+        Ok(())
+    }
+
+    // the uniforms of show_panorama.frag and show_premultiplied.frag
+    fn set_display_uniforms(
+        &self,
+        uniforms: &HashMap<String, WebGlUniformLocation>,
+        display: &DisplaySettings,
+    ) {
+        self.context
+            .uniform1f(Some(&uniforms["exposure"]), display.exposure);
+        self.context.uniform1i(
+            Some(&uniforms["tone_mapping"]),
+            display.tone_mapping.uniform(),
+        );
+        self.context
+            .uniform1f(Some(&uniforms["gamma"]), display.gamma);
+    }
+
+    pub fn show_selection(
+        &self,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+    ) -> Result<(), JsValue> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+
+        self.context
+            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+        let program = link_program(
+            &self.context,
+            &self.drawing_canvas_vert_shader,
+            &self.selection_overlay_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "fov".to_string(),
+                "mask_tex".to_string(),
+                "rotation_x".to_string(),
+                "rotation_y".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(self.selection_texture()),
+        );
+        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        self.context.uniform1i(Some(&uniforms["mask_tex"]), 0);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+
+        BlendMode::Normal.apply(&self.context);
+        self.context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+        Ok(())
+    }
+
+    pub fn load_reference(&mut self, image: &image::RgbaImage) -> Result<(), PanoError> {
+        let texture = create_layer_texture(
+            &self.context,
+            image.width() as usize,
+            image.height() as usize,
+            Some(image.as_raw().as_slice()),
+        )?;
+        self.delete_reference();
+        self.reference = Some(Reference {
+            texture,
+            aspect: image.width() as f32 / image.height() as f32,
+        });
+        Ok(())
+    }
+
+    pub fn delete_reference(&mut self) {
+        if let Some(reference) = self.reference.take() {
+            self.context.delete_texture(Some(&reference.texture));
+        }
+    }
+
+    pub fn show_reference(
+        &self,
+        settings: &ReferenceSettings,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+    ) -> Result<(), JsValue> {
+        let reference = if let Some(reference) = self.reference.as_ref() {
+            reference
+        } else {
+            return Ok(());
+        };
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+
+        self.context
+            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+        let program = link_program(
+            &self.context,
+            &self.drawing_canvas_vert_shader,
+            &self.reference_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "fov".to_string(),
+                "tex".to_string(),
+                "opacity".to_string(),
+                "rotation".to_string(),
+                "center".to_string(),
+                "half_size".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        let (rotation, center, half_size) =
+            settings.plane(reference.aspect, rotation_x, rotation_y, fov);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&reference.texture));
+        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.context
+            .uniform1f(Some(&uniforms["opacity"]), settings.opacity);
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(&uniforms["rotation"]),
+            false,
+            &to_gl_matrix(&rotation),
+        );
+        self.context
+            .uniform2f(Some(&uniforms["center"]), center.0, center.1);
+        self.context
+            .uniform2f(Some(&uniforms["half_size"]), half_size.0, half_size.1);
+
+        BlendMode::Normal.apply(&self.context);
+        self.context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+        Ok(())
+    }
+
+    pub fn show_alpha_grid(
+        &self,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+    ) -> Result<(), JsValue> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+
+        self.context
+            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+        let program = link_program(
+            &self.context,
+            &self.drawing_canvas_vert_shader,
+            &self.alpha_grid_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "fov".to_string(),
+                "rotation_x".to_string(),
+                "rotation_y".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        self.context.enable(WebGl2RenderingContext::BLEND);
+        self.context.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+
+        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        self.context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+
+        Ok(())
+    }
+
+    pub fn show_grid(&self, rotation_x: f32, rotation_y: f32, fov: f32) -> Result<(), JsValue> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+
+        self.context
+            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+        let program = link_program(
+            &self.context,
+            &self.drawing_canvas_vert_shader,
+            &self.grid_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "fov".to_string(),
+                "rotation_x".to_string(),
+                "rotation_y".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        self.context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+
+        Ok(())
+    }
+
+    // flattens the visible layers into a new texture (premultiplied alpha)
+    pub fn composite_layers(&self) -> Result<TiledTexture, JsValue> {
+        let mut composite = TiledTexture::new(self.width, self.height, self.format, [0; 4]);
+        // the adjustment layers read the tiles of the composite from this one
+        let mut scratch = None;
+
+        let program = link_program(
+            &self.context,
+            &self.tile_view_vert_shader,
+            &self.layer_composite_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "target_rect".to_string(),
+                "tex".to_string(),
+                "opacity".to_string(),
+            ],
+        )?;
+
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            if layer.adjustment.is_some() {
+                if scratch.is_none() {
+                    scratch = Some(create_texture(
+                        &self.context,
+                        self.format,
+                        TILE_SIZE,
+                        TILE_SIZE,
+                        None,
+                    )?);
+                }
+                self.composite_adjustment(layer, &mut composite, scratch.as_ref().unwrap())?;
+                continue;
+            }
+            self.context.use_program(Some(&program));
+            self.context
+                .active_texture(WebGl2RenderingContext::TEXTURE0);
+            self.context.uniform1i(Some(&uniforms["tex"]), 0);
+            self.context
+                .uniform1f(Some(&uniforms["opacity"]), layer.opacity);
+            layer.blend_mode.apply(&self.context);
+            // the transparent tiles change nothing
+            let tiles: Vec<TileIndex> = layer
+                .texture
+                .all_tiles()
+                .into_iter()
+                .filter(|&index| {
+                    layer
+                        .texture
+                        .tile_or_white(index, &self.white_texture)
+                        .is_some()
+                })
+                .collect();
+            composite.draw(&self.context, &tiles, &uniforms["target_rect"], |index| {
+                self.context.bind_texture(
+                    WebGl2RenderingContext::TEXTURE_2D,
+                    layer.texture.tile_or_white(index, &self.white_texture),
+                );
+                self.context
+                    .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+                Ok(())
+            })?;
+        }
+
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        if let Some(scratch) = scratch {
+            self.context.delete_texture(Some(&scratch));
+        }
+
+        Ok(composite)
+    }
+
+    // the size of the textures which cover the whole panorama, e.g. the brush stroke and the
+    // selection. the document size, halved while it is over the texture size limit of the GPU
+    fn work_size(&self) -> (usize, usize) {
+        let max_size = self
+            .context
+            .get_parameter(WebGl2RenderingContext::MAX_TEXTURE_SIZE)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(4096.0) as usize;
+        let (mut width, mut height) = (self.width, self.height);
+        while width > max_size || height > max_size {
+            width /= 2;
+            height /= 2;
+        }
+        (width.max(1), height.max(1))
+    }
+
+    // RGBA8 pixels of a texture of `work_size`
+    pub fn read_texture(&self, texture: &WebGlTexture) -> Result<Vec<u8>, JsValue> {
+        let (width, height) = self.work_size();
+        let frame_buffer = self.context.create_framebuffer().unwrap();
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+        self.context.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(texture),
+            0,
+        );
+
+        let mut data: Vec<u8> = vec![0; width * height * 4];
+        self.context.read_pixels_with_opt_u8_array(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            WebGl2RenderingContext::RGBA,
+            WebGl2RenderingContext::UNSIGNED_BYTE,
+            Some(data.as_mut_slice()),
+        )?;
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.context.delete_framebuffer(Some(&frame_buffer));
+
+        Ok(data)
+    }
+
+    pub fn to_project(
+        &self,
+        view: ViewState,
+        grid: GridSettings,
+        brush_presets: BrushPresetLibrary,
+    ) -> Result<Project, PanoError> {
+        let mut layers = vec![];
+        let mut layer_images = vec![];
+        let mut right_eye_images = vec![];
+        // float layers are kept in exr, which is premultiplied
+        let extension = if self.format.is_float() { "exr" } else { "png" };
+        for (idx, layer) in self.layers.iter().enumerate() {
+            layer_images.push(self.encode_layer_texture(layer.eye_texture(Eye::Left).unwrap())?);
+            let right_eye_file = if let Some(texture) = layer.eye_texture(Eye::Right) {
+                right_eye_images.push(self.encode_layer_texture(texture)?);
+                Some(format!("layers/{:>04}_right.{}", idx, extension))
+            } else {
+                None
+            };
+            layers.push(LayerManifest {
+                name: layer.name.clone(),
+                file: format!("layers/{:>04}.{}", idx, extension),
+                right_eye_file,
+                visible: layer.visible,
+                opacity: layer.opacity,
+                locked: layer.locked,
+                blend_mode: serde_json::to_value(layer.blend_mode)
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_string(),
+                adjustment: layer.adjustment,
+            });
+        }
+
+        Ok(Project {
+            manifest: ProjectManifest {
+                version: crate::project::PROJECT_FORMAT_VERSION,
+                width: self.width,
+                height: self.height,
+                format: self.format,
+                stereo: self.stereo,
+                view,
+                grid,
+                layers,
+                active_layer: self.active_layer,
+                brush_presets: Some(brush_presets),
+            },
+            layer_images,
+            right_eye_images,
+        })
+    }
+
+    // encoded in bands of rows, a large float layer does not fit in memory at once
+    fn encode_layer_texture(&self, texture: &TiledTexture) -> Result<Vec<u8>, PanoError> {
+        if self.format.is_float() {
+            encode_exr_rows(texture.width, texture.height, |y, count| {
+                let data = texture.read_rows(&self.context, y, count)?;
+                Ok(to_float_pixels(&premultiply(&self.format.to_f32(&data))))
+            })
+        } else {
+            encode_png_rows(texture.width, texture.height, |y, count| {
+                Ok(texture.read_rows(&self.context, y, count)?)
+            })
+        }
+    }
+
+    pub fn load_project(&mut self, project: &Project) -> Result<(), PanoError> {
+        let manifest = &project.manifest;
+
+        let mut layers = vec![];
+        let mut right_eye_images = project.right_eye_images.iter();
+        for (layer, image) in manifest.layers.iter().zip(project.layer_images.iter()) {
+            let texture = self.decode_layer_texture(manifest, layer, image)?;
+            let other_eye = if manifest.stereo.is_stereo() {
+                let image = right_eye_images
+                    .next()
+                    .filter(|_| layer.right_eye_file.is_some())
+                    .ok_or_else(|| {
+                        PanoError::Decode(format!("layer {} has no right eye", layer.name))
+                    })?;
+                Some(self.decode_layer_texture(manifest, layer, image)?)
+            } else {
+                None
+            };
+            let mut new_layer = Layer {
+                visible: layer.visible,
+                opacity: layer.opacity,
+                locked: layer.locked,
+                other_eye,
+                // blend modes unknown to this version fall back to normal
+                blend_mode: serde_json::from_value(serde_json::Value::String(
+                    layer.blend_mode.clone(),
+                ))
+                .unwrap_or(BlendMode::Normal),
+                ..Layer::new(layer.name.clone(), texture)
+            };
+            if let Some(adjustment) = layer.adjustment {
+                new_layer.set_adjustment(&self.context, adjustment)?;
+            }
+            layers.push(new_layer);
+        }
+        if layers.is_empty() {
+            return Err(PanoError::Decode(String::from("project has no layer")));
+        }
+
+        for layer in self.layers.drain(..) {
+            layer.delete_textures(&self.context);
+        }
+        let dropped = self.history.clear();
+        self.release_history_entries(dropped);
+        self.clear_selection();
+        self.active_layer = manifest.active_layer.min(layers.len() - 1);
+        self.layers = layers;
+        self.width = manifest.width;
+        self.height = manifest.height;
+        self.format = manifest.format;
+        self.stereo = manifest.stereo;
+        self.eye = Eye::Left;
+        Ok(())
+    }
+
+    fn decode_layer_texture(
+        &self,
+        manifest: &ProjectManifest,
+        layer: &LayerManifest,
+        image: &[u8],
+    ) -> Result<TiledTexture, PanoError> {
+        let (width, height, pixels) = if manifest.format.is_float() {
+            let image = decode_exr(image)?;
+            let pixels = unpremultiply(&image.pixels.concat());
+            (
+                image.width,
+                image.height,
+                manifest.format.f32_to_pixels(&pixels),
+            )
+        } else {
+            let image = decode_png(image)?;
+            (
+                image.width() as usize,
+                image.height() as usize,
+                image.into_raw(),
+            )
+        };
+        if (width, height) != (manifest.width, manifest.height) {
+            return Err(PanoError::Decode(format!(
+                "layer {} is {}x{}, not {}x{}",
+                layer.name, width, height, manifest.width, manifest.height
+            )));
+        }
+        // adjustment layers apply everywhere unless painted
+        let fill = if layer.adjustment.is_some() {
+            [255; 4]
+        } else {
+            [0; 4]
+        };
+        Ok(TiledTexture::from_pixels(
+            &self.context,
+            manifest.width,
+            manifest.height,
+            manifest.format,
+            fill,
+            &pixels,
+        )?)
+    }
+
+    // `count` rows from `y` of the composites of both eyes packed in `self.stereo`,
+    // of the composite of the document if mono
+    fn read_packed_rows(
+        &self,
+        composites: &[(Eye, TiledTexture)],
+        y: usize,
+        count: usize,
+    ) -> Result<Vec<u8>, JsValue> {
+        let (width, height) = self.stereo.packed_size(self.width, self.height);
+        let pixel_size = self.format.pixel_size();
+        let row_size = self.width * pixel_size;
+        let mut packed = vec![0; width * count * pixel_size];
+        for (eye, composite) in composites {
+            let (x, eye_y, _, _) = self.stereo.eye_rect(*eye, width, height);
+            // the rows of the band in the eye
+            let start = y.max(eye_y);
+            let end = (y + count).min(eye_y + self.height);
+            if start >= end {
+                continue;
+            }
+            let data = composite.read_rows(&self.context, start - eye_y, end - start)?;
+            for (row, pixels) in data.chunks_exact(row_size).enumerate() {
+                let offset = ((start - y + row) * width + x) * pixel_size;
+                packed[offset..offset + row_size].copy_from_slice(pixels);
+            }
+        }
+        Ok(packed)
+    }
+
+    // the file of the composite in `format`. png and jpeg images of mono documents are marked
+    // as photo spheres first shown in `view`
+    pub fn export_image(
+        &mut self,
+        format: ExportFormat,
+        view: &ViewState,
+    ) -> Result<Vec<u8>, PanoError> {
+        let composites = self.for_each_eye(|webgl| Ok((webgl.eye, webgl.composite_layers()?)))?;
+        let bytes = self.encode_composites(&composites, format);
+        for (_, composite) in composites {
+            composite.delete(&self.context);
+        }
+        Ok(self.embed_photo_sphere(bytes?, format, view))
+    }
+
+    // png and exr are encoded in bands of rows, the encoders of the other formats take
+    // the whole image
+    fn encode_composites(
+        &self,
+        composites: &[(Eye, TiledTexture)],
+        format: ExportFormat,
+    ) -> Result<Vec<u8>, PanoError> {
+        let (width, height) = self.stereo.packed_size(self.width, self.height);
+        let rows = |y, count| self.read_packed_rows(composites, y, count);
+
+        if self.format.is_float() {
+            // already linear and premultiplied
+            if format == ExportFormat::Exr {
+                return encode_exr_rows(width, height, |y, count| {
+                    Ok(to_float_pixels(&self.format.to_f32(&rows(y, count)?)))
+                });
+            }
+            let image = FloatImage {
+                width,
+                height,
+                pixels: to_float_pixels(&self.format.to_f32(&rows(0, height)?)),
+            };
+            return encode_float_image(&image, format);
+        }
+
+        if format == ExportFormat::Png {
+            return encode_png_rows(width, height, |y, count| {
+                let mut data = rows(y, count)?;
+                unpremultiply_rgba8(&mut data);
+                Ok(data)
+            });
+        }
+        let mut data = rows(0, height)?;
+        unpremultiply_rgba8(&mut data);
+        let data = image::RgbaImage::from_vec(width as u32, height as u32, data)
+            .ok_or_else(|| PanoError::Encode(String::from("composite size")))?;
+        encode_image(data, format)
+    }
+
+    fn embed_photo_sphere(
+        &self,
+        bytes: Vec<u8>,
+        format: ExportFormat,
+        view: &ViewState,
+    ) -> Vec<u8> {
+        // the metadata describes a single panorama
+        if self.stereo.is_stereo() {
+            return bytes;
+        }
+        let gpano = GPano::full(self.width, self.height, view);
+        gpano.embed(bytes, format)
+    }
+
+    // the canvas of `count` views turning once around the vertical axis from `rotation_y`,
+    // without the grid and the reference
+    pub fn turntable_frames(
+        &mut self,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+        display: &DisplaySettings,
+        count: usize,
+    ) -> Result<Vec<image::RgbaImage>, JsValue> {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.get_element_by_id("main_canvas").unwrap();
+        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+        let (width, height) = (canvas.width(), canvas.height());
+        let reference = ReferenceSettings {
+            visible: false,
+            ..Default::default()
+        };
+
+        let mut frames = vec![];
+        for idx in 0..count {
+            let rotation_y = rotation_y + 360.0 * idx as f32 / count as f32;
+            self.show(rotation_x, rotation_y, fov, false, &reference, display)?;
+
+            let mut data: Vec<u8> = vec![0; width as usize * height as usize * 4];
+            self.context.read_pixels_with_opt_u8_array(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                Some(data.as_mut_slice()),
+            )?;
+            // the rows of the canvas are read from the bottom
+            let mut frame = image::RgbaImage::new(width, height);
+            for (row, pixels) in data.chunks_exact(width as usize * 4).rev().enumerate() {
+                for (x, pixel) in pixels.chunks_exact(4).enumerate() {
+                    // the alpha grid is drawn under the layers
+                    frame.put_pixel(
+                        x as u32,
+                        row as u32,
+                        image::Rgba([pixel[0], pixel[1], pixel[2], 255]),
+                    );
+                }
+            }
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    pub fn start_brush(&mut self) -> Result<(), PanoError> {
+        // in the format of the layers, so that a float stroke keeps its colors above 1.0
+        let (width, height) = self.work_size();
+        let brush_texture = TiledTexture::new(width, height, self.format, [0; 4]);
+        if let Some(old) = self.brush_texture.lock().unwrap().replace(brush_texture) {
+            old.delete(&self.context);
+        }
+
+        self.brush_dist = 0.0;
+        self.stroke.clear();
+        Ok(())
+    }
+
+    pub fn draw_brush(
+        &mut self,
+        brush: &BrushPreset,
+        start_position: (f32, f32, f32),
+        end_position: (f32, f32, f32),
+    ) -> Result<(), PanoError> {
+        let x1 = (start_position.0 * start_position.0
+            + start_position.1 * start_position.1
+            + start_position.2 * start_position.2)
+            .sqrt();
+        let x1 = (
+            start_position.0 / x1,
+            start_position.1 / x1,
+            start_position.2 / x1,
+        );
+        let x2 = (end_position.0 * end_position.0
+            + end_position.1 * end_position.1
+            + end_position.2 * end_position.2)
+            .sqrt();
+        let x2 = (
+            end_position.0 / x2,
+            end_position.1 / x2,
+            end_position.2 / x2,
+        );
+        let dist = (x1.0 * x2.0 + x1.1 * x2.1 + x1.2 * x2.2).abs().acos() / brush.size;
+        let segment = (start_position, end_position, brush.size);
 
-        let work_texture = self.work_texture.lock().unwrap();
-        self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&work_texture));
-        self.context
-            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-                WebGl2RenderingContext::TEXTURE_2D,
-                0,
-                WebGl2RenderingContext::RGBA as i32,
-                WORK_TEXTURE_WIDTH as i32,
-                WORK_TEXTURE_HEIGHT as i32,
-                0,
-                WebGl2RenderingContext::RGBA,
-                WebGl2RenderingContext::UNSIGNED_BYTE,
-                Some(image.as_raw().as_slice()),
-            )?;
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
-            WebGl2RenderingContext::LINEAR as i32,
+        let program = link_program(
+            &self.context,
+            &self.brush_vert_shader,
+            &self.brush_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "target_rect".to_string(),
+                "start_position".to_string(),
+                "end_position".to_string(),
+                "point_num".to_string(),
+                "point_offset".to_string(),
+                "width".to_string(),
+                "brush_color".to_string(),
+                "hardness".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+        self.context.uniform3f(
+            Some(&uniforms["start_position"]),
+            start_position.0,
+            start_position.1,
+            start_position.2,
         );
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-            WebGl2RenderingContext::LINEAR as i32,
+        self.context.uniform3f(
+            Some(&uniforms["end_position"]),
+            end_position.0,
+            end_position.1,
+            end_position.2,
+        );
+        self.context.uniform1i(
+            Some(&uniforms["point_num"]),
+            ((self.brush_dist + dist).floor() as i32) - self.brush_dist.floor() as i32,
         );
         self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+            .uniform1f(Some(&uniforms["point_offset"]), self.brush_dist % 1.0);
+        self.context.uniform1f(Some(&uniforms["width"]), brush.size);
+        let color = self.paint_color(brush);
+        self.context.uniform4f(
+            Some(&uniforms["brush_color"]),
+            color[0],
+            color[1],
+            color[2],
+            brush.opacity,
+        );
+        self.context
+            .uniform1f(Some(&uniforms["hardness"]), brush.hardness);
+
+        self.context.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+        {
+            let mut brush_texture = self.brush_texture.lock().unwrap();
+            let brush_texture = brush_texture
+                .as_mut()
+                .ok_or_else(|| JsValue::from_str("the stroke is not started"))?;
+            // only the tiles under the segment are allocated
+            let tiles: Vec<TileIndex> = segment_tiles(brush_texture, &IDENTITY, segment)
+                .into_iter()
+                .collect();
+            let context = &self.context;
+            brush_texture.draw(context, &tiles, &uniforms["target_rect"], |_| {
+                context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 24);
+                Ok(())
+            })?;
+        }
 
+        self.brush_dist += dist;
+        self.stroke.push(segment);
         Ok(())
     }
 
-    pub fn import_exp_to_work_texture(&self, path: &Path) -> Result<(), JsValue> {
-        let image = crate::file_io::read_exr(path);
-        crate::console_log!("height {:?}", image.layer_data.channel_data.pixels.len());
-        crate::console_log!("width {:?}", image.layer_data.channel_data.pixels[0].len());
-        let mut pixels = vec![];
-        for x in 0..image.layer_data.channel_data.pixels.len() {
-            for y in 0..image.layer_data.channel_data.pixels[0].len() {
-                pixels.push(image.layer_data.channel_data.pixels[x][y][0]);
-                pixels.push(image.layer_data.channel_data.pixels[x][y][1]);
-                pixels.push(image.layer_data.channel_data.pixels[x][y][2]);
-                pixels.push(image.layer_data.channel_data.pixels[x][y][3]);
-            }
+    // tiles of the active layer under the stroke drawn since `start_brush`
+    fn stroke_tiles(&self, rotation_x: f32, rotation_y: f32) -> Vec<TileIndex> {
+        let rotation = view_rotation(rotation_x, rotation_y);
+        let texture = &self.active_layer().texture;
+        let mut tiles = BTreeSet::new();
+        for &segment in self.stroke.iter() {
+            tiles.extend(segment_tiles(texture, &rotation, segment));
         }
-        let work_texture = self.work_texture.lock().unwrap();
-        self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&work_texture));
+        tiles.into_iter().collect()
+    }
+
+    // `disparity` (degree) puts the stroke on the other eye of stereo documents too, see
+    // `Eye::other_eye_rotation`. the strokes of both eyes are undone at once.
+    pub fn stop_brush(
+        &mut self,
+        rotation_x: f32,
+        rotation_y: f32,
+        disparity: Option<f32>,
+    ) -> Result<(), PanoError> {
+        let brush_texture = self.brush_texture.lock().unwrap().take();
+        let brush_texture = match brush_texture {
+            Some(brush_texture) if !self.active_layer().locked => brush_texture,
+            _ => return Ok(()),
+        };
+        let mut entries: Vec<HistoryEntry> = self
+            .project_brush(&brush_texture, rotation_x, rotation_y)?
+            .into_iter()
+            .collect();
+        if let Some(disparity) = disparity.filter(|_| self.stereo.is_stereo()) {
+            let shown = self.eye;
+            self.set_eye(shown.other());
+            let entry = self.project_brush(
+                &brush_texture,
+                rotation_x,
+                rotation_y + shown.other_eye_rotation(disparity),
+            );
+            self.set_eye(shown);
+            entries.extend(entry?);
+        }
+        brush_texture.delete(&self.context);
+
+        match entries.len() {
+            0 => {}
+            1 => self.push_history(entries.pop().unwrap()),
+            _ => self.push_history(HistoryEntry::Batch(entries)),
+        }
+        Ok(())
+    }
+
+    // draws the stroke in the view of `rotation_x` and `rotation_y` on the active layer
+    fn project_brush(
+        &mut self,
+        brush_texture: &TiledTexture,
+        rotation_x: f32,
+        rotation_y: f32,
+    ) -> Result<Option<HistoryEntry>, JsValue> {
+        let tiles = self.stroke_tiles(rotation_x, rotation_y);
+        let before = self.begin_layer_edit(&tiles)?;
+
+        let program = link_program(
+            &self.context,
+            &self.tile_view_vert_shader,
+            &self.panorama_rotation_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "target_rect".to_string(),
+                "tex".to_string(),
+                "source_rect".to_string(),
+                "mask_tex".to_string(),
+                "rotation_x".to_string(),
+                "rotation_y".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
 
         self.context
-            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_f32_array(
-                WebGl2RenderingContext::TEXTURE_2D,
-                0,
-                WebGl2RenderingContext::RGBA32F as i32,
-                image.layer_data.channel_data.pixels[0].len() as i32,
-                image.layer_data.channel_data.pixels.len() as i32,
-                0,
-                WebGl2RenderingContext::RGBA,
-                WebGl2RenderingContext::FLOAT,
-                Some(pixels.as_slice()),
-            )?;
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
-            WebGl2RenderingContext::LINEAR as i32,
-        );
-        self.context.tex_parameteri(
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.context.bind_texture(
             WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-            WebGl2RenderingContext::LINEAR as i32,
+            Some(self.selection_texture()),
         );
+        self.context.uniform1i(Some(&uniforms["mask_tex"]), 1);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
 
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_WRAP_S,
-            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
-        );
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_WRAP_T,
-            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        self.context.enable(WebGl2RenderingContext::BLEND);
+        self.context.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
         );
+        let strokes = stroke_sources(brush_texture);
+        let context = &self.context;
+        self.layers[self.active_layer].texture.draw(
+            context,
+            &tiles,
+            &uniforms["target_rect"],
+            |_| {
+                for (tile, rect) in strokes.iter() {
+                    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(tile));
+                    context.uniform4f(
+                        Some(&uniforms["source_rect"]),
+                        rect[0],
+                        rect[1],
+                        rect[2],
+                        rect[3],
+                    );
+                    context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+                }
+                Ok(())
+            },
+        )?;
         self.context
             .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
-        Ok(())
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+
+        self.layer_edit_entry(self.active_layer, before)
     }
 
-    pub fn draw_circle(
+    // `sampling` carries a direction of the new texture, of `width` x `height`,
+    // to the direction sampled from `texture`
+    fn resample_texture(
         &self,
-        scale: f32,
-        position: (f32, f32, f32),
-        circle_color: (f32, f32, f32, f32),
-    ) -> Result<(), JsValue> {
-        let frame_buffer = self.context.create_framebuffer().unwrap();
-        self.context
-            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+        texture: &TiledTexture,
+        sampling: &Mat3,
+        width: usize,
+        height: usize,
+    ) -> Result<TiledTexture, JsValue> {
+        let mut resampled = TiledTexture::new(width, height, texture.format, texture.fill);
+        if texture.allocated_tiles().is_empty() {
+            return Ok(resampled);
+        }
+        let sources: Vec<(&WebGlTexture, [f32; 4])> = texture
+            .all_tiles()
+            .into_iter()
+            .filter_map(|index| {
+                let tile = texture.tile_or_white(index, &self.white_texture)?;
+                Some((tile, texture.tex_rect(index)))
+            })
+            .collect();
 
-        let work_texture = self.work_texture.lock().unwrap();
-        self.context.framebuffer_texture_2d(
-            WebGl2RenderingContext::FRAMEBUFFER,
-            WebGl2RenderingContext::COLOR_ATTACHMENT0,
-            WebGl2RenderingContext::TEXTURE_2D,
-            Some(&work_texture),
-            0,
+        let program = link_program(
+            &self.context,
+            &self.tile_view_vert_shader,
+            &self.rotate_panorama_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "target_rect".to_string(),
+                "tex".to_string(),
+                "source_rect".to_string(),
+                "rotation".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
+
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(&uniforms["rotation"]),
+            false,
+            &to_gl_matrix(sampling),
         );
+
+        // a plain copy, the transparent pixels must be kept
+        self.context.disable(WebGl2RenderingContext::BLEND);
+        let tiles = resampled.all_tiles();
+        resampled.draw(&self.context, &tiles, &uniforms["target_rect"], |_| {
+            // each source tile draws the pixels sampled from it
+            for (source, rect) in sources.iter() {
+                self.context
+                    .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(source));
+                self.context.uniform4f(
+                    Some(&uniforms["source_rect"]),
+                    rect[0],
+                    rect[1],
+                    rect[2],
+                    rect[3],
+                );
+                self.context
+                    .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+            }
+            Ok(())
+        })?;
+        self.context.enable(WebGl2RenderingContext::BLEND);
         self.context
-            .viewport(0, 0, WORK_TEXTURE_WIDTH as i32, WORK_TEXTURE_HEIGHT as i32);
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+        Ok(resampled)
+    }
 
+    fn resample_selection(&mut self, sampling: &Mat3) -> Result<(), JsValue> {
+        let selection = if let Some(selection) = self.selection.take() {
+            selection
+        } else {
+            return Ok(());
+        };
         let program = link_program(
             &self.context,
             &self.all_view_vert_shader,
-            &self.draw_circle_frag_shader,
+            &self.rotate_panorama_frag_shader,
         )?;
         let uniforms = get_uniform_locations(
             &self.context,
             &program,
             vec![
-                "scale".to_string(),
-                "position".to_string(),
-                "circle_color".to_string(),
+                "tex".to_string(),
+                "source_rect".to_string(),
+                "rotation".to_string(),
             ],
         )?;
         self.context.use_program(Some(&program));
 
-        self.context.uniform1f(Some(&uniforms["scale"]), scale);
-        self.context.uniform3f(
-            Some(&uniforms["position"]),
-            position.0,
-            position.1,
-            position.2,
-        );
-        self.context.uniform4f(
-            Some(&uniforms["circle_color"]),
-            circle_color.0,
-            circle_color.1,
-            circle_color.2,
-            circle_color.3,
-        );
-        self.context.blend_func(
-            WebGl2RenderingContext::SRC_ALPHA,
-            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
-        );
-        self.context
-            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
-
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
         self.context
-            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+            .uniform4f(Some(&uniforms["source_rect"]), 0.0, 0.0, 1.0, 1.0);
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(&uniforms["rotation"]),
+            false,
+            &to_gl_matrix(sampling),
+        );
 
+        self.selection = Some(self.render_selection(Some(&selection))?);
+        self.context.delete_texture(Some(&selection));
         Ok(())
     }
 
-    pub fn show(
-        &self,
-        rotation_x: f32,
-        rotation_y: f32,
-        fov: f32,
-        enable_grid: bool,
-    ) -> Result<(), JsValue> {
-        self.show_alpha_grid(rotation_x, rotation_y, fov)?;
-        self.show_texture(rotation_x, rotation_y, fov)?;
-        self.show_brush_texture(fov)?;
-        if enable_grid {
-            self.show_grid(rotation_x, rotation_y, fov)?;
+    // rotates every layer and the selection, `rotation` carries the old directions to the new ones
+    pub fn rotate_panorama(&mut self, rotation: &Mat3) -> Result<(), PanoError> {
+        let sampling = transpose(rotation);
+        let entries: Vec<HistoryEntry> = self
+            .for_each_eye(|webgl| {
+                let mut entries = vec![];
+                for idx in 0..webgl.layers.len() {
+                    let tiles = webgl.layers[idx].texture.all_tiles();
+                    let before = webgl.read_layer_tiles(idx, &tiles)?;
+                    let texture = webgl.resample_texture(
+                        &webgl.layers[idx].texture,
+                        &sampling,
+                        webgl.width,
+                        webgl.height,
+                    )?;
+                    let old_texture = std::mem::replace(&mut webgl.layers[idx].texture, texture);
+                    old_texture.delete(&webgl.context);
+                    entries.extend(webgl.layer_edit_entry(idx, before)?);
+                }
+                Ok(entries)
+            })?
+            .into_iter()
+            .flatten()
+            .collect();
+        self.resample_selection(&sampling)?;
+        if !entries.is_empty() {
+            self.push_history(HistoryEntry::Batch(entries));
         }
         Ok(())
     }
 
-    pub fn show_texture(&self, rotation_x: f32, rotation_y: f32, fov: f32) -> Result<(), JsValue> {
-        let document = web_sys::window().unwrap().document().unwrap();
-        let canvas = document.get_element_by_id("main_canvas").unwrap();
-        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+    // resamples every layer to the new size, which clears the history as its tiles do not fit anymore
+    pub fn resize_document(&mut self, width: usize, height: usize) -> Result<(), JsValue> {
+        if (width, height) == (self.width, self.height) {
+            return Ok(());
+        }
+        self.for_each_eye(|webgl| {
+            for idx in 0..webgl.layers.len() {
+                let texture =
+                    webgl.resample_texture(&webgl.layers[idx].texture, &IDENTITY, width, height)?;
+                let old_texture = std::mem::replace(&mut webgl.layers[idx].texture, texture);
+                old_texture.delete(&webgl.context);
+            }
+            Ok(())
+        })?;
+        let dropped = self.history.clear();
+        self.release_history_entries(dropped);
+        self.width = width;
+        self.height = height;
+        // to the new work size
+        self.resample_selection(&IDENTITY)
+    }
+
+    // draws a flat image on the plane given by `frame` (see `tangent_frame`) onto the active layer,
+    // `half_size` is the size of the image on the plane at distance 1
+    pub fn draw_image_on_sphere(
+        &mut self,
+        image: &image::RgbaImage,
+        frame: &Mat3,
+        half_size: (f32, f32),
+    ) -> Result<(), PanoError> {
+        if self.active_layer().locked {
+            return Ok(());
+        }
+        // the corners of the image are the farthest from its center
+        let tiles = self.active_layer().texture.tiles_around(
+            (frame[0][2], frame[1][2], frame[2][2]),
+            half_size.0.hypot(half_size.1).atan(),
+        );
+        let before = self.begin_layer_edit(&tiles)?;
+
+        let texture = create_texture(
+            &self.context,
+            self.format,
+            image.width() as usize,
+            image.height() as usize,
+            Some(&self.format.rgba8_to_pixels(image.as_raw())),
+        )?;
 
-        self.context
-            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
         let program = link_program(
             &self.context,
-            &self.drawing_canvas_vert_shader,
-            &self.show_panorama_frag_shader,
+            &self.tile_view_vert_shader,
+            &self.project_image_frag_shader,
         )?;
         let uniforms = get_uniform_locations(
             &self.context,
             &program,
             vec![
-                "fov".to_string(),
+                "target_rect".to_string(),
                 "tex".to_string(),
-                "rotation_x".to_string(),
-                "rotation_y".to_string(),
+                "mask_tex".to_string(),
+                "rotation".to_string(),
+                "half_size".to_string(),
             ],
         )?;
         self.context.use_program(Some(&program));
 
-        let work_texture = self.work_texture.lock().unwrap();
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(self.selection_texture()),
+        );
+        self.context.uniform1i(Some(&uniforms["mask_tex"]), 1);
         self.context
             .active_texture(WebGl2RenderingContext::TEXTURE0);
         self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&work_texture));
-        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
         self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(&uniforms["rotation"]),
+            false,
+            &to_gl_matrix(&transpose(frame)),
+        );
         self.context
-            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
-        self.context
-            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+            .uniform2f(Some(&uniforms["half_size"]), half_size.0, half_size.1);
 
         self.context.enable(WebGl2RenderingContext::BLEND);
         self.context.blend_func(
             WebGl2RenderingContext::SRC_ALPHA,
             WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
         );
+        self.draw_on_active_layer(&tiles, &uniforms["target_rect"])?;
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context.delete_texture(Some(&texture));
+
+        Ok(self.commit_layer_edit(before)?)
+    }
+
+    fn selection_texture(&self) -> &WebGlTexture {
+        self.selection.as_ref().unwrap_or(&self.white_texture)
+    }
+
+    pub fn clear_selection(&mut self) {
+        if let Some(selection) = self.selection.take() {
+            self.context.delete_texture(Some(&selection));
+        }
+    }
+
+    // draws the used program into a new mask, `input` is bound to TEXTURE0
+    fn render_selection(&self, input: Option<&WebGlTexture>) -> Result<WebGlTexture, JsValue> {
+        let (width, height) = self.work_size();
+        let texture = create_layer_texture(&self.context, width, height, None)?;
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, input);
+        let frame_buffer = self.context.create_framebuffer().unwrap();
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+        self.context.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        self.context.viewport(0, 0, width as i32, height as i32);
+
+        self.context.disable(WebGl2RenderingContext::BLEND);
         self.context
             .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+        self.context.enable(WebGl2RenderingContext::BLEND);
+
         self.context
             .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.context.delete_framebuffer(Some(&frame_buffer));
+
+        Ok(texture)
+    }
 
-        Ok(())
+    fn set_selection(&mut self, selection: WebGlTexture) {
+        self.clear_selection();
+        self.selection = Some(selection);
     }
 
-    pub fn show_alpha_grid(
-        &self,
+    // `polygon` is in canvas coordinates (-1.0 - 1.0) of the current view
+    pub fn select_polygon(
+        &mut self,
+        polygon: &[(f32, f32)],
         rotation_x: f32,
         rotation_y: f32,
         fov: f32,
-    ) -> Result<(), JsValue> {
-        let document = web_sys::window().unwrap().document().unwrap();
-        let canvas = document.get_element_by_id("main_canvas").unwrap();
-        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
-
-        self.context
-            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+    ) -> Result<(), PanoError> {
         let program = link_program(
             &self.context,
-            &self.drawing_canvas_vert_shader,
-            &self.alpha_grid_frag_shader,
+            &self.all_view_vert_shader,
+            &self.selection_polygon_frag_shader,
         )?;
         let uniforms = get_uniform_locations(
             &self.context,
             &program,
             vec![
-                "fov".to_string(),
                 "rotation_x".to_string(),
                 "rotation_y".to_string(),
+                "fov".to_string(),
+                "points".to_string(),
+                "point_count".to_string(),
             ],
         )?;
         self.context.use_program(Some(&program));
 
-        self.context.enable(WebGl2RenderingContext::BLEND);
-        self.context.blend_func(
-            WebGl2RenderingContext::SRC_ALPHA,
-            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
-        );
-
-        self.context.uniform1f(Some(&uniforms["fov"]), fov);
+        let points: Vec<f32> = polygon.iter().flat_map(|&(x, y)| [x, y]).collect();
         self.context
             .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
         self.context
             .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        self.context.uniform1f(Some(&uniforms["fov"]), fov);
         self.context
-            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+            .uniform2fv_with_f32_array(Some(&uniforms["points"]), &points);
+        self.context
+            .uniform1i(Some(&uniforms["point_count"]), polygon.len() as i32);
 
+        let selection = self.render_selection(None)?;
+        self.set_selection(selection);
         Ok(())
     }
 
-    pub fn show_grid(&self, rotation_x: f32, rotation_y: f32, fov: f32) -> Result<(), JsValue> {
-        let document = web_sys::window().unwrap().document().unwrap();
-        let canvas = document.get_element_by_id("main_canvas").unwrap();
-        let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+    // selects the pixels of the active layer close to the color at `tex_coords`
+    pub fn select_color(
+        &mut self,
+        tex_coords: (f32, f32),
+        tolerance: f32,
+    ) -> Result<(), PanoError> {
+        let texture = &self.active_layer().texture;
+        let target = texture.read_pixel(
+            &self.context,
+            ((tex_coords.0 * texture.width as f32) as usize).min(texture.width - 1),
+            ((tex_coords.1 * texture.height as f32) as usize).min(texture.height - 1),
+        )?;
 
-        self.context
-            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
         let program = link_program(
             &self.context,
-            &self.drawing_canvas_vert_shader,
-            &self.grid_frag_shader,
+            &self.all_view_vert_shader,
+            &self.selection_color_frag_shader,
         )?;
         let uniforms = get_uniform_locations(
             &self.context,
             &program,
             vec![
-                "fov".to_string(),
-                "rotation_x".to_string(),
-                "rotation_y".to_string(),
+                "tex".to_string(),
+                "tile_rect".to_string(),
+                "target_color".to_string(),
+                "tolerance".to_string(),
             ],
         )?;
         self.context.use_program(Some(&program));
 
-        self.context.uniform1f(Some(&uniforms["fov"]), fov);
-        self.context
-            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
-        self.context
-            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        self.context.uniform1i(Some(&uniforms["tex"]), 0);
+        self.context.uniform4f(
+            Some(&uniforms["target_color"]),
+            target[0],
+            target[1],
+            target[2],
+            target[3],
+        );
         self.context
-            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+            .uniform1f(Some(&uniforms["tolerance"]), tolerance);
 
-        Ok(())
-    }
-
-    pub fn save_png(&self, path: &Path) -> Result<(), JsValue> {
+        let (width, height) = self.work_size();
+        let selection = create_layer_texture(&self.context, width, height, None)?;
         let frame_buffer = self.context.create_framebuffer().unwrap();
         self.context
             .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
-
-        let work_texture = self.work_texture.lock().unwrap();
         self.context.framebuffer_texture_2d(
             WebGl2RenderingContext::FRAMEBUFFER,
             WebGl2RenderingContext::COLOR_ATTACHMENT0,
             WebGl2RenderingContext::TEXTURE_2D,
-            Some(&work_texture),
+            Some(&selection),
             0,
         );
+        self.context.viewport(0, 0, width as i32, height as i32);
+
+        // the unallocated tiles are of the fill color
+        let fill_selected = texture
+            .fill
+            .iter()
+            .zip(target.iter())
+            .all(|(&value, target)| (value as f32 / 255.0 - target).abs() <= tolerance);
+        let fill_mask = if fill_selected { 1.0 } else { 0.0 };
         self.context
-            .viewport(0, 0, WORK_TEXTURE_WIDTH as i32, WORK_TEXTURE_HEIGHT as i32);
+            .clear_color(fill_mask, fill_mask, fill_mask, 1.0);
+        self.context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
 
-        let mut data: Vec<u8> = vec![0; WORK_TEXTURE_WIDTH * WORK_TEXTURE_HEIGHT * 4];
-        self.context.read_pixels_with_opt_u8_array(
-            0,
-            0,
-            WORK_TEXTURE_WIDTH as i32,
-            WORK_TEXTURE_HEIGHT as i32,
-            WebGl2RenderingContext::RGBA,
-            WebGl2RenderingContext::UNSIGNED_BYTE,
-            Some(data.as_mut_slice()),
-        )?;
+        self.context.disable(WebGl2RenderingContext::BLEND);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        for index in texture.allocated_tiles() {
+            let rect = texture.tex_rect(index);
+            self.context
+                .bind_texture(WebGl2RenderingContext::TEXTURE_2D, texture.tile(index));
+            self.context.uniform4f(
+                Some(&uniforms["tile_rect"]),
+                rect[0],
+                rect[1],
+                rect[2],
+                rect[3],
+            );
+            self.context
+                .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+        }
+        self.context.enable(WebGl2RenderingContext::BLEND);
+
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
         self.context
             .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.context.delete_framebuffer(Some(&frame_buffer));
 
-        let data =
-            image::RgbaImage::from_vec(WORK_TEXTURE_WIDTH as u32, WORK_TEXTURE_HEIGHT as u32, data)
-                .unwrap();
-        write_image(path, data);
+        self.set_selection(selection);
         Ok(())
     }
 
-    pub fn start_brush(&mut self) -> Result<(), JsValue> {
-        let brush_texture = self.context.create_texture().unwrap();
-
-        self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&brush_texture));
-        self.context
-            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-                WebGl2RenderingContext::TEXTURE_2D,
-                0,
-                WebGl2RenderingContext::RGBA as i32,
-                WORK_TEXTURE_WIDTH as i32,
-                WORK_TEXTURE_HEIGHT as i32,
-                0,
-                WebGl2RenderingContext::RGBA,
-                WebGl2RenderingContext::UNSIGNED_BYTE,
-                None,
-            )
-            .unwrap();
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
-            WebGl2RenderingContext::LINEAR as i32,
-        );
-        self.context.tex_parameteri(
-            WebGl2RenderingContext::TEXTURE_2D,
-            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-            WebGl2RenderingContext::LINEAR as i32,
-        );
-        self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
-
-        *self.brush_texture.lock().unwrap() = Some(brush_texture);
+    pub fn invert_selection(&mut self) -> Result<(), JsValue> {
+        let program = link_program(
+            &self.context,
+            &self.all_view_vert_shader,
+            &self.selection_invert_frag_shader,
+        )?;
+        let uniforms =
+            get_uniform_locations(&self.context, &program, vec!["mask_tex".to_string()])?;
+        self.context.use_program(Some(&program));
+        self.context.uniform1i(Some(&uniforms["mask_tex"]), 0);
 
-        self.brush_dist = 0.0;
+        let selection = self.render_selection(Some(self.selection_texture()))?;
+        self.set_selection(selection);
         Ok(())
     }
 
-    pub fn draw_brush(
-        &mut self,
-        start_position: (f32, f32, f32),
-        end_position: (f32, f32, f32),
-    ) -> Result<(), JsValue> {
-        let x1 = (start_position.0 * start_position.0
-            + start_position.1 * start_position.1
-            + start_position.2 * start_position.2)
-            .sqrt();
-        let x1 = (
-            start_position.0 / x1,
-            start_position.1 / x1,
-            start_position.2 / x1,
-        );
-        let x2 = (end_position.0 * end_position.0
-            + end_position.1 * end_position.1
-            + end_position.2 * end_position.2)
-            .sqrt();
-        let x2 = (
-            end_position.0 / x2,
-            end_position.1 / x2,
-            end_position.2 / x2,
-        );
-        let dist = (x1.0 * x2.0 + x1.1 * x2.1 + x1.2 * x2.2).abs().acos() / 0.02;
-
-        let frame_buffer = self.context.create_framebuffer().unwrap();
-        self.context
-            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+    // the mask in r, None when nothing is selected
+    pub fn selection_pixels(&self) -> Result<Option<image::RgbaImage>, PanoError> {
+        let selection = match self.selection.as_ref() {
+            Some(selection) => selection,
+            None => return Ok(None),
+        };
+        let (width, height) = self.work_size();
+        let pixels = self.read_texture(selection)?;
+        Ok(image::RgbaImage::from_vec(
+            width as u32,
+            height as u32,
+            pixels,
+        ))
+    }
 
-        self.context.framebuffer_texture_2d(
-            WebGl2RenderingContext::FRAMEBUFFER,
-            WebGl2RenderingContext::COLOR_ATTACHMENT0,
-            WebGl2RenderingContext::TEXTURE_2D,
-            Some(self.brush_texture.lock().unwrap().as_ref().unwrap()),
-            0,
-        );
-        self.context
-            .viewport(0, 0, WORK_TEXTURE_WIDTH as i32, WORK_TEXTURE_HEIGHT as i32);
+    // fills the selection of the active layer with the color and the opacity of `brush`
+    pub fn fill_selection(&mut self, brush: &BrushPreset) -> Result<(), JsValue> {
+        if self.active_layer().locked {
+            return Ok(());
+        }
+        let tiles = self.active_layer().texture.all_tiles();
+        let before = self.begin_layer_edit(&tiles)?;
 
         let program = link_program(
             &self.context,
-            &self.brush_vert_shader,
-            &self.brush_frag_shader,
+            &self.tile_view_vert_shader,
+            &self.fill_frag_shader,
         )?;
         let uniforms = get_uniform_locations(
             &self.context,
             &program,
             vec![
-                "start_position".to_string(),
-                "end_position".to_string(),
-                "point_num".to_string(),
-                "point_offset".to_string(),
+                "target_rect".to_string(),
+                "mask_tex".to_string(),
+                "fill_color".to_string(),
             ],
         )?;
         self.context.use_program(Some(&program));
-        self.context.uniform3f(
-            Some(&uniforms["start_position"]),
-            start_position.0,
-            start_position.1,
-            start_position.2,
-        );
-        self.context.uniform3f(
-            Some(&uniforms["end_position"]),
-            end_position.0,
-            end_position.1,
-            end_position.2,
+
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(self.selection_texture()),
         );
-        self.context.uniform1i(
-            Some(&uniforms["point_num"]),
-            ((self.brush_dist + dist).floor() as i32) - self.brush_dist.floor() as i32,
+        self.context.uniform1i(Some(&uniforms["mask_tex"]), 0);
+        let color = self.paint_color(brush);
+        self.context.uniform4f(
+            Some(&uniforms["fill_color"]),
+            color[0],
+            color[1],
+            color[2],
+            brush.opacity,
         );
-        self.context
-            .uniform1f(Some(&uniforms["point_offset"]), self.brush_dist % 1.0);
 
+        self.context.enable(WebGl2RenderingContext::BLEND);
         self.context.blend_func(
             WebGl2RenderingContext::SRC_ALPHA,
             WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
         );
+        self.draw_on_active_layer(&tiles, &uniforms["target_rect"])?;
         self.context
-            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 24);
-
-        self.context
-            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
 
-        self.brush_dist += dist;
-        Ok(())
+        self.commit_layer_edit(before)
     }
-    pub fn stop_brush(&mut self, rotation_x: f32, rotation_y: f32) -> Result<(), JsValue> {
-        if let Some(brush_texture) = self.brush_texture.lock().unwrap().as_ref() {
-            let frame_buffer = self.context.create_framebuffer().unwrap();
-            self.context
-                .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
-
-            let work_texture = self.work_texture.lock().unwrap();
-            self.context.framebuffer_texture_2d(
-                WebGl2RenderingContext::FRAMEBUFFER,
-                WebGl2RenderingContext::COLOR_ATTACHMENT0,
-                WebGl2RenderingContext::TEXTURE_2D,
-                Some(&work_texture),
-                0,
-            );
-            self.context
-                .viewport(0, 0, WORK_TEXTURE_WIDTH as i32, WORK_TEXTURE_HEIGHT as i32);
 
-            let program = link_program(
-                &self.context,
-                &self.all_view_vert_shader,
-                &self.panorama_rotation_frag_shader,
-            )?;
-            let uniforms = get_uniform_locations(
-                &self.context,
-                &program,
-                vec![
-                    "tex".to_string(),
-                    "rotation_x".to_string(),
-                    "rotation_y".to_string(),
-                ],
-            )?;
-            self.context.use_program(Some(&program));
+    pub fn stop_clone_stamp(
+        &mut self,
+        rotation_x: f32,
+        rotation_y: f32,
+        clone_rotation: &Mat3,
+    ) -> Result<(), PanoError> {
+        let brush_texture = self.brush_texture.lock().unwrap().take();
+        let brush_texture = match brush_texture {
+            Some(brush_texture) if !self.active_layer().locked => brush_texture,
+            _ => return Ok(()),
+        };
+        let tiles = self.stroke_tiles(rotation_x, rotation_y);
+        let before = self.begin_layer_edit(&tiles)?;
+        // the layer texture cannot be sampled while rendering into it
+        let source = self.active_layer().texture.copy(&self.context)?;
 
-            self.context
-                .active_texture(WebGl2RenderingContext::TEXTURE0);
-            self.context
-                .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(brush_texture));
-            self.context.uniform1i(Some(&uniforms["tex"]), 0);
-            self.context
-                .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
-            self.context
-                .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        let program = link_program(
+            &self.context,
+            &self.tile_view_vert_shader,
+            &self.clone_stamp_frag_shader,
+        )?;
+        let uniforms = get_uniform_locations(
+            &self.context,
+            &program,
+            vec![
+                "target_rect".to_string(),
+                "brush_tex".to_string(),
+                "brush_rect".to_string(),
+                "source_tex".to_string(),
+                "source_rect".to_string(),
+                "mask_tex".to_string(),
+                "rotation_x".to_string(),
+                "rotation_y".to_string(),
+                "clone_rotation".to_string(),
+            ],
+        )?;
+        self.context.use_program(Some(&program));
 
-            self.context.enable(WebGl2RenderingContext::BLEND);
-            self.context.blend_func(
-                WebGl2RenderingContext::SRC_ALPHA,
-                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
-            );
+        self.context.uniform1i(Some(&uniforms["brush_tex"]), 0);
+        self.context.uniform1i(Some(&uniforms["source_tex"]), 1);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE2);
+        self.context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(self.selection_texture()),
+        );
+        self.context.uniform1i(Some(&uniforms["mask_tex"]), 2);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_x"]), rotation_x);
+        self.context
+            .uniform1f(Some(&uniforms["rotation_y"]), rotation_y);
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(&uniforms["clone_rotation"]),
+            false,
+            &to_gl_matrix(clone_rotation),
+        );
+
+        self.context.enable(WebGl2RenderingContext::BLEND);
+        self.context.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+        // the transparent tiles of the source and of the stroke add nothing
+        let sources = stroke_sources(&source);
+        let strokes = stroke_sources(&brush_texture);
+        let context = &self.context;
+        self.layers[self.active_layer].texture.draw(
+            context,
+            &tiles,
+            &uniforms["target_rect"],
+            |_| {
+                for (stroke, brush_rect) in strokes.iter() {
+                    context.active_texture(WebGl2RenderingContext::TEXTURE0);
+                    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(stroke));
+                    context.uniform4f(
+                        Some(&uniforms["brush_rect"]),
+                        brush_rect[0],
+                        brush_rect[1],
+                        brush_rect[2],
+                        brush_rect[3],
+                    );
+                    context.active_texture(WebGl2RenderingContext::TEXTURE1);
+                    for (tile, rect) in sources.iter() {
+                        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(tile));
+                        context.uniform4f(
+                            Some(&uniforms["source_rect"]),
+                            rect[0],
+                            rect[1],
+                            rect[2],
+                            rect[3],
+                        );
+                        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        for unit in (0..3).rev() {
             self.context
-                .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+                .active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
             self.context
                 .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
-
-            self.context
-                .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
         }
+        source.delete(&self.context);
+        brush_texture.delete(&self.context);
 
-        *self.brush_texture.lock().unwrap() = None;
-        Ok(())
+        Ok(self.commit_layer_edit(before)?)
     }
 
-    pub fn show_brush_texture(&self, fov: f32) -> Result<(), JsValue> {
+    pub fn show_brush_texture(&self, fov: f32, display: &DisplaySettings) -> Result<(), JsValue> {
         if let Some(brush_texture) = self.brush_texture.lock().unwrap().as_ref() {
             let document = web_sys::window().unwrap().document().unwrap();
             let canvas = document.get_element_by_id("main_canvas").unwrap();
@@ -1192,28 +5327,40 @@ impl ModelWebGL {
                 vec![
                     "fov".to_string(),
                     "tex".to_string(),
+                    "tile_rect".to_string(),
                     "rotation_x".to_string(),
                     "rotation_y".to_string(),
+                    "opacity".to_string(),
+                    "exposure".to_string(),
+                    "tone_mapping".to_string(),
+                    "gamma".to_string(),
                 ],
             )?;
             self.context.use_program(Some(&program));
 
             self.context
                 .active_texture(WebGl2RenderingContext::TEXTURE0);
-            self.context
-                .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(brush_texture));
             self.context.uniform1f(Some(&uniforms["fov"]), fov);
             self.context.uniform1i(Some(&uniforms["tex"]), 0);
             self.context.uniform1f(Some(&uniforms["rotation_x"]), 0.0);
             self.context.uniform1f(Some(&uniforms["rotation_y"]), 0.0);
+            self.context.uniform1f(Some(&uniforms["opacity"]), 1.0);
+            self.set_display_uniforms(&uniforms, display);
 
-            self.context.enable(WebGl2RenderingContext::BLEND);
-            self.context.blend_func(
-                WebGl2RenderingContext::SRC_ALPHA,
-                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
-            );
-            self.context
-                .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+            BlendMode::Normal.apply(&self.context);
+            for (tile, rect) in stroke_sources(brush_texture) {
+                self.context
+                    .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(tile));
+                self.context.uniform4f(
+                    Some(&uniforms["tile_rect"]),
+                    rect[0],
+                    rect[1],
+                    rect[2],
+                    rect[3],
+                );
+                self.context
+                    .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+            }
             self.context
                 .bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
         }
@@ -1221,6 +5368,108 @@ impl ModelWebGL {
     }
 }
 
+// the path chosen in a file dialog, none when it is canceled or fails
+async fn chosen_path(
+    dialog: Result<JsValue, JsValue>,
+    link: &ComponentLink<Model>,
+) -> Option<String> {
+    let path = match dialog {
+        Ok(promise) => wasm_bindgen_futures::JsFuture::from(js_sys::Promise::from(promise)).await,
+        Err(err) => Err(err),
+    };
+    match path {
+        Ok(path) => path.as_string(),
+        Err(err) => {
+            link.send_message(Msg::ShowError {
+                message: PanoError::Io(format!("{:?}", err)).to_string(),
+            });
+            None
+        }
+    }
+}
+
+// the images numbered from `first` in one animation at `path`
+async fn export_sequence_animation(
+    first: &Path,
+    path: &Path,
+    settings: &AnimationSettings,
+) -> Result<(), PanoError> {
+    let format = AnimationFormat::from_path(path)?;
+    let mut frames = vec![];
+    for frame in frame_sequence(first).await {
+        frames.push(read_image(&frame).await?);
+    }
+    let bytes = encode_animation(frames, format, settings)?;
+    write_binary(path, bytes).await
+}
+
+// tiles of `texture` under a segment (start, end, width) of `draw_brush`, whose positions are
+// in the canvas space, seen through `rotation`
+fn segment_tiles(
+    texture: &TiledTexture,
+    rotation: &Mat3,
+    segment: (Vec3, Vec3, f32),
+) -> BTreeSet<TileIndex> {
+    let (start, end, width) = segment;
+    // the canvas space has y up
+    let start = normalize(mul_vec(rotation, (start.0, -start.1, start.2)));
+    let end = normalize(mul_vec(rotation, (end.0, -end.1, end.2)));
+    let angle = dot(start, end).clamp(-1.0, 1.0).acos();
+    let steps = (angle / width).ceil().max(1.0) as usize;
+    let mut tiles = BTreeSet::new();
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let point = (
+            start.0 + (end.0 - start.0) * t,
+            start.1 + (end.1 - start.1) * t,
+            start.2 + (end.2 - start.2) * t,
+        );
+        // the brush spreads `width` / 2 around the points, which are `width` apart at most
+        tiles.extend(texture.tiles_around(point, width * 2.0));
+    }
+    tiles
+}
+
+// the allocated tiles of `texture` with their parts of the panorama
+fn stroke_sources(texture: &TiledTexture) -> Vec<(&WebGlTexture, [f32; 4])> {
+    texture
+        .allocated_tiles()
+        .into_iter()
+        .filter_map(|index| Some((texture.tile(index)?, texture.tex_rect(index))))
+        .collect()
+}
+
+fn to_float_pixels(values: &[f32]) -> Vec<[f32; 4]> {
+    values
+        .chunks_exact(4)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+        .collect()
+}
+
+// the composite is premultiplied, the exported images are not
+fn unpremultiply_rgba8(data: &mut [u8]) {
+    for pixel in data.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in pixel.iter_mut().take(3) {
+            *channel = (*channel as u32 * 255)
+                .checked_div(alpha)
+                .map_or(0, |value| value.min(255)) as u8;
+        }
+    }
+}
+
+// the shortcuts leave the keys typed into the form fields, e.g. the text of the text tool
+fn is_form_field(target: Option<web_sys::EventTarget>) -> bool {
+    target
+        .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+        .is_some_and(|element| {
+            matches!(
+                element.tag_name().to_lowercase().as_str(),
+                "input" | "textarea" | "select"
+            )
+        })
+}
+
 fn request_animation_frame(f: &Closure<dyn FnMut()>) {
     web_sys::window()
         .unwrap()
@@ -0,0 +1,54 @@
+use yew::prelude::*;
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct Props {
+    pub open: bool,
+    pub on_restore: Callback<()>,
+    pub on_discard: Callback<()>,
+}
+
+pub struct RecoveryDialog {
+    props: Props,
+}
+
+impl Component for RecoveryDialog {
+    type Message = ();
+    type Properties = Props;
+
+    fn create(props: Self::Properties, _link: ComponentLink<Self>) -> Self {
+        Self { props }
+    }
+
+    fn update(&mut self, _msg: Self::Message) -> ShouldRender {
+        false
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            self.props = props;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let on_restore = self.props.on_restore.clone();
+        let on_discard = self.props.on_discard.clone();
+        html! {
+            <div id="centerpoint">
+                <dialog
+                    id="recovery dialog"
+                    open=self.props.open
+                >
+                    {"前回のセッションは正常に終了しませんでした。"}
+                    <br />
+                    {"自動保存されたデータを復元しますか？"}
+                    <br />
+                    <button onclick=Callback::from(move |_| on_restore.emit(()))>{ "復元" }</button>
+                    <button onclick=Callback::from(move |_| on_discard.emit(()))>{ "破棄" }</button>
+                </dialog>
+            </div>
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use web_sys::WebGlTexture;
+
+use crate::sphere::{mul, transpose, view_rotation, Mat3, IDENTITY};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ReferencePlacement {
+    // fixed on the canvas, follows the view
+    Screen,
+    // pinned to a region of the sphere
+    Sphere,
+}
+
+#[derive(PartialEq, Clone)]
+pub struct ReferenceSettings {
+    pub visible: bool,
+    pub opacity: f32,
+    pub placement: ReferencePlacement,
+    // center on the canvas (-1.0 - 1.0, y is down) and half width relative to the canvas
+    pub screen_x: f32,
+    pub screen_y: f32,
+    pub screen_size: f32,
+    // center on the sphere as a view rotation (degree) and angular width (degree)
+    pub rotation_x: f32,
+    pub rotation_y: f32,
+    pub angular_size: f32,
+}
+
+impl Default for ReferenceSettings {
+    fn default() -> Self {
+        ReferenceSettings {
+            visible: true,
+            opacity: 0.5,
+            placement: ReferencePlacement::Screen,
+            screen_x: 0.6,
+            screen_y: -0.6,
+            screen_size: 0.3,
+            rotation_x: 0.0,
+            rotation_y: 0.0,
+            angular_size: 60.0,
+        }
+    }
+}
+
+// only shown on the canvas, never exported
+pub struct Reference {
+    pub texture: WebGlTexture,
+    // width / height of the image
+    pub aspect: f32,
+}
+
+impl ReferenceSettings {
+    // uniforms of reference.frag: rotation from the view space to the image plane,
+    // center and half size of the image on the plane
+    pub fn plane(
+        &self,
+        aspect: f32,
+        rotation_x: f32,
+        rotation_y: f32,
+        fov: f32,
+    ) -> (Mat3, (f32, f32), (f32, f32)) {
+        match self.placement {
+            ReferencePlacement::Screen => {
+                let factor = (fov / 2.0 / 180.0 * std::f32::consts::PI).tan();
+                let half_width = self.screen_size * factor;
+                (
+                    IDENTITY,
+                    (self.screen_x * factor, self.screen_y * factor),
+                    (half_width, half_width / aspect),
+                )
+            }
+            ReferencePlacement::Sphere => {
+                let frame = view_rotation(self.rotation_x, self.rotation_y);
+                let half_width = (self.angular_size / 2.0 / 180.0 * std::f32::consts::PI).tan();
+                (
+                    mul(&transpose(&frame), &view_rotation(rotation_x, rotation_y)),
+                    (0.0, 0.0),
+                    (half_width, half_width / aspect),
+                )
+            }
+        }
+    }
+}
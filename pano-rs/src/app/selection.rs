@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+// must match MAX_POINTS of selection_polygon.frag
+pub const MAX_SELECTION_POINTS: usize = 256;
+pub const DEFAULT_SELECTION_TOLERANCE: f32 = 0.1;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SelectionShape {
+    Rectangle,
+    Lasso,
+}
+
+// the selection mask read back from the texture (the mask is in r), in the work size of the
+// document. compared by pointer so that it can be passed as a property cheaply
+#[derive(Clone)]
+pub struct SelectionMask(pub Arc<image::RgbaImage>);
+
+impl PartialEq for SelectionMask {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// polygon of the selection in canvas coordinates (-1.0 - 1.0) from the points dragged with the mouse
+pub fn selection_polygon(
+    shape: SelectionShape,
+    points: &[(f32, f32)],
+    canvas_size: f32,
+) -> Vec<(f32, f32)> {
+    let points: Vec<(f32, f32)> = points
+        .iter()
+        .map(|(x, y)| (2.0 * x / canvas_size - 1.0, 2.0 * y / canvas_size - 1.0))
+        .collect();
+    match shape {
+        SelectionShape::Rectangle => {
+            if let (Some(&(x0, y0)), Some(&(x1, y1))) = (points.first(), points.last()) {
+                vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+            } else {
+                vec![]
+            }
+        }
+        SelectionShape::Lasso => {
+            // thin out long lassos to fit in the uniform array
+            let step = points.len().div_ceil(MAX_SELECTION_POINTS);
+            points.into_iter().step_by(step.max(1)).collect()
+        }
+    }
+}
+
+// a polygon without area (e.g. a click without dragging) clears the selection
+pub fn polygon_area(polygon: &[(f32, f32)]) -> f32 {
+    let mut area = 0.0;
+    for (idx, &(x0, y0)) in polygon.iter().enumerate() {
+        let (x1, y1) = polygon[(idx + 1) % polygon.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    area.abs() / 2.0
+}
+
+// keeps `before` outside of the selection (all RGBA8 of the same size)
+pub fn apply_mask(before: &[u8], after: &mut [u8], mask: &[u8]) {
+    for ((before, after), mask) in before
+        .chunks(4)
+        .zip(after.chunks_mut(4))
+        .zip(mask.chunks(4))
+    {
+        let m = mask[0] as u32;
+        for (before, after) in before.iter().zip(after.iter_mut()) {
+            *after = ((*after as u32 * m + *before as u32 * (255 - m) + 127) / 255) as u8;
+        }
+    }
+}
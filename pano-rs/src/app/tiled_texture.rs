@@ -0,0 +1,507 @@
+use std::collections::{BTreeSet, HashMap};
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{WebGlTexture, WebGlUniformLocation};
+
+use super::layer::create_texture;
+use crate::pixel_format::PixelFormat;
+use crate::sphere::{direction_to_tex_coords, mul_vec, normalize, view_rotation, Vec3};
+use crate::WebGl2RenderingContext;
+
+// every WebGL2 implementation supports textures of this size
+pub const TILE_SIZE: usize = 2048;
+
+// (column, row) in the grid of tiles
+pub type TileIndex = (usize, usize);
+
+// pixels of some tiles in the format of the texture, e.g. before an edit
+pub type TilePixels = Vec<(TileIndex, Vec<u8>)>;
+
+// equirectangular image split into tiles of `TILE_SIZE`.
+// a tile is allocated when it is written first, the others read as `fill`.
+pub struct TiledTexture {
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    // 0 or 255 for each channel, i.e. 0.0 or 1.0 in the float format
+    pub fill: [u8; 4],
+    tiles: HashMap<TileIndex, WebGlTexture>,
+}
+
+impl TiledTexture {
+    pub fn new(width: usize, height: usize, format: PixelFormat, fill: [u8; 4]) -> Self {
+        TiledTexture {
+            width,
+            height,
+            format,
+            fill,
+            tiles: HashMap::new(),
+        }
+    }
+
+    // `pixels` are in `format`, tiles equal to `fill` are not allocated
+    pub fn from_pixels(
+        context: &WebGl2RenderingContext,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        fill: [u8; 4],
+        pixels: &[u8],
+    ) -> Result<Self, JsValue> {
+        let pixel_size = format.pixel_size();
+        Self::from_tiles(context, width, height, format, fill, |rect| {
+            let (x, y, tile_width, tile_height) = rect;
+            Ok(Some(crop(
+                pixels,
+                pixel_size,
+                width,
+                x,
+                y,
+                tile_width,
+                tile_height,
+            )))
+        })
+    }
+
+    // built one tile at a time, so that no buffer of the whole image is needed.
+    // `tile_pixels` returns the pixels of the tile at (x, y, width, height) in `format`,
+    // None or pixels equal to `fill` leave the tile unallocated.
+    pub fn from_tiles(
+        context: &WebGl2RenderingContext,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        fill: [u8; 4],
+        mut tile_pixels: impl FnMut((usize, usize, usize, usize)) -> Result<Option<Vec<u8>>, JsValue>,
+    ) -> Result<Self, JsValue> {
+        let mut texture = TiledTexture::new(width, height, format, fill);
+        let pixel_size = format.pixel_size();
+        let fill_pixel = format.fill_pixels(fill, 1);
+        for index in texture.all_tiles() {
+            let pixels = match tile_pixels(texture.rect(index))? {
+                Some(pixels) => pixels,
+                None => continue,
+            };
+            if !pixels
+                .chunks_exact(pixel_size)
+                .all(|pixel| pixel == fill_pixel)
+            {
+                texture.write_tile(context, index, &pixels)?;
+            }
+        }
+        Ok(texture)
+    }
+
+    pub fn columns(&self) -> usize {
+        self.width.div_ceil(TILE_SIZE)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.height.div_ceil(TILE_SIZE)
+    }
+
+    pub fn all_tiles(&self) -> Vec<TileIndex> {
+        let mut tiles = vec![];
+        for row in 0..self.rows() {
+            for column in 0..self.columns() {
+                tiles.push((column, row));
+            }
+        }
+        tiles
+    }
+
+    pub fn allocated_tiles(&self) -> Vec<TileIndex> {
+        let mut tiles: Vec<TileIndex> = self.tiles.keys().copied().collect();
+        tiles.sort_unstable();
+        tiles
+    }
+
+    pub fn tile(&self, index: TileIndex) -> Option<&WebGlTexture> {
+        self.tiles.get(&index)
+    }
+
+    // the tile or `white` for an unallocated tile of a white texture, e.g. the mask of an adjustment,
+    // None when the tile is transparent
+    pub fn tile_or_white<'a>(
+        &'a self,
+        index: TileIndex,
+        white: &'a WebGlTexture,
+    ) -> Option<&'a WebGlTexture> {
+        match self.tiles.get(&index) {
+            Some(texture) => Some(texture),
+            None if self.fill == [255; 4] => Some(white),
+            None => None,
+        }
+    }
+
+    // (x, y, width, height) in pixels
+    pub fn rect(&self, index: TileIndex) -> (usize, usize, usize, usize) {
+        let x = index.0 * TILE_SIZE;
+        let y = index.1 * TILE_SIZE;
+        (
+            x,
+            y,
+            TILE_SIZE.min(self.width - x),
+            TILE_SIZE.min(self.height - y),
+        )
+    }
+
+    // (x0, y0, x1, y1) in the texture coordinates of the whole image
+    pub fn tex_rect(&self, index: TileIndex) -> [f32; 4] {
+        let (x, y, width, height) = self.rect(index);
+        [
+            x as f32 / self.width as f32,
+            y as f32 / self.height as f32,
+            (x + width) as f32 / self.width as f32,
+            (y + height) as f32 / self.height as f32,
+        ]
+    }
+
+    // the same as `tex_rect` in `fragment_position` of all_view.vert (-1.0 - 1.0)
+    pub fn position_rect(&self, index: TileIndex) -> [f32; 4] {
+        let rect = self.tex_rect(index);
+        [
+            rect[0] * 2.0 - 1.0,
+            rect[1] * 2.0 - 1.0,
+            rect[2] * 2.0 - 1.0,
+            rect[3] * 2.0 - 1.0,
+        ]
+    }
+
+    pub fn allocate(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        index: TileIndex,
+    ) -> Result<&WebGlTexture, JsValue> {
+        if !self.tiles.contains_key(&index) {
+            // tiles are allocated while drawing, the texture bound by the caller is kept
+            let bound = context.get_parameter(WebGl2RenderingContext::TEXTURE_BINDING_2D)?;
+            let (_, _, width, height) = self.rect(index);
+            let pixels = self.format.fill_pixels(self.fill, width * height);
+            let texture = create_tile_texture(context, self.format, width, height, Some(&pixels))?;
+            context.bind_texture(
+                WebGl2RenderingContext::TEXTURE_2D,
+                bound.dyn_ref::<WebGlTexture>(),
+            );
+            self.tiles.insert(index, texture);
+        }
+        Ok(&self.tiles[&index])
+    }
+
+    pub fn read_tile(
+        &self,
+        context: &WebGl2RenderingContext,
+        index: TileIndex,
+    ) -> Result<Vec<u8>, JsValue> {
+        let (_, _, width, height) = self.rect(index);
+        match self.tiles.get(&index) {
+            Some(texture) => read_pixels(context, self.format, texture, 0, 0, width, height),
+            None => Ok(self.format.fill_pixels(self.fill, width * height)),
+        }
+    }
+
+    pub fn write_tile(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        index: TileIndex,
+        pixels: &[u8],
+    ) -> Result<(), JsValue> {
+        let (_, _, width, height) = self.rect(index);
+        self.write_rect(context, index, (0, 0, width, height), pixels)
+    }
+
+    // `rect` is (x, y, width, height) in the tile
+    pub fn write_rect(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        index: TileIndex,
+        rect: (usize, usize, usize, usize),
+        pixels: &[u8],
+    ) -> Result<(), JsValue> {
+        let (x, y, width, height) = rect;
+        let format = self.format;
+        let texture = self.allocate(context, index)?;
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(texture));
+        context.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_array_buffer_view(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            x as i32,
+            y as i32,
+            width as i32,
+            height as i32,
+            WebGl2RenderingContext::RGBA,
+            format.data_type(),
+            Some(&format.to_array(pixels)),
+        )?;
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        Ok(())
+    }
+
+    // `count` rows of the whole width from `y` in `format`, e.g. to encode the image in bands
+    // instead of reading all of it at once
+    pub fn read_rows(
+        &self,
+        context: &WebGl2RenderingContext,
+        y: usize,
+        count: usize,
+    ) -> Result<Vec<u8>, JsValue> {
+        let pixel_size = self.format.pixel_size();
+        let row_size = self.width * pixel_size;
+        let mut pixels = self.format.fill_pixels(self.fill, self.width * count);
+        for row in y / TILE_SIZE..=(y + count - 1) / TILE_SIZE {
+            for column in 0..self.columns() {
+                let texture = match self.tiles.get(&(column, row)) {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+                let (tile_x, tile_y, width, height) = self.rect((column, row));
+                // the rows of the band in the tile
+                let start = y.max(tile_y);
+                let end = (y + count).min(tile_y + height);
+                let tile_pixels = read_pixels(
+                    context,
+                    self.format,
+                    texture,
+                    0,
+                    start - tile_y,
+                    width,
+                    end - start,
+                )?;
+                let tile_row_size = width * pixel_size;
+                for (row, tile_row) in tile_pixels.chunks_exact(tile_row_size).enumerate() {
+                    let offset = (start - y + row) * row_size + tile_x * pixel_size;
+                    pixels[offset..offset + tile_row_size].copy_from_slice(tile_row);
+                }
+            }
+        }
+        Ok(pixels)
+    }
+
+    // the channels as the shaders see them
+    pub fn read_pixel(
+        &self,
+        context: &WebGl2RenderingContext,
+        x: usize,
+        y: usize,
+    ) -> Result<[f32; 4], JsValue> {
+        let index = (x / TILE_SIZE, y / TILE_SIZE);
+        let pixel = match self.tiles.get(&index) {
+            Some(texture) => read_pixels(
+                context,
+                self.format,
+                texture,
+                x % TILE_SIZE,
+                y % TILE_SIZE,
+                1,
+                1,
+            )?,
+            None => self.format.fill_pixels(self.fill, 1),
+        };
+        let pixel = self.format.to_f32(&pixel);
+        Ok([pixel[0], pixel[1], pixel[2], pixel[3]])
+    }
+
+    // copies the allocated tiles
+    pub fn copy(&self, context: &WebGl2RenderingContext) -> Result<Self, JsValue> {
+        let mut copied = TiledTexture::new(self.width, self.height, self.format, self.fill);
+        let frame_buffer = context.create_framebuffer().unwrap();
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+        for (&index, texture) in self.tiles.iter() {
+            let (_, _, width, height) = self.rect(index);
+            context.framebuffer_texture_2d(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0,
+                WebGl2RenderingContext::TEXTURE_2D,
+                Some(texture),
+                0,
+            );
+            let tile = create_tile_texture(context, self.format, width, height, None)?;
+            context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&tile));
+            context.copy_tex_sub_image_2d(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                0,
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+            );
+            context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+            copied.tiles.insert(index, tile);
+        }
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        context.delete_framebuffer(Some(&frame_buffer));
+        Ok(copied)
+    }
+
+    // renders into each of `tiles` with the used program, whose vertex shader is tile_view.vert.
+    // `draw` issues the draw calls once the tile is bound to the framebuffer.
+    pub fn draw(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        tiles: &[TileIndex],
+        target_rect: &WebGlUniformLocation,
+        mut draw: impl FnMut(TileIndex) -> Result<(), JsValue>,
+    ) -> Result<(), JsValue> {
+        let frame_buffer = context.create_framebuffer().unwrap();
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+        for &index in tiles {
+            let (_, _, width, height) = self.rect(index);
+            let rect = self.position_rect(index);
+            context.framebuffer_texture_2d(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0,
+                WebGl2RenderingContext::TEXTURE_2D,
+                Some(self.allocate(context, index)?),
+                0,
+            );
+            context.viewport(0, 0, width as i32, height as i32);
+            context.uniform4f(Some(target_rect), rect[0], rect[1], rect[2], rect[3]);
+            draw(index)?;
+        }
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        context.delete_framebuffer(Some(&frame_buffer));
+        Ok(())
+    }
+
+    // tiles which may contain the directions within `radius` (radian) from `direction`
+    pub fn tiles_around(&self, direction: Vec3, radius: f32) -> Vec<TileIndex> {
+        use std::f32::consts::{FRAC_PI_2, PI};
+
+        let direction = normalize(direction);
+        let (u, _) = direction_to_tex_coords(direction);
+        let elevation = direction.1.clamp(-1.0, 1.0).asin();
+        // a margin of a few pixels for the texture filtering
+        let margin = 2.0 / self.height as f32;
+
+        let v0 = (((elevation - radius) / FRAC_PI_2 + 1.0) / 2.0 - margin).max(0.0);
+        let v1 = (((elevation + radius) / FRAC_PI_2 + 1.0) / 2.0 + margin).min(1.0);
+        let half_width = if elevation.abs() + radius >= FRAC_PI_2 {
+            // contains a pole
+            0.5
+        } else {
+            (radius.sin() / elevation.cos()).min(1.0).asin() / PI / 2.0 + margin
+        };
+
+        let rows = (v0 * self.height as f32) as usize / TILE_SIZE
+            ..=((v1 * self.height as f32) as usize).min(self.height - 1) / TILE_SIZE;
+        let mut columns = BTreeSet::new();
+        if half_width >= 0.5 {
+            columns.extend(0..self.columns());
+        } else {
+            // the range may wrap around the seam
+            for (start, end) in [
+                (u - half_width, u + half_width),
+                (u - half_width + 1.0, u + half_width + 1.0),
+                (u - half_width - 1.0, u + half_width - 1.0),
+            ] {
+                let start = start.max(0.0);
+                let end = end.min(1.0);
+                if start < end {
+                    let first = (start * self.width as f32) as usize / TILE_SIZE;
+                    let last = ((end * self.width as f32) as usize).min(self.width - 1) / TILE_SIZE;
+                    columns.extend(first..=last);
+                }
+            }
+        }
+
+        let mut tiles = vec![];
+        for row in rows {
+            for &column in columns.iter() {
+                tiles.push((column, row));
+            }
+        }
+        tiles
+    }
+
+    // tiles which may appear in the view of drawing_canvas.vert
+    pub fn tiles_in_view(&self, rotation_x: f32, rotation_y: f32, fov: f32) -> Vec<TileIndex> {
+        let center = mul_vec(&view_rotation(rotation_x, rotation_y), (0.0, 0.0, 1.0));
+        // to the corners of the canvas
+        let radius = ((fov / 2.0).to_radians().tan() * std::f32::consts::SQRT_2).atan();
+        self.tiles_around(center, radius)
+    }
+
+    pub fn delete(&self, context: &WebGl2RenderingContext) {
+        for texture in self.tiles.values() {
+            context.delete_texture(Some(texture));
+        }
+    }
+}
+
+// tiles are clamped at their edges, so that they do not bleed into each other
+fn create_tile_texture(
+    context: &WebGl2RenderingContext,
+    format: PixelFormat,
+    width: usize,
+    height: usize,
+    pixels: Option<&[u8]>,
+) -> Result<WebGlTexture, JsValue> {
+    let texture = create_texture(context, format, width, height, pixels)?;
+    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+    context.tex_parameteri(
+        WebGl2RenderingContext::TEXTURE_2D,
+        WebGl2RenderingContext::TEXTURE_WRAP_S,
+        WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+    );
+    context.tex_parameteri(
+        WebGl2RenderingContext::TEXTURE_2D,
+        WebGl2RenderingContext::TEXTURE_WRAP_T,
+        WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+    );
+    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+    Ok(texture)
+}
+
+fn read_pixels(
+    context: &WebGl2RenderingContext,
+    format: PixelFormat,
+    texture: &WebGlTexture,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, JsValue> {
+    let frame_buffer = context.create_framebuffer().unwrap();
+    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&frame_buffer));
+    context.framebuffer_texture_2d(
+        WebGl2RenderingContext::FRAMEBUFFER,
+        WebGl2RenderingContext::COLOR_ATTACHMENT0,
+        WebGl2RenderingContext::TEXTURE_2D,
+        Some(texture),
+        0,
+    );
+    let array = format.new_array(width * height);
+    context.read_pixels_with_opt_array_buffer_view(
+        x as i32,
+        y as i32,
+        width as i32,
+        height as i32,
+        WebGl2RenderingContext::RGBA,
+        format.data_type(),
+        Some(&array),
+    )?;
+    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+    context.delete_framebuffer(Some(&frame_buffer));
+    Ok(format.array_to_pixels(&array))
+}
+
+// a rectangle of an image of `image_width`, whose pixels are `pixel_size` bytes
+fn crop(
+    pixels: &[u8],
+    pixel_size: usize,
+    image_width: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let mut cropped = Vec::with_capacity(width * height * pixel_size);
+    for row in y..y + height {
+        let start = (row * image_width + x) * pixel_size;
+        cropped.extend_from_slice(&pixels[start..start + width * pixel_size]);
+    }
+    cropped
+}
@@ -0,0 +1,421 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use futures::executor::block_on;
+use image::RgbaImage;
+use pano::cpu::{
+    cubes_to_equirectangular, equirectangular_to_cubes, reproject, resize, rotate, transfer,
+};
+use pano::cube_face_convention::{
+    read_cube_faces, write_cube_faces, CubeFaceConvention, CubeFacePreset, CUBE_FACE_NAMES,
+};
+use pano::error::PanoError;
+use pano::file_io::{ExportFormat, DEFAULT_QUALITY};
+use pano::sphere::yaw_pitch_roll;
+
+// batch conversions of panoramas on the CPU, e.g.
+//   pano-cli cubes2equi cubes/ equirectangular.png --width 4096
+//   pano-cli rotate frames/ rotated/ --yaw 90
+// a directory as the input converts every image (or every cube face folder) in it
+// into the output directory. the files are read and written with the native storage of file_io.
+
+const USAGE: &str = "usage: pano-cli <command> <input> <output> [options]
+
+commands:
+  cubes2equi  cube faces (front.png, back.png, left.png, right.png, top.png, bottom.png by default)
+              in a folder to an equirectangular image
+              --width <px> (3840) --height <px> (width / 2)
+  equi2cubes  equirectangular image to a folder of cube faces
+              --size <px> (width / 4)
+  rotate      rotate the panorama
+              --yaw <degree> (0) --pitch <degree> (0) --roll <degree> (0)
+  reproject   flat image of a view of the panorama
+              --rotation-x <degree> (0) --rotation-y <degree> (0) --fov <degree> (60) --size <px> (1024)
+  transfer    image transfer of the app, dots scattered on the panorama
+  resize      resample the panorama
+              --width <px> --height <px> (width / 2)
+
+options:
+  --quality <1-100> (90) quality of jpeg outputs
+
+cube face options of cubes2equi and equi2cubes:
+  --faces <pano|px|posx|blender|unity|threejs|unreal> (pano) naming and orientation of the faces
+  --rotate <face>:<degree> the file of the face is turned clockwise, e.g. top:90
+  --flip <face>            the file of the face is flipped horizontally
+  <face> is front, back, left, right, top or bottom. --rotate and --flip can be repeated,
+  they apply to the files of --faces, every --rotate before --flip";
+
+struct Options {
+    values: Vec<(String, String)>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut values = vec![];
+        let mut args = args.iter();
+        while let Some(name) = args.next() {
+            let name = name
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument: {}", name))?;
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value of --{}", name))?;
+            values.push((name.to_string(), value.clone()));
+        }
+        Ok(Options { values })
+    }
+
+    fn get<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.values.iter().rev().find(|(key, _)| key == name) {
+            Some((_, value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value of --{}: {}", name, value)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_or<T: std::str::FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        Ok(self.get(name)?.unwrap_or(default))
+    }
+
+    // the values of an option which can be repeated
+    fn get_all(&self, name: &str) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn cube_face_convention(&self) -> Result<CubeFaceConvention, String> {
+        let preset = match self.get::<String>("faces")? {
+            Some(key) => CubeFacePreset::from_key(&key)
+                .ok_or_else(|| format!("unknown cube faces: {}", key))?,
+            None => CubeFacePreset::Pano,
+        };
+        let mut convention = CubeFaceConvention::preset(preset);
+        let face_index = |name: &str| {
+            CUBE_FACE_NAMES
+                .iter()
+                .position(|face| *face == name)
+                .ok_or_else(|| format!("unknown cube face: {}", name))
+        };
+        for value in self.get_all("rotate") {
+            let invalid = || format!("invalid value of --rotate: {}", value);
+            let (face, degree) = value.split_once(':').ok_or_else(invalid)?;
+            let degree = degree.parse::<u32>().map_err(|_| invalid())?;
+            if degree % 90 != 0 {
+                return Err(invalid());
+            }
+            // the file of the preset is turned further
+            let orientation = &mut convention.orientations[face_index(face)?];
+            let turns = (degree / 90 % 4) as u8;
+            // turning a flipped file clockwise turns the face counterclockwise
+            let turns = if orientation.flip { 4 - turns } else { turns };
+            orientation.rotation = (orientation.rotation + turns) % 4;
+        }
+        for face in self.get_all("flip") {
+            let orientation = &mut convention.orientations[face_index(face)?];
+            orientation.flip = !orientation.flip;
+        }
+        Ok(convention)
+    }
+}
+
+fn read_image(path: &Path) -> Result<RgbaImage, PanoError> {
+    block_on(pano::file_io::read_image(path))
+}
+
+fn write_image(path: &Path, image: RgbaImage, quality: u8) -> Result<(), PanoError> {
+    let format = ExportFormat::from_path(path, quality)?;
+    // webp is encoded by the browser
+    if let ExportFormat::WebP { .. } = format {
+        return Err(PanoError::UnsupportedFormat(String::from("webp")));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| PanoError::Io(format!("{}: {}", parent.display(), e)))?;
+    }
+    block_on(pano::file_io::write_image(path, image, format))
+}
+
+fn is_image(path: &Path) -> bool {
+    path.is_file() && ExportFormat::from_path(path, DEFAULT_QUALITY).is_ok()
+}
+
+fn has_cube_faces(path: &Path, convention: &CubeFaceConvention) -> bool {
+    (0..6).all(|face| path.join(convention.file_name(face)).is_file())
+}
+
+// entries of `dir` which satisfy `filter`, sorted by name
+fn entries(dir: &Path, filter: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, PanoError> {
+    let mut paths = fs::read_dir(dir)
+        .map_err(|e| PanoError::Io(format!("{}: {}", dir.display(), e)))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| filter(path))
+        .collect::<Vec<_>>();
+    paths.sort();
+    Ok(paths)
+}
+
+fn cubes2equi(input: &Path, output: &Path, options: &Options) -> Result<(), String> {
+    let width = options.get_or("width", 3840)?;
+    let height = options.get_or("height", width / 2)?;
+    let quality = options.get_or("quality", DEFAULT_QUALITY)?;
+    let convention = options.cube_face_convention()?;
+    let convert = |input: &Path, output: &Path| -> Result<(), PanoError> {
+        let cubes = block_on(read_cube_faces(input, &convention))?;
+        write_image(
+            output,
+            cubes_to_equirectangular(&cubes, width, height),
+            quality,
+        )
+    };
+    if has_cube_faces(input, &convention) {
+        convert(input, output)?;
+        return Ok(());
+    }
+    // frames of an animation, a folder of cube faces per frame
+    let frames = entries(input, |path| has_cube_faces(path, &convention))?;
+    if frames.is_empty() {
+        return Err(format!("no cube faces in {}", input.display()));
+    }
+    for frame in frames {
+        let name = frame.file_name().unwrap().to_string_lossy().to_string();
+        let output = output.join(format!("{}.png", name));
+        println!("{}", output.display());
+        convert(&frame, &output)?;
+    }
+    Ok(())
+}
+
+fn equi2cubes(input: &Path, output: &Path, options: &Options) -> Result<(), String> {
+    let size = options.get::<u32>("size")?;
+    let convention = options.cube_face_convention()?;
+    let convert = |input: &Path, output: &Path| -> Result<(), PanoError> {
+        let image = read_image(input)?;
+        let size = size.unwrap_or(image.width() / 4);
+        fs::create_dir_all(output)
+            .map_err(|e| PanoError::Io(format!("{}: {}", output.display(), e)))?;
+        let cubes = equirectangular_to_cubes(&image, size);
+        block_on(write_cube_faces(output, &cubes, &convention))
+    };
+    if input.is_dir() {
+        // a folder of cube faces per image
+        for path in entries(input, is_image)? {
+            let output = output.join(path.file_stem().unwrap());
+            println!("{}", output.display());
+            convert(&path, &output)?;
+        }
+        Ok(())
+    } else {
+        Ok(convert(input, output)?)
+    }
+}
+
+// `f` converts an image, every image in the folder when `input` is a folder
+fn convert_images(
+    input: &Path,
+    output: &Path,
+    options: &Options,
+    f: impl Fn(&RgbaImage) -> RgbaImage,
+) -> Result<(), String> {
+    let quality = options.get_or("quality", DEFAULT_QUALITY)?;
+    let convert = |input: &Path, output: &Path| -> Result<(), PanoError> {
+        let image = read_image(input)?;
+        write_image(output, f(&image), quality)
+    };
+    if input.is_dir() {
+        for path in entries(input, is_image)? {
+            let output = output.join(path.file_name().unwrap());
+            println!("{}", output.display());
+            convert(&path, &output)?;
+        }
+        Ok(())
+    } else {
+        Ok(convert(input, output)?)
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (command, input, output) = match args {
+        [command, input, output, ..] => (command.as_str(), Path::new(input), Path::new(output)),
+        _ => return Err(String::from(USAGE)),
+    };
+    let options = Options::parse(&args[3..])?;
+    match command {
+        "cubes2equi" => cubes2equi(input, output, &options),
+        "equi2cubes" => equi2cubes(input, output, &options),
+        "rotate" => {
+            let rotation = yaw_pitch_roll(
+                options.get_or("yaw", 0.0)?,
+                options.get_or("pitch", 0.0)?,
+                options.get_or("roll", 0.0)?,
+            );
+            convert_images(input, output, &options, |image| rotate(image, &rotation))
+        }
+        "reproject" => {
+            let rotation_x = options.get_or("rotation-x", 0.0)?;
+            let rotation_y = options.get_or("rotation-y", 0.0)?;
+            let fov = options.get_or("fov", 60.0)?;
+            let size = options.get_or("size", 1024)?;
+            convert_images(input, output, &options, |image| {
+                reproject(image, rotation_x, rotation_y, fov, size)
+            })
+        }
+        "transfer" => convert_images(input, output, &options, transfer),
+        "resize" => {
+            let width = options
+                .get("width")?
+                .ok_or_else(|| String::from("--width is needed"))?;
+            let height = options.get_or("height", width / 2)?;
+            convert_images(input, output, &options, |image| {
+                resize(image, width, height)
+            })
+        }
+        _ => Err(format!("unknown command: {}\n\n{}", command, USAGE)),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(message) = run(&args) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // a new empty directory for a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pano-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn options_take_the_last_value() {
+        let options = Options::parse(&args(&["--width", "100", "--width", "200"])).unwrap();
+        assert_eq!(options.get::<u32>("width").unwrap(), Some(200));
+        assert_eq!(options.get::<u32>("height").unwrap(), None);
+        assert!(Options::parse(&args(&["--width"])).is_err());
+        assert!(Options::parse(&args(&["width", "100"])).is_err());
+        let options = Options::parse(&args(&["--width", "wide"])).unwrap();
+        assert!(options.get::<u32>("width").is_err());
+    }
+
+    #[test]
+    fn cube_face_options_override_the_preset() {
+        let options = Options::parse(&args(&[
+            "--faces", "px", "--rotate", "top:90", "--rotate", "back:180", "--flip", "bottom",
+        ]))
+        .unwrap();
+        let convention = options.cube_face_convention().unwrap();
+        assert_eq!(convention.names[0], "pz");
+        assert_eq!(convention.orientations[4].rotation, 1);
+        assert_eq!(convention.orientations[1].rotation, 2);
+        assert!(convention.orientations[5].flip);
+
+        for invalid in [
+            &["--faces", "cubemap"][..],
+            &["--rotate", "top:45"],
+            &["--rotate", "up:90"],
+            &["--flip", "side"],
+        ] {
+            let options = Options::parse(&args(invalid)).unwrap();
+            assert!(options.cube_face_convention().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn cube_face_options_are_composed_with_the_preset() {
+        let options = Options::parse(&args(&[
+            "--faces", "unreal", "--rotate", "top:90", "--rotate", "front:90", "--rotate",
+            "front:90", "--flip", "left", "--flip", "back", "--flip", "back",
+        ]))
+        .unwrap();
+        let convention = options.cube_face_convention().unwrap();
+        let preset = CubeFaceConvention::preset(CubeFacePreset::Unreal);
+        assert_eq!(convention.names, preset.names);
+        // unreal turns the top 3 times and the front 3 times
+        assert_eq!(convention.orientations[4].rotation, 0);
+        assert_eq!(convention.orientations[0].rotation, 1);
+        assert!(convention.orientations[2].flip);
+        assert!(!convention.orientations[1].flip);
+        assert_eq!(convention.orientations[1], preset.orientations[1]);
+    }
+
+    #[test]
+    fn cube_faces_round_trip() {
+        let dir = temp_dir("cubes");
+        let image = RgbaImage::from_fn(256, 128, |x, y| {
+            image::Rgba([(x / 2) as u8, (y * 2) as u8, 128, 255])
+        });
+        let input = dir.join("input.png");
+        write_image(&input, image.clone(), DEFAULT_QUALITY).unwrap();
+
+        let faces = dir.join("faces");
+        let output = dir.join("output.png");
+        let input_str = input.to_str().unwrap();
+        let faces_str = faces.to_str().unwrap();
+        run(&args(&[
+            "equi2cubes",
+            input_str,
+            faces_str,
+            "--faces",
+            "unreal",
+        ]))
+        .unwrap();
+        assert!(has_cube_faces(
+            &faces,
+            &CubeFaceConvention::preset(CubeFacePreset::Unreal)
+        ));
+        run(&args(&[
+            "cubes2equi",
+            faces_str,
+            output.to_str().unwrap(),
+            "--faces",
+            "unreal",
+            "--width",
+            "256",
+        ]))
+        .unwrap();
+
+        let round_trip = read_image(&output).unwrap();
+        assert_eq!(round_trip.dimensions(), image.dimensions());
+        // the columns wrap around at the back and meet at the poles, which are not smooth
+        // in this image
+        for (x, y, pixel) in image
+            .enumerate_pixels()
+            .filter(|(x, y, _)| (8..248).contains(x) && (16..112).contains(y))
+        {
+            let other = round_trip.get_pixel(x, y);
+            for channel in 0..4 {
+                assert!(
+                    pixel[channel].abs_diff(other[channel]) <= 3,
+                    "({}, {}) {:?} {:?}",
+                    x,
+                    y,
+                    pixel,
+                    other
+                );
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_commands_are_errors() {
+        assert!(run(&args(&["flip", "a.png", "b.png"])).is_err());
+        assert!(run(&args(&["rotate"])).is_err());
+    }
+}
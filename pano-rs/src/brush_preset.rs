@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::PanoError;
+use crate::file_io::{read_binary, write_binary};
+use crate::storage::storage;
+
+pub const BRUSH_PRESET_FORMAT_VERSION: u32 = 1;
+// in the app data of the storage, e.g. the userData of Electron
+pub const BRUSH_PRESET_FILE: &str = "brush_presets.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BrushPreset {
+    pub name: String,
+    // rgb in 0.0 - 1.0
+    pub color: [f32; 3],
+    pub opacity: f32,
+    // angular width of the stroke (radian)
+    pub size: f32,
+    // 1.0 is a hard edge, 0.0 fades out from the center
+    pub hardness: f32,
+    // multiplies the color in float documents to paint above 1.0
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+impl Default for BrushPreset {
+    fn default() -> Self {
+        BrushPreset {
+            name: "default".to_string(),
+            color: [1.0, 0.0, 0.0],
+            opacity: 1.0,
+            size: 0.02,
+            hardness: 1.0,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BrushPresetLibrary {
+    pub version: u32,
+    pub presets: Vec<BrushPreset>,
+}
+
+impl Default for BrushPresetLibrary {
+    fn default() -> Self {
+        BrushPresetLibrary {
+            version: BRUSH_PRESET_FORMAT_VERSION,
+            presets: vec![BrushPreset::default()],
+        }
+    }
+}
+
+impl BrushPresetLibrary {
+    // an empty list falls back to the default presets, the panel needs a selected preset.
+    // Libraries from a newer version are refused, saving them again would drop their new fields.
+    pub fn from_json(json: &str) -> Result<Self, PanoError> {
+        let library: Self =
+            serde_json::from_str(json).map_err(|e| PanoError::Decode(e.to_string()))?;
+        if library.version > BRUSH_PRESET_FORMAT_VERSION {
+            return Err(PanoError::UnsupportedFormat(format!(
+                "ブラシプリセットのバージョン {} (このアプリは {} まで)",
+                library.version, BRUSH_PRESET_FORMAT_VERSION
+            )));
+        }
+        if library.presets.is_empty() {
+            Ok(Self::default())
+        } else {
+            Ok(library)
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub async fn load(path: &Path) -> Result<Self, PanoError> {
+        Self::from_json(&String::from_utf8_lossy(
+            read_binary(path).await?.as_slice(),
+        ))
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), PanoError> {
+        let json = self
+            .to_json()
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+        write_binary(path, json.into_bytes()).await
+    }
+
+    async fn library_path() -> Result<PathBuf, PanoError> {
+        storage().app_data_path(BRUSH_PRESET_FILE).await
+    }
+
+    // the default presets until a library is saved
+    pub async fn load_library() -> Result<Self, PanoError> {
+        let path = Self::library_path().await?;
+        if storage().exists(&path).await {
+            Self::load(&path).await
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub async fn load_or_default() -> Self {
+        Self::load_library().await.unwrap_or_else(|err| {
+            crate::console_log!("failed to load brush presets: {}", err);
+            Self::default()
+        })
+    }
+
+    // saves as the library `load_library` loads
+    pub async fn save_library(&self) -> Result<(), PanoError> {
+        self.save(&Self::library_path().await?).await
+    }
+
+    // appends presets of `other`, skipping the ones which already exist
+    pub fn merge(&mut self, other: BrushPresetLibrary) {
+        for mut preset in other.presets {
+            if self.presets.contains(&preset) {
+                continue;
+            }
+            preset.name = self.unique_name(&preset.name);
+            self.presets.push(preset);
+        }
+    }
+
+    // returns a name which is not used yet, e.g. "brush (2)"
+    pub fn unique_name(&self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut idx = 2;
+        while self.presets.iter().any(|preset| preset.name == name) {
+            name = format!("{} ({})", base, idx);
+            idx += 1;
+        }
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::storage::{set_storage, MemoryStorage};
+
+    fn library() -> BrushPresetLibrary {
+        let mut library = BrushPresetLibrary::default();
+        library.presets.push(BrushPreset {
+            name: "soft".to_string(),
+            hardness: 0.2,
+            ..BrushPreset::default()
+        });
+        library
+    }
+
+    #[test]
+    fn save_and_load() {
+        set_storage(Rc::new(MemoryStorage::default()));
+        let path = Path::new("presets.json");
+        block_on(library().save(path)).unwrap();
+        assert_eq!(block_on(BrushPresetLibrary::load(path)).unwrap(), library());
+    }
+
+    #[test]
+    fn the_saved_library_is_loaded() {
+        set_storage(Rc::new(MemoryStorage::default()));
+        assert_eq!(
+            block_on(BrushPresetLibrary::load_or_default()),
+            BrushPresetLibrary::default()
+        );
+        block_on(library().save_library()).unwrap();
+        assert_eq!(block_on(BrushPresetLibrary::load_or_default()), library());
+    }
+
+    #[test]
+    fn empty_libraries_fall_back_to_the_default() {
+        let library = BrushPresetLibrary::from_json(r#"{"version": 1, "presets": []}"#).unwrap();
+        assert_eq!(library, BrushPresetLibrary::default());
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let json = format!(
+            r#"{{"version": {}, "presets": []}}"#,
+            BRUSH_PRESET_FORMAT_VERSION + 1
+        );
+        assert!(matches!(
+            BrushPresetLibrary::from_json(&json),
+            Err(PanoError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn merge_renames_and_skips_duplicates() {
+        let mut merged = library();
+        let mut other = BrushPresetLibrary::default();
+        other.presets.push(BrushPreset {
+            name: "soft".to_string(),
+            hardness: 0.5,
+            ..BrushPreset::default()
+        });
+        merged.merge(other);
+        let names: Vec<&str> = merged
+            .presets
+            .iter()
+            .map(|preset| preset.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["default", "soft", "soft (2)"]);
+    }
+}
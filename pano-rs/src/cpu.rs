@@ -0,0 +1,427 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use image::{Rgba, RgbaImage};
+
+use crate::cube_face_convention::CubeFaces;
+use crate::sphere::{
+    cross, direction_to_tex_coords, dot, mul_vec, normalize, screen_to_direction, transpose, Mat3,
+    Vec3,
+};
+
+// CPU versions of the conversions of the shaders, for the native pano-cli which has no WebGL.
+// the directions and the texture coordinates follow the shaders: y is down and the rows of the
+// images are from the top.
+
+// bilinear on a grid of `width` x `height` pixels, `texel` also gets the pixels out of the grid
+fn bilinear(
+    width: u32,
+    height: u32,
+    u: f32,
+    v: f32,
+    texel: impl Fn(i64, i64) -> Rgba<u8>,
+) -> Rgba<u8> {
+    let x = u * width as f32 - 0.5;
+    let y = v * height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let corners = [
+        (texel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (texel(x0 + 1, y0), fx * (1.0 - fy)),
+        (texel(x0, y0 + 1), (1.0 - fx) * fy),
+        (texel(x0 + 1, y0 + 1), fx * fy),
+    ];
+    Rgba([0, 1, 2, 3].map(|channel| {
+        corners
+            .iter()
+            .map(|(pixel, weight)| pixel[channel] as f32 * weight)
+            .sum::<f32>()
+            .round()
+            .clamp(0.0, 255.0) as u8
+    }))
+}
+
+// `u` repeats and `v` is clamped
+fn sample(image: &RgbaImage, u: f32, v: f32) -> Rgba<u8> {
+    let (width, height) = image.dimensions();
+    bilinear(width, height, u, v, |x, y| {
+        *image.get_pixel(
+            x.rem_euclid(width as i64) as u32,
+            y.clamp(0, height as i64 - 1) as u32,
+        )
+    })
+}
+
+fn sample_direction(image: &RgbaImage, direction: Vec3) -> Rgba<u8> {
+    let (u, v) = direction_to_tex_coords(direction);
+    sample(image, u, v)
+}
+
+// direction of the point (u, v) in -1.0 - 1.0 of a face in the order of `CUBE_FACE_NAMES`,
+// it is out of the face beyond -1.0 and 1.0
+fn cube_face_direction(face: usize, u: f32, v: f32) -> Vec3 {
+    match face {
+        0 => (u, v, 1.0),
+        1 => (-u, v, -1.0),
+        2 => (-1.0, v, u),
+        3 => (1.0, v, -u),
+        4 => (u, -1.0, v),
+        _ => (u, 1.0, -v),
+    }
+}
+
+// inverse of `cube_face_direction`, same as 6cubes_to_equirectangular.frag
+fn cube_face_coords((x, y, z): Vec3) -> (usize, f32, f32) {
+    if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        if x <= 0.0 {
+            (2, -z / x, -y / x)
+        } else {
+            (3, -z / x, y / x)
+        }
+    } else if y.abs() >= z.abs() {
+        if y <= 0.0 {
+            (4, -x / y, -z / y)
+        } else {
+            (5, x / y, -z / y)
+        }
+    } else if z <= 0.0 {
+        (1, x / z, -y / z)
+    } else {
+        (0, x / z, y / z)
+    }
+}
+
+// the pixels out of a face are taken from the neighbouring faces, so that the seams are
+// interpolated like the inside of the faces
+fn sample_cube(cubes: &CubeFaces, face: usize, u: f32, v: f32) -> Rgba<u8> {
+    let image = &cubes.faces[face];
+    let (width, height) = image.dimensions();
+    bilinear(width, height, u, v, |x, y| {
+        if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+            return *image.get_pixel(x as u32, y as u32);
+        }
+        let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+        let (face, u, v) = cube_face_coords(cube_face_direction(face, u, v));
+        let image = &cubes.faces[face];
+        let (width, height) = image.dimensions();
+        let column = ((u + 1.0) / 2.0 * width as f32) as i64;
+        let row = ((v + 1.0) / 2.0 * height as f32) as i64;
+        *image.get_pixel(
+            column.clamp(0, width as i64 - 1) as u32,
+            row.clamp(0, height as i64 - 1) as u32,
+        )
+    })
+}
+
+// direction of the pixel center (x, y) of an equirectangular image, as `pt` of the shaders
+fn equirectangular_direction(x: u32, y: u32, width: u32, height: u32) -> Vec3 {
+    let azimuth = ((x as f32 + 0.5) / width as f32 * 2.0 - 1.0) * PI;
+    let elevation = ((y as f32 + 0.5) / height as f32 * 2.0 - 1.0) * FRAC_PI_2;
+    (
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        elevation.cos() * azimuth.cos(),
+    )
+}
+
+fn equirectangular_from(
+    width: u32,
+    height: u32,
+    mut color: impl FnMut(Vec3) -> Rgba<u8>,
+) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        color(equirectangular_direction(x, y, width, height))
+    })
+}
+
+// as 6cubes_to_equirectangular.frag, but the seams of the faces are interpolated
+pub fn cubes_to_equirectangular(cubes: &CubeFaces, width: u32, height: u32) -> RgbaImage {
+    equirectangular_from(width, height, |direction| {
+        let (face, u, v) = cube_face_coords(direction);
+        sample_cube(cubes, face, (u + 1.0) / 2.0, (v + 1.0) / 2.0)
+    })
+}
+
+// inverse of `cubes_to_equirectangular`, faces of `size` x `size`
+pub fn equirectangular_to_cubes(image: &RgbaImage, size: u32) -> CubeFaces {
+    CubeFaces {
+        faces: [0, 1, 2, 3, 4, 5].map(|face| {
+            RgbaImage::from_fn(size, size, |x, y| {
+                let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                sample_direction(image, cube_face_direction(face, u, v))
+            })
+        }),
+    }
+}
+
+// same as rotate_panorama, `rotation` carries the old directions to the new ones
+pub fn rotate(image: &RgbaImage, rotation: &Mat3) -> RgbaImage {
+    let sampling = transpose(rotation);
+    equirectangular_from(image.width(), image.height(), |direction| {
+        sample_direction(image, mul_vec(&sampling, direction))
+    })
+}
+
+pub fn resize(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    equirectangular_from(width, height, |direction| {
+        sample_direction(image, direction)
+    })
+}
+
+// flat image of `size` x `size` seen in the view of the app at (rotation_x, rotation_y) with `fov`
+pub fn reproject(
+    image: &RgbaImage,
+    rotation_x: f32,
+    rotation_y: f32,
+    fov: f32,
+    size: u32,
+) -> RgbaImage {
+    RgbaImage::from_fn(size, size, |x, y| {
+        let direction = screen_to_direction(
+            x as f32 + 0.5,
+            y as f32 + 0.5,
+            size as f32,
+            fov,
+            rotation_x,
+            rotation_y,
+        );
+        sample_direction(image, direction)
+    })
+}
+
+// same as image_transfer.frag, which paints the panorama with dots scattered on a warped cube
+pub fn transfer(image: &RgbaImage) -> RgbaImage {
+    const N: f32 = 128.0;
+    const SCALE_CONSTANT: f32 = 0.005;
+    const MAGIC_ANGLE: f32 = 0.868_734_8;
+    let tan_warp_theta = MAGIC_ANGLE.tan();
+    let warp = |x: f32| (MAGIC_ANGLE * x).tan() / tan_warp_theta;
+    let unwarp = |x: f32| (x * tan_warp_theta).atan() / MAGIC_ANGLE;
+    let quantize = |x: f32| (0.5 * N * x + 0.5).floor() * 2.0 / N;
+    let hash = |face: f32, u: f32, v: f32, iteration: usize| {
+        let i = iteration as f32;
+        hash33((
+            face * 351.097_8 + i * 9.134,
+            u * 351.097_8 + i * 5.45,
+            v * 351.097_8 + i * 34.953_7,
+        ))
+    };
+
+    equirectangular_from(image.width(), image.height(), |pt| {
+        let mut color = Rgba([0, 0, 0, 0]);
+        let permutation = CubeFacePermutation::of(pt);
+        let cf = permutation.to_face(pt);
+        let uv = (unwarp(cf.0 / cf.2), unwarp(cf.1 / cf.2));
+        let uv_quantized = (quantize(uv.0), quantize(uv.1));
+        for iteration in 0..10 {
+            let (_, _, z) = hash(
+                permutation.face_id(),
+                uv_quantized.0,
+                uv_quantized.1,
+                iteration,
+            );
+            let mut current_maximum_z = z - 1.0;
+            for du in -1..=1 {
+                for dv in -1..=1 {
+                    let neighbor = (
+                        uv_quantized.0 + du as f32 * 2.0 / N,
+                        uv_quantized.1 + dv as f32 * 2.0 / N,
+                    );
+                    let extra = (
+                        (neighbor.0 - neighbor.0.clamp(-1.0, 1.0)).abs(),
+                        (neighbor.1 - neighbor.1.clamp(-1.0, 1.0)).abs(),
+                    );
+                    if extra.0.min(extra.1) > 0.0 {
+                        continue;
+                    }
+
+                    let pt_neighbor = permutation.to_sphere((neighbor.0, neighbor.1, 1.0));
+                    let neighbor_permutation = CubeFacePermutation::of(pt_neighbor);
+                    let cf_neighbor = neighbor_permutation.to_face(pt_neighbor);
+                    let uv_neighbor = (
+                        quantize(cf_neighbor.0 / cf_neighbor.2),
+                        quantize(cf_neighbor.1 / cf_neighbor.2),
+                    );
+                    let hash_neighbor = hash(
+                        neighbor_permutation.face_id(),
+                        uv_neighbor.0,
+                        uv_neighbor.1,
+                        iteration,
+                    );
+                    let uv_neighbor = (
+                        warp(uv_neighbor.0 + (hash_neighbor.0 - 0.5) * 2.0 / N),
+                        warp(uv_neighbor.1 + (hash_neighbor.1 - 0.5) * 2.0 / N),
+                    );
+                    let pt_neighbor = normalize(neighbor_permutation.to_sphere((
+                        uv_neighbor.0,
+                        uv_neighbor.1,
+                        1.0,
+                    )));
+
+                    let dist = dot(normalize(pt), pt_neighbor).clamp(-1.0, 1.0).acos();
+                    let scale = SCALE_CONSTANT
+                        + (SCALE_CONSTANT / 2.0)
+                            * (hash_neighbor.0 + hash_neighbor.1 + hash_neighbor.2)
+                            / 3.0;
+                    if dist < scale && hash_neighbor.2 > current_maximum_z {
+                        color = sample_direction(image, pt_neighbor);
+                        current_maximum_z = hash_neighbor.2;
+                    }
+                }
+            }
+        }
+        color
+    })
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn hash33(p: Vec3) -> Vec3 {
+    let p = (
+        fract(p.0 * 0.1031),
+        fract(p.1 * 0.1030),
+        fract(p.2 * 0.0973),
+    );
+    let d = dot(p, (p.1 + 19.19, p.0 + 19.19, p.2 + 19.19));
+    let p = (p.0 + d, p.1 + d, p.2 + d);
+    (
+        fract((p.0 + p.1) * p.2),
+        fract((p.0 + p.0) * p.1),
+        fract((p.1 + p.0) * p.0),
+    )
+}
+
+// `cube_face_permutation_matrix` of image_transfer.frag, its columns
+struct CubeFacePermutation {
+    columns: [Vec3; 3],
+}
+
+impl CubeFacePermutation {
+    fn of(p: Vec3) -> Self {
+        let a = (p.0.abs(), p.1.abs(), p.2.abs());
+        let c = a.0.max(a.1).max(a.2);
+        let s = if c == a.0 {
+            (1.0, 0.0, 0.0)
+        } else if c == a.1 {
+            (0.0, 1.0, 0.0)
+        } else {
+            (0.0, 0.0, 1.0)
+        };
+        let sign = dot(p, s).signum();
+        let s = (s.0 * sign, s.1 * sign, s.2 * sign);
+        let q = (s.1, s.2, s.0);
+        CubeFacePermutation {
+            columns: [cross(q, s), q, s],
+        }
+    }
+
+    // `p * PT`
+    fn to_face(&self, p: Vec3) -> Vec3 {
+        let [x, y, z] = self.columns;
+        (dot(p, x), dot(p, y), dot(p, z))
+    }
+
+    // `PT * p`
+    fn to_sphere(&self, p: Vec3) -> Vec3 {
+        let [x, y, z] = self.columns;
+        (
+            x.0 * p.0 + y.0 * p.1 + z.0 * p.2,
+            x.1 * p.0 + y.1 * p.1 + z.1 * p.2,
+            x.2 * p.0 + y.2 * p.1 + z.2 * p.2,
+        )
+    }
+
+    fn face_id(&self) -> f32 {
+        let axis = self.columns[2];
+        let mut idx = axis.1.abs() * 2.0 + axis.2.abs() * 4.0;
+        if axis.0 + axis.1 + axis.2 < 0.0 {
+            idx += 1.0;
+        }
+        idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a smooth panorama, whose pixels change by `frequency` waves around the sphere
+    fn panorama(width: u32, height: u32, frequency: f32) -> RgbaImage {
+        equirectangular_from(width, height, |(x, y, z)| {
+            let wave = |t: f32| ((t * frequency).sin() * 120.0 + 128.0) as u8;
+            Rgba([wave(x), wave(y), wave(z), 255])
+        })
+    }
+
+    fn pixel_error(a: &Rgba<u8>, b: &Rgba<u8>) -> u8 {
+        (0..4)
+            .map(|channel| a[channel].abs_diff(b[channel]))
+            .max()
+            .unwrap()
+    }
+
+    // the max errors of the pixels within a pixel of the face edges, and of the others
+    fn seam_and_inside_errors(image: &RgbaImage, face_size: u32) -> (u8, u8) {
+        let cubes = equirectangular_to_cubes(image, face_size);
+        let round_trip = cubes_to_equirectangular(&cubes, image.width(), image.height());
+        let (mut seam, mut inside) = (0, 0);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let direction = equirectangular_direction(x, y, image.width(), image.height());
+            let (_, u, v) = cube_face_coords(direction);
+            let to_edge = (1.0 - u.abs().max(v.abs())) / 2.0 * face_size as f32;
+            let error = pixel_error(pixel, round_trip.get_pixel(x, y));
+            if to_edge < 1.0 {
+                seam = seam.max(error);
+            } else {
+                inside = inside.max(error);
+            }
+        }
+        (seam, inside)
+    }
+
+    #[test]
+    fn cube_faces_round_trip() {
+        let image = panorama(256, 128, 4.0);
+        let cubes = equirectangular_to_cubes(&image, 64);
+        let round_trip = cubes_to_equirectangular(&cubes, 256, 128);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert!(
+                pixel_error(pixel, round_trip.get_pixel(x, y)) <= 2,
+                "({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn cube_face_seams_are_interpolated_across_faces() {
+        let (seam, inside) = seam_and_inside_errors(&panorama(512, 256, 12.0), 128);
+        assert!(seam <= inside + 1, "seam {}, inside {}", seam, inside);
+    }
+
+    #[test]
+    fn cube_face_coords_inverts_cube_face_direction() {
+        for face in 0..6 {
+            for &(u, v) in &[(0.0, 0.0), (0.5, -0.25), (-0.9, 0.9)] {
+                let (found, found_u, found_v) = cube_face_coords(cube_face_direction(face, u, v));
+                assert_eq!(found, face);
+                assert!((found_u - u).abs() < 1e-6 && (found_v - v).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_round_trip() {
+        let image = panorama(256, 128, 4.0);
+        let rotation = crate::sphere::yaw_pitch_roll(90.0, 0.0, 0.0);
+        let round_trip = rotate(&rotate(&image, &rotation), &transpose(&rotation));
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert!(pixel_error(pixel, round_trip.get_pixel(x, y)) <= 2);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::path::Path;
+
+use image::imageops::{flip_horizontal, rotate180, rotate270, rotate90};
+use image::RgbaImage;
+
+use crate::error::PanoError;
+use crate::file_io::{read_image, write_image, ExportFormat};
+
+// how the six cube face files are named and oriented. the faces of pano are the views from the
+// inside: the sides are upright, the bottom of `top` and the top of `bottom` are next to `front`.
+// other conventions rename the faces and rotate or flip the images of the faces.
+
+pub const CUBE_FACE_NAMES: [&str; 6] = ["front", "back", "left", "right", "top", "bottom"];
+
+// in the order of `CUBE_FACE_NAMES`
+pub struct CubeFaces {
+    pub faces: [RgbaImage; 6],
+}
+
+// the file image is the face of pano turned `rotation` times by 90 degrees clockwise,
+// then flipped horizontally when `flip`
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct FaceOrientation {
+    pub rotation: u8,
+    pub flip: bool,
+}
+
+impl FaceOrientation {
+    const fn new(rotation: u8, flip: bool) -> Self {
+        FaceOrientation { rotation, flip }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CubeFacePreset {
+    Pano,
+    // the cube map of OpenGL, named px.png, nx.png, ...
+    OpenGl,
+    // the same as OpenGl, named posx.png, negx.png, ...
+    PosNeg,
+    // z up and the front view looks at +y, named by the axes of Blender
+    Blender,
+    // the 6 sided skybox, whose left is +x, which is on the right from +z
+    Unity,
+    // the cube map of OpenGL seen from three.js, whose camera looks at -z
+    ThreeJs,
+    // the cube map sampled by the axes of Unreal: x forward, y right and z up
+    Unreal,
+}
+
+impl CubeFacePreset {
+    pub const ALL: [CubeFacePreset; 7] = [
+        CubeFacePreset::Pano,
+        CubeFacePreset::OpenGl,
+        CubeFacePreset::PosNeg,
+        CubeFacePreset::Blender,
+        CubeFacePreset::Unity,
+        CubeFacePreset::ThreeJs,
+        CubeFacePreset::Unreal,
+    ];
+
+    // the name in the options of pano-cli
+    pub fn key(&self) -> &'static str {
+        match self {
+            CubeFacePreset::Pano => "pano",
+            CubeFacePreset::OpenGl => "px",
+            CubeFacePreset::PosNeg => "posx",
+            CubeFacePreset::Blender => "blender",
+            CubeFacePreset::Unity => "unity",
+            CubeFacePreset::ThreeJs => "threejs",
+            CubeFacePreset::Unreal => "unreal",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|preset| preset.key() == key)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CubeFacePreset::Pano => "pano (front, back, ...)",
+            CubeFacePreset::OpenGl => "OpenGL (px, nx, ...)",
+            CubeFacePreset::PosNeg => "posx, negx, ...",
+            CubeFacePreset::Blender => "Blender",
+            CubeFacePreset::Unity => "Unity",
+            CubeFacePreset::ThreeJs => "three.js",
+            CubeFacePreset::Unreal => "Unreal",
+        }
+    }
+
+    // (name, orientation) of front, back, left, right, top and bottom of pano
+    fn faces(&self) -> [(&'static str, FaceOrientation); 6] {
+        let upright = FaceOrientation::default();
+        match self {
+            CubeFacePreset::Pano => CUBE_FACE_NAMES.map(|name| (name, upright)),
+            CubeFacePreset::OpenGl => [
+                ("pz", upright),
+                ("nz", upright),
+                ("nx", upright),
+                ("px", upright),
+                ("py", upright),
+                ("ny", upright),
+            ],
+            CubeFacePreset::PosNeg => [
+                ("posz", upright),
+                ("negz", upright),
+                ("negx", upright),
+                ("posx", upright),
+                ("posy", upright),
+                ("negy", upright),
+            ],
+            CubeFacePreset::Blender => [
+                ("py", upright),
+                ("ny", upright),
+                ("nx", upright),
+                ("px", upright),
+                ("pz", upright),
+                ("nz", upright),
+            ],
+            CubeFacePreset::Unity => [
+                ("front", upright),
+                ("back", upright),
+                ("right", upright),
+                ("left", upright),
+                ("up", upright),
+                ("down", upright),
+            ],
+            CubeFacePreset::ThreeJs => [
+                ("nz", upright),
+                ("pz", upright),
+                ("px", upright),
+                ("nx", upright),
+                ("py", FaceOrientation::new(2, false)),
+                ("ny", FaceOrientation::new(2, false)),
+            ],
+            CubeFacePreset::Unreal => [
+                ("px", FaceOrientation::new(3, false)),
+                ("nx", FaceOrientation::new(1, false)),
+                ("ny", upright),
+                ("py", FaceOrientation::new(2, false)),
+                ("pz", FaceOrientation::new(3, false)),
+                ("nz", FaceOrientation::new(3, false)),
+            ],
+        }
+    }
+}
+
+// in the order of `CUBE_FACE_NAMES`
+#[derive(Clone, PartialEq, Debug)]
+pub struct CubeFaceConvention {
+    pub names: [String; 6],
+    pub orientations: [FaceOrientation; 6],
+}
+
+impl Default for CubeFaceConvention {
+    fn default() -> Self {
+        Self::preset(CubeFacePreset::Pano)
+    }
+}
+
+impl CubeFaceConvention {
+    pub fn preset(preset: CubeFacePreset) -> Self {
+        let faces = preset.faces();
+        CubeFaceConvention {
+            names: faces.map(|(name, _)| name.to_string()),
+            orientations: faces.map(|(_, orientation)| orientation),
+        }
+    }
+
+    // the preset which is the same, none when a face is changed
+    pub fn matching_preset(&self) -> Option<CubeFacePreset> {
+        CubeFacePreset::ALL
+            .iter()
+            .copied()
+            .find(|preset| Self::preset(*preset) == *self)
+    }
+
+    pub fn file_name(&self, face: usize) -> String {
+        format!("{}.png", self.names[face])
+    }
+
+    // the face of pano to the file image
+    pub fn to_file(&self, face: usize, image: &RgbaImage) -> RgbaImage {
+        let orientation = self.orientations[face];
+        let image = match orientation.rotation % 4 {
+            1 => rotate90(image),
+            2 => rotate180(image),
+            3 => rotate270(image),
+            _ => image.clone(),
+        };
+        if orientation.flip {
+            flip_horizontal(&image)
+        } else {
+            image
+        }
+    }
+
+    // the file image to the face of pano
+    pub fn to_face(&self, face: usize, image: &RgbaImage) -> RgbaImage {
+        let orientation = self.orientations[face];
+        let image = if orientation.flip {
+            flip_horizontal(image)
+        } else {
+            image.clone()
+        };
+        match orientation.rotation % 4 {
+            1 => rotate270(&image),
+            2 => rotate180(&image),
+            3 => rotate90(&image),
+            _ => image,
+        }
+    }
+}
+
+// the face files in `dir`
+pub async fn read_cube_faces(
+    dir: &Path,
+    convention: &CubeFaceConvention,
+) -> Result<CubeFaces, PanoError> {
+    let mut faces = vec![];
+    for face in 0..6 {
+        let image = read_image(&dir.join(convention.file_name(face))).await?;
+        faces.push(convention.to_face(face, &image));
+    }
+    Ok(CubeFaces {
+        faces: faces.try_into().unwrap(),
+    })
+}
+
+pub async fn write_cube_faces(
+    dir: &Path,
+    cubes: &CubeFaces,
+    convention: &CubeFaceConvention,
+) -> Result<(), PanoError> {
+    for (face, image) in cubes.faces.iter().enumerate() {
+        let image = convention.to_file(face, image);
+        write_image(
+            &dir.join(convention.file_name(face)),
+            image,
+            ExportFormat::Png,
+        )
+        .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+
+// errors of file and WebGL operations which are shown to the user instead of panicking
+#[derive(Debug, Clone, PartialEq)]
+pub enum PanoError {
+    Io(String),
+    Decode(String),
+    Encode(String),
+    UnsupportedFormat(String),
+    Gl(String),
+}
+
+impl fmt::Display for PanoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PanoError::Io(message) => write!(f, "ファイルの読み書きに失敗しました: {}", message),
+            PanoError::Decode(message) => write!(f, "ファイルを読み込めませんでした: {}", message),
+            PanoError::Encode(message) => write!(f, "ファイルを書き出せませんでした: {}", message),
+            PanoError::UnsupportedFormat(format) => {
+                write!(f, "対応していない形式です: {}", format)
+            }
+            PanoError::Gl(message) => write!(f, "WebGLのエラーです: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PanoError {}
+
+// for the WebGL calls, the storage maps its errors to `Io` itself
+impl From<JsValue> for PanoError {
+    fn from(value: JsValue) -> Self {
+        PanoError::Gl(format!("{:?}", value))
+    }
+}
+
+impl From<PanoError> for JsValue {
+    fn from(error: PanoError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+impl From<PanoError> for String {
+    fn from(error: PanoError) -> Self {
+        error.to_string()
+    }
+}
@@ -4,62 +4,495 @@ use std::path::Path;
 use exr::prelude::*;
 use js_sys::Uint8Array;
 
-use crate::wasm_bind::{read_file, write_file};
-
-pub fn read_binary(path: &Path) -> Vec<u8> {
-    let buffer = read_file(path.to_str().unwrap()).unwrap();
-    let buffer: Vec<u8> = Uint8Array::new_with_byte_offset_and_length(
-        &buffer.buffer(),
-        buffer.byte_offset(),
-        buffer.length(),
-    )
-    .to_vec();
-    buffer
+use crate::error::PanoError;
+use crate::storage::storage;
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+// linear premultiplied rgba, rows from the top, e.g. of exr and hdr
+pub struct FloatImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 4]>,
 }
 
-pub fn write_binary(path: &Path, data: Vec<u8>) {
-    let array = Uint8Array::new_with_length(data.len() as u32);
-    array.copy_from(data.as_slice());
-    write_file(path.to_str().unwrap(), &array).unwrap();
+// in the storage of `crate::storage::storage`
+pub async fn read_binary(path: &Path) -> std::result::Result<Vec<u8>, PanoError> {
+    storage().read(path).await
 }
 
-pub fn read_image(path: &Path) -> image::RgbaImage {
-    image::load(
-        Cursor::new(read_binary(path).as_slice()),
-        image::ImageFormat::Png,
-    )
-    .unwrap()
-    .to_rgba8()
+pub async fn write_binary(path: &Path, data: Vec<u8>) -> std::result::Result<(), PanoError> {
+    storage().write(path, data).await
 }
 
-pub fn write_image(path: &Path, data: image::RgbaImage) {
+pub fn decode_png(bytes: &[u8]) -> std::result::Result<image::RgbaImage, PanoError> {
+    match image::guess_format(bytes) {
+        Ok(image::ImageFormat::Png) => {}
+        Ok(format) => return Err(PanoError::UnsupportedFormat(format!("{:?}", format))),
+        Err(e) => return Err(PanoError::Decode(e.to_string())),
+    }
+    Ok(image::load(Cursor::new(bytes), image::ImageFormat::Png)
+        .map_err(|e| PanoError::Decode(e.to_string()))?
+        .to_rgba8())
+}
+
+pub fn encode_png(data: image::RgbaImage) -> std::result::Result<Vec<u8>, PanoError> {
     let data = image::DynamicImage::ImageRgba8(data);
     let mut bytes: Vec<u8> = Vec::new();
     data.write_to(&mut bytes, image::ImageOutputFormat::Png)
-        .unwrap();
+        .map_err(|e| PanoError::Encode(e.to_string()))?;
+    Ok(bytes)
+}
+
+// the rows encoded by `encode_png_rows` and `encode_exr_rows` at once, a multiple of the
+// 16 rows of an exr block
+pub const ENCODE_ROWS: usize = 256;
+
+// rgba8 png of the images too large to be held at once, `rows(y, count)` returns
+// `count` rows from `y`
+pub fn encode_png_rows(
+    width: usize,
+    height: usize,
+    mut rows: impl FnMut(usize, usize) -> std::result::Result<Vec<u8>, PanoError>,
+) -> std::result::Result<Vec<u8>, PanoError> {
+    let encode_error = |e: png::EncodingError| PanoError::Encode(e.to_string());
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(encode_error)?;
+    let mut stream = writer.stream_writer().map_err(encode_error)?;
+    for y in (0..height).step_by(ENCODE_ROWS) {
+        let data = rows(y, ENCODE_ROWS.min(height - y))?;
+        std::io::Write::write_all(&mut stream, &data)
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+    }
+    stream.finish().map_err(encode_error)?;
+    drop(writer);
+    Ok(bytes)
+}
+
+pub const DEFAULT_QUALITY: u8 = 90;
+
+// formats an image can be exported to, chosen by the extension of the output path
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+    Png,
+    // quality is 1 - 100
+    Jpeg { quality: u8 },
+    WebP { quality: u8 },
+    Tiff,
+    Hdr,
+    Exr,
+}
+
+impl ExportFormat {
+    // `quality` is used by the lossy formats
+    pub fn from_path(path: &Path, quality: u8) -> std::result::Result<Self, PanoError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Ok(ExportFormat::Png),
+            "jpg" | "jpeg" => Ok(ExportFormat::Jpeg { quality }),
+            "webp" => Ok(ExportFormat::WebP { quality }),
+            "tif" | "tiff" => Ok(ExportFormat::Tiff),
+            "hdr" => Ok(ExportFormat::Hdr),
+            "exr" => Ok(ExportFormat::Exr),
+            _ => Err(PanoError::UnsupportedFormat(extension)),
+        }
+    }
+}
+
+// the format is detected from the content first, then from the extension of `path`
+pub fn decode_image(bytes: &[u8], path: &Path) -> std::result::Result<image::RgbaImage, PanoError> {
+    // the image crate does not read exr
+    if bytes.starts_with(&EXR_MAGIC) {
+        return Ok(float_to_rgba(&decode_exr(bytes)?));
+    }
+    let format = image::guess_format(bytes)
+        .or_else(|_| image::ImageFormat::from_path(path))
+        .map_err(|_| PanoError::UnsupportedFormat(format!("{}", path.display())))?;
+    match format {
+        image::ImageFormat::Png
+        | image::ImageFormat::Jpeg
+        | image::ImageFormat::WebP
+        | image::ImageFormat::Tiff
+        | image::ImageFormat::Hdr => {}
+        _ => return Err(PanoError::UnsupportedFormat(format!("{:?}", format))),
+    }
+    Ok(image::load(Cursor::new(bytes), format)
+        .map_err(|e| PanoError::Decode(e.to_string()))?
+        .to_rgba8())
+}
+
+pub fn encode_image(
+    data: image::RgbaImage,
+    format: ExportFormat,
+) -> std::result::Result<Vec<u8>, PanoError> {
+    let (width, height) = data.dimensions();
+    let mut bytes: Vec<u8> = Vec::new();
+    match format {
+        ExportFormat::Png => return encode_png(data),
+        ExportFormat::Jpeg { quality } => {
+            // jpeg has no alpha
+            let data = image::DynamicImage::ImageRgba8(data).to_rgb8();
+            image::DynamicImage::ImageRgb8(data)
+                .write_to(&mut bytes, image::ImageOutputFormat::Jpeg(quality))
+                .map_err(|e| PanoError::Encode(e.to_string()))?;
+        }
+        ExportFormat::WebP { quality } => {
+            let array = Uint8Array::new_with_length(data.len() as u32);
+            array.copy_from(data.as_raw());
+            bytes = crate::wasm_bind::encode_webp(&array, width, height, quality as f32 / 100.0)
+                .map_err(|e| PanoError::Encode(format!("{:?}", e)))?
+                .to_vec();
+        }
+        ExportFormat::Tiff => {
+            image::codecs::tiff::TiffEncoder::new(Cursor::new(&mut bytes))
+                .encode(data.as_raw(), width, height, image::ColorType::Rgba8)
+                .map_err(|e| PanoError::Encode(e.to_string()))?;
+        }
+        ExportFormat::Hdr => {
+            // inverse of the gamma 2.2 the hdr decoder of the image crate applies
+            let pixels: Vec<image::Rgb<f32>> = data
+                .pixels()
+                .map(|pixel| {
+                    image::Rgb([0, 1, 2].map(|channel| (pixel[channel] as f32 / 255.0).powf(2.2)))
+                })
+                .collect();
+            image::codecs::hdr::HdrEncoder::new(&mut bytes)
+                .encode(&pixels, width as usize, height as usize)
+                .map_err(|e| PanoError::Encode(e.to_string()))?;
+        }
+        ExportFormat::Exr => return encode_exr(&rgba_to_float(&data)),
+    }
+    Ok(bytes)
+}
+
+// keeps the colors above 1.0 in exr and hdr, the other formats are converted from srgb
+pub fn decode_float_image(bytes: &[u8], path: &Path) -> std::result::Result<FloatImage, PanoError> {
+    if bytes.starts_with(&EXR_MAGIC) {
+        return decode_exr(bytes);
+    }
+    if image::guess_format(bytes).ok() == Some(image::ImageFormat::Hdr) {
+        return decode_hdr(bytes);
+    }
+    Ok(rgba_to_float(&decode_image(bytes, path)?))
+}
+
+pub fn encode_float_image(
+    image: &FloatImage,
+    format: ExportFormat,
+) -> std::result::Result<Vec<u8>, PanoError> {
+    match format {
+        ExportFormat::Exr => encode_exr(image),
+        ExportFormat::Hdr => encode_hdr(image),
+        _ => encode_image(float_to_rgba(image), format),
+    }
+}
+
+// radiance hdr is linear without alpha
+fn decode_hdr(bytes: &[u8]) -> std::result::Result<FloatImage, PanoError> {
+    let decoder = image::codecs::hdr::HdrDecoder::new(Cursor::new(bytes))
+        .map_err(|e| PanoError::Decode(e.to_string()))?;
+    let metadata = decoder.metadata();
+    // read_image_hdr converts the pixels on threads, which are not available in wasm
+    let pixels = decoder
+        .read_image_native()
+        .map_err(|e| PanoError::Decode(e.to_string()))?
+        .into_iter()
+        .map(|pixel| {
+            let rgb = pixel.to_hdr();
+            [rgb[0], rgb[1], rgb[2], 1.0]
+        })
+        .collect();
+    Ok(FloatImage {
+        width: metadata.width as usize,
+        height: metadata.height as usize,
+        pixels,
+    })
+}
+
+fn encode_hdr(image: &FloatImage) -> std::result::Result<Vec<u8>, PanoError> {
+    let pixels: Vec<image::Rgb<f32>> = image
+        .pixels
+        .iter()
+        .map(|pixel| {
+            let alpha = pixel[3];
+            image::Rgb([0, 1, 2].map(|channel| {
+                if alpha > 0.0 {
+                    (pixel[channel] / alpha).max(0.0)
+                } else {
+                    0.0
+                }
+            }))
+        })
+        .collect();
+    let mut bytes: Vec<u8> = Vec::new();
+    image::codecs::hdr::HdrEncoder::new(&mut bytes)
+        .encode(&pixels, image.width, image.height)
+        .map_err(|e| PanoError::Encode(e.to_string()))?;
+    Ok(bytes)
+}
+
+pub async fn read_image(path: &Path) -> std::result::Result<image::RgbaImage, PanoError> {
+    decode_image(read_binary(path).await?.as_slice(), path)
+}
+
+pub async fn write_image(
+    path: &Path,
+    data: image::RgbaImage,
+    format: ExportFormat,
+) -> std::result::Result<(), PanoError> {
+    write_binary(path, encode_image(data, format)?).await
+}
+
+pub async fn read_exr(path: &Path) -> std::result::Result<FloatImage, PanoError> {
+    decode_exr(read_binary(path).await?.as_slice())
+}
 
-    write_binary(path, bytes);
+pub async fn write_exr(path: &Path, image: &FloatImage) -> std::result::Result<(), PanoError> {
+    write_binary(path, encode_exr(image)?).await
 }
 
-pub fn read_exr(path: &Path) -> PixelImage<Vec<Vec<[f32; 4]>>, RgbaChannels> {
+// reads the first layer with any channels: rgb(a), luminance (Y) or a single channel
+pub fn decode_exr(bytes: &[u8]) -> std::result::Result<FloatImage, PanoError> {
     let image = exr::prelude::read()
         .no_deep_data()
         .largest_resolution_level()
-        .rgba_channels(
-            |resolution, _| {
-                let default_pixel = [0.0, 0.0, 0.0, 0.0];
-                let empty_line = vec![default_pixel; resolution.width()];
-                let empty_image = vec![empty_line; resolution.height()];
-                empty_image
-            },
-            |pixel_vector, position, (r, g, b, a): (f32, f32, f32, f32)| {
-                pixel_vector[position.y()][position.x()] = [r, g, b, a]
-            },
-        )
+        .all_channels()
         .first_valid_layer()
         .all_attributes()
         .non_parallel()
-        .from_buffered(Cursor::new(read_binary(path).as_slice()))
+        .from_buffered(Cursor::new(bytes))
+        .map_err(|e| PanoError::Decode(e.to_string()))?;
+    let layer = image.layer_data;
+    let (width, height) = (layer.size.width(), layer.size.height());
+    let channels = &layer.channel_data.list;
+    // channels of named layers are prefixed like `diffuse.R`
+    let find = |name: &str| {
+        channels
+            .iter()
+            .find(|channel| channel.name.to_string().rsplit('.').next() == Some(name))
+            .map(|channel| &channel.sample_data)
+    };
+    let gray = find("Y")
+        .or_else(|| channels.first().map(|channel| &channel.sample_data))
+        .ok_or_else(|| PanoError::Decode(String::from("no channel")))?;
+    let (red, green, blue) = match (find("R"), find("G"), find("B")) {
+        (Some(red), Some(green), Some(blue)) => (red, green, blue),
+        _ => (gray, gray, gray),
+    };
+    let alpha = find("A");
+    let pixels = (0..width * height)
+        .map(|idx| {
+            [
+                red.value_by_flat_index(idx).to_f32(),
+                green.value_by_flat_index(idx).to_f32(),
+                blue.value_by_flat_index(idx).to_f32(),
+                alpha.map_or(1.0, |alpha| alpha.value_by_flat_index(idx).to_f32()),
+            ]
+        })
+        .collect();
+    Ok(FloatImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+pub fn encode_exr(image: &FloatImage) -> std::result::Result<Vec<u8>, PanoError> {
+    encode_exr_rows(image.width, image.height, |y, count| {
+        Ok(image.pixels[y * image.width..(y + count) * image.width].to_vec())
+    })
+}
+
+// exr of the images too large to be held at once, `rows(y, count)` returns
+// `count` rows from `y`. the blocks are written in order, so one band is held at a time.
+pub fn encode_exr_rows(
+    width: usize,
+    height: usize,
+    mut rows: impl FnMut(usize, usize) -> std::result::Result<Vec<[f32; 4]>, PanoError>,
+) -> std::result::Result<Vec<u8>, PanoError> {
+    use exr::block::writer::ChunksWriter;
+    use exr::block::UncompressedBlock;
+
+    let encode_error = |e: exr::error::Error| PanoError::Encode(e.to_string());
+    let header = exr::meta::header::Header::new(
+        Text::from("rgba"),
+        (width, height),
+        ["A", "B", "G", "R"]
+            .iter()
+            .map(|&name| ChannelDescription::new(name, SampleType::F32, true))
+            .collect(),
+    )
+    .with_encoding(
+        Compression::ZIP16,
+        exr::meta::BlockDescription::ScanLines,
+        LineOrder::Increasing,
+    );
+
+    // the band of `ENCODE_ROWS` rows from its first row
+    let mut band: Option<(usize, Vec<[f32; 4]>)> = None;
+    let mut band_error = None;
+    let mut bytes: Vec<u8> = Vec::new();
+    exr::block::write(
+        Cursor::new(&mut bytes),
+        exr::meta::Headers::from_vec(vec![header]),
+        true,
+        |meta_data, chunk_writer| {
+            let blocks = meta_data.collect_ordered_blocks(|block_index| {
+                let channels = &meta_data.headers[block_index.layer].channels;
+                UncompressedBlock::from_lines(channels, block_index, |line| {
+                    let y = line.location.position.y();
+                    let first = y - y % ENCODE_ROWS;
+                    if band.as_ref().map(|(start, _)| *start) != Some(first) {
+                        let pixels =
+                            rows(first, ENCODE_ROWS.min(height - first)).unwrap_or_else(|e| {
+                                band_error.get_or_insert(e);
+                                vec![[0.0; 4]; width * ENCODE_ROWS.min(height - first)]
+                            });
+                        band = Some((first, pixels));
+                    }
+                    let pixels = &band.as_ref().unwrap().1;
+                    let row = &pixels[(y - first) * width..];
+                    let x = line.location.position.x();
+                    // exr sorts the channels by name
+                    let channel = [3, 2, 1, 0][line.location.channel];
+                    line.write_samples(|idx| row[x + idx][channel])
+                        .expect("the line of the block is in the image");
+                })
+            });
+            chunk_writer
+                .on_progress(|_| {})
+                .compress_all_blocks_sequential(&meta_data, blocks)
+        },
+    )
+    .map_err(encode_error)?;
+    match band_error {
+        Some(err) => Err(err),
+        None => Ok(bytes),
+    }
+}
+
+// unpremultiplied srgb, like the other formats
+pub fn float_to_rgba(image: &FloatImage) -> image::RgbaImage {
+    let mut data = Vec::with_capacity(image.pixels.len() * 4);
+    for pixel in image.pixels.iter() {
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        for channel in pixel.iter().take(3) {
+            let value = if alpha > 0.0 { channel / alpha } else { 0.0 };
+            data.push((linear_to_srgb(value) * 255.0).round() as u8);
+        }
+        data.push((alpha * 255.0).round() as u8);
+    }
+    image::RgbaImage::from_vec(image.width as u32, image.height as u32, data).unwrap()
+}
+
+pub fn rgba_to_float(data: &image::RgbaImage) -> FloatImage {
+    let pixels = data
+        .pixels()
+        .map(|pixel| {
+            let alpha = pixel[3] as f32 / 255.0;
+            [
+                srgb_to_linear(pixel[0] as f32 / 255.0) * alpha,
+                srgb_to_linear(pixel[1] as f32 / 255.0) * alpha,
+                srgb_to_linear(pixel[2] as f32 / 255.0) * alpha,
+                alpha,
+            ]
+        })
+        .collect();
+    FloatImage {
+        width: data.width() as usize,
+        height: data.height() as usize,
+        pixels,
+    }
+}
+
+// flat rgba floats from premultiplied to straight alpha
+pub fn unpremultiply(values: &[f32]) -> Vec<f32> {
+    values
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let alpha = pixel[3];
+            let scale = if alpha > 0.0 { 1.0 / alpha } else { 0.0 };
+            [pixel[0] * scale, pixel[1] * scale, pixel[2] * scale, alpha]
+        })
+        .collect()
+}
+
+pub fn premultiply(values: &[f32]) -> Vec<f32> {
+    values
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let alpha = pixel[3];
+            [pixel[0] * alpha, pixel[1] * alpha, pixel[2] * alpha, alpha]
+        })
+        .collect()
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // taller than a band, and not a multiple of it nor of the 16 rows of an exr block
+    const WIDTH: usize = 5;
+    const HEIGHT: usize = ENCODE_ROWS + 21;
+
+    #[test]
+    fn png_rows_round_trip() {
+        let data: Vec<u8> = (0..WIDTH * HEIGHT * 4)
+            .map(|idx| (idx % 251) as u8)
+            .collect();
+        let bytes = encode_png_rows(WIDTH, HEIGHT, |y, count| {
+            Ok(data[y * WIDTH * 4..(y + count) * WIDTH * 4].to_vec())
+        })
         .unwrap();
-    image
+        assert_eq!(decode_png(&bytes).unwrap().into_raw(), data);
+    }
+
+    #[test]
+    fn exr_rows_round_trip() {
+        let image = FloatImage {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: (0..WIDTH * HEIGHT)
+                .map(|idx| {
+                    let value = idx as f32 / 7.0;
+                    [value, value * 2.0, -value, 0.5]
+                })
+                .collect(),
+        };
+        let decoded = decode_exr(&encode_exr(&image).unwrap()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (WIDTH, HEIGHT));
+        assert_eq!(decoded.pixels, image.pixels);
+    }
+
+    #[test]
+    fn errors_of_the_rows_are_returned() {
+        let result = encode_exr_rows(WIDTH, HEIGHT, |_, _| {
+            Err(PanoError::Gl(String::from("lost")))
+        });
+        assert_eq!(result, Err(PanoError::Gl(String::from("lost"))));
+    }
 }
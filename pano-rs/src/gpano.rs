@@ -0,0 +1,387 @@
+use crate::file_io::ExportFormat;
+use crate::project::ViewState;
+
+// Photo Sphere metadata (GPano XMP), which makes viewers show an equirectangular image as a panorama.
+// see https://developers.google.com/streetview/spherical-metadata
+
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+// the fov of a new view
+const DEFAULT_FOV: f32 = 60.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GPano {
+    pub full_width: usize,
+    pub full_height: usize,
+    // the image is this part of the full panorama
+    pub cropped_left: usize,
+    pub cropped_top: usize,
+    pub cropped_width: usize,
+    pub cropped_height: usize,
+    // degree, the heading is 0 at the left edge of the full panorama and the pitch is positive upward
+    pub initial_heading: f32,
+    pub initial_pitch: f32,
+    pub initial_fov: f32,
+}
+
+impl GPano {
+    // an image of the whole panorama, first shown in `view`
+    pub fn full(width: usize, height: usize, view: &ViewState) -> Self {
+        // the view looks at the center of the image when rotation_y is 0, see `direction_to_tex_coords`
+        GPano {
+            full_width: width,
+            full_height: height,
+            cropped_left: 0,
+            cropped_top: 0,
+            cropped_width: width,
+            cropped_height: height,
+            initial_heading: (180.0 - view.rotation_y).rem_euclid(360.0),
+            initial_pitch: -view.rotation_x,
+            initial_fov: view.fov,
+        }
+    }
+
+    // the cropped area scaled to a full panorama of `width` x `height`
+    pub fn cropped_rect(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let scale_x = width as f64 / self.full_width.max(1) as f64;
+        let scale_y = height as f64 / self.full_height.max(1) as f64;
+        let left = (self.cropped_left as f64 * scale_x).round() as usize % width;
+        let top = ((self.cropped_top as f64 * scale_y).round() as usize).min(height - 1);
+        let cropped_width =
+            ((self.cropped_width as f64 * scale_x).round() as usize).clamp(1, width);
+        let cropped_height =
+            ((self.cropped_height as f64 * scale_y).round() as usize).clamp(1, height - top);
+        (left, top, cropped_width, cropped_height)
+    }
+
+    pub fn initial_view(&self) -> ViewState {
+        ViewState {
+            rotation_x: -self.initial_pitch.clamp(-90.0, 90.0),
+            rotation_y: (180.0 - self.initial_heading).rem_euclid(360.0),
+            fov: self.initial_fov,
+        }
+    }
+
+    pub fn to_xmp(&self) -> String {
+        let properties = [
+            ("ProjectionType", String::from("equirectangular")),
+            ("UsePanoramaViewer", String::from("True")),
+            ("FullPanoWidthPixels", self.full_width.to_string()),
+            ("FullPanoHeightPixels", self.full_height.to_string()),
+            ("CroppedAreaLeftPixels", self.cropped_left.to_string()),
+            ("CroppedAreaTopPixels", self.cropped_top.to_string()),
+            (
+                "CroppedAreaImageWidthPixels",
+                self.cropped_width.to_string(),
+            ),
+            (
+                "CroppedAreaImageHeightPixels",
+                self.cropped_height.to_string(),
+            ),
+            // integers in the specification
+            (
+                "InitialViewHeadingDegrees",
+                (self.initial_heading.round() as i32).to_string(),
+            ),
+            (
+                "InitialViewPitchDegrees",
+                (self.initial_pitch.round() as i32).to_string(),
+            ),
+            (
+                "InitialHorizontalFOVDegrees",
+                format!("{:.1}", self.initial_fov),
+            ),
+        ];
+        let mut xmp = String::from(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+             <rdf:Description rdf:about=\"\" xmlns:GPano=\"http://ns.google.com/photos/1.0/panorama/\">\n",
+        );
+        for (name, value) in properties.iter() {
+            xmp.push_str(&format!("<GPano:{0}>{1}</GPano:{0}>\n", name, value));
+        }
+        xmp.push_str("</rdf:Description>\n</rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>");
+        xmp
+    }
+
+    // None unless the xmp describes an equirectangular panorama
+    pub fn from_xmp(xmp: &str) -> Option<Self> {
+        if let Some(projection) = xmp_property(xmp, "ProjectionType") {
+            if projection != "equirectangular" {
+                return None;
+            }
+        }
+        let number = |name: &str| xmp_property(xmp, name)?.trim().parse::<f32>().ok();
+        // a size of less than a pixel is treated as missing
+        let size = |name: &str| number(name).filter(|v| *v >= 1.0).map(|v| v as usize);
+        let full_width = size("FullPanoWidthPixels")?;
+        let full_height = size("FullPanoHeightPixels")?;
+        let cropped_width = size("CroppedAreaImageWidthPixels").unwrap_or(full_width);
+        let cropped_height = size("CroppedAreaImageHeightPixels").unwrap_or(full_height);
+        let cropped_left = number("CroppedAreaLeftPixels").map_or(0, |v| v as usize);
+        let cropped_top = number("CroppedAreaTopPixels").map_or(0, |v| v as usize);
+        // the center of the image unless specified
+        let center_heading =
+            (cropped_left as f32 + cropped_width as f32 / 2.0) / full_width as f32 * 360.0;
+        Some(GPano {
+            full_width,
+            full_height,
+            cropped_left,
+            cropped_top,
+            cropped_width,
+            cropped_height,
+            initial_heading: number("InitialViewHeadingDegrees").unwrap_or(center_heading),
+            initial_pitch: number("InitialViewPitchDegrees").unwrap_or(0.0),
+            initial_fov: number("InitialHorizontalFOVDegrees").unwrap_or(DEFAULT_FOV),
+        })
+    }
+
+    // adds the xmp to an encoded png or jpeg, the other formats are returned as they are
+    pub fn embed(&self, bytes: Vec<u8>, format: ExportFormat) -> Vec<u8> {
+        match format {
+            ExportFormat::Png => embed_png(bytes, self.to_xmp().as_bytes()),
+            ExportFormat::Jpeg { .. } => embed_jpeg(bytes, self.to_xmp().as_bytes()),
+            _ => bytes,
+        }
+    }
+
+    // from an encoded png or jpeg
+    pub fn read(bytes: &[u8]) -> Option<Self> {
+        let xmp = if bytes.starts_with(&PNG_SIGNATURE) {
+            png_xmp(bytes)?
+        } else if bytes.starts_with(&[0xff, 0xd8]) {
+            jpeg_xmp(bytes)?
+        } else {
+            return None;
+        };
+        Self::from_xmp(&String::from_utf8_lossy(xmp))
+    }
+}
+
+// the value of `GPano:name`, written either as an attribute or as an element
+fn xmp_property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let attribute = format!("GPano:{}=\"", name);
+    if let Some(start) = xmp.find(&attribute) {
+        let value = &xmp[start + attribute.len()..];
+        return value.find('"').map(|end| &value[..end]);
+    }
+    let element = format!("<GPano:{}>", name);
+    let start = xmp.find(&element)? + element.len();
+    let value = &xmp[start..];
+    value.find('<').map(|end| &value[..end])
+}
+
+// an iTXt chunk right after IHDR, which is always the first chunk
+fn embed_png(bytes: Vec<u8>, xmp: &[u8]) -> Vec<u8> {
+    let ihdr_end = PNG_SIGNATURE.len() + 8 + 13 + 4;
+    if bytes.len() < ihdr_end {
+        return bytes;
+    }
+    let mut data = XMP_KEYWORD.to_vec();
+    // no compression, no language and no translated keyword
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(xmp);
+
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(b"iTXt");
+    chunk.extend_from_slice(&data);
+    chunk.extend_from_slice(&crc32fast::hash(&chunk[4..]).to_be_bytes());
+
+    let mut embedded = bytes[..ihdr_end].to_vec();
+    embedded.extend_from_slice(&chunk);
+    embedded.extend_from_slice(&bytes[ihdr_end..]);
+    embedded
+}
+
+fn png_xmp(bytes: &[u8]) -> Option<&[u8]> {
+    let mut position = PNG_SIGNATURE.len();
+    while position + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[position..position + 4].try_into().ok()?) as usize;
+        let chunk_type = &bytes[position + 4..position + 8];
+        let data = bytes.get(position + 8..position + 8 + length)?;
+        if chunk_type == b"iTXt"
+            && data.starts_with(XMP_KEYWORD)
+            && data.get(XMP_KEYWORD.len()) == Some(&0)
+        {
+            // compression flag and method, then the language and the translated keyword
+            let rest = &data[XMP_KEYWORD.len() + 1..];
+            if rest.first() != Some(&0) {
+                return None;
+            }
+            let rest = rest.get(2..)?;
+            let language_end = rest.iter().position(|&byte| byte == 0)?;
+            let rest = &rest[language_end + 1..];
+            let keyword_end = rest.iter().position(|&byte| byte == 0)?;
+            return Some(&rest[keyword_end + 1..]);
+        }
+        if chunk_type == b"IEND" {
+            break;
+        }
+        position += 12 + length;
+    }
+    None
+}
+
+// an APP1 segment right after SOI and the APP0 (JFIF) segment
+fn embed_jpeg(bytes: Vec<u8>, xmp: &[u8]) -> Vec<u8> {
+    let length = 2 + XMP_NAMESPACE.len() + xmp.len();
+    if length > u16::MAX as usize || bytes.len() < 4 {
+        return bytes;
+    }
+    let mut position = 2;
+    if bytes[2..4] == [0xff, 0xe0] && bytes.len() >= 6 {
+        position += 2 + u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+    }
+    let mut embedded = bytes[..position].to_vec();
+    embedded.extend_from_slice(&[0xff, 0xe1]);
+    embedded.extend_from_slice(&(length as u16).to_be_bytes());
+    embedded.extend_from_slice(XMP_NAMESPACE);
+    embedded.extend_from_slice(xmp);
+    embedded.extend_from_slice(&bytes[position..]);
+    embedded
+}
+
+fn jpeg_xmp(bytes: &[u8]) -> Option<&[u8]> {
+    let mut position = 2;
+    while position + 4 <= bytes.len() && bytes[position] == 0xff {
+        let marker = bytes[position + 1];
+        // the metadata comes before the image data
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+        let length = u16::from_be_bytes([bytes[position + 2], bytes[position + 3]]) as usize;
+        let data = bytes.get(position + 4..position + 2 + length)?;
+        if marker == 0xe1 && data.starts_with(XMP_NAMESPACE) {
+            return Some(&data[XMP_NAMESPACE.len()..]);
+        }
+        position += 2 + length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_io::encode_image;
+
+    fn gpano() -> GPano {
+        let view = ViewState {
+            rotation_x: -10.0,
+            rotation_y: 30.0,
+            fov: 75.0,
+        };
+        GPano::full(8, 4, &view)
+    }
+
+    fn image() -> image::RgbaImage {
+        image::RgbaImage::from_fn(8, 4, |x, y| {
+            image::Rgba([x as u8 * 30, y as u8 * 60, 0, 255])
+        })
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let bytes = encode_image(image(), ExportFormat::Png).unwrap();
+        let embedded = gpano().embed(bytes, ExportFormat::Png);
+        assert_eq!(png_xmp(&embedded), Some(gpano().to_xmp().as_bytes()));
+        assert_eq!(GPano::read(&embedded), Some(gpano()));
+        // the image is still readable
+        let decoded = image::load_from_memory(&embedded).unwrap().to_rgba8();
+        assert_eq!(decoded, image());
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        let format = ExportFormat::Jpeg { quality: 90 };
+        let bytes = encode_image(image(), format).unwrap();
+        let embedded = gpano().embed(bytes, format);
+        assert_eq!(jpeg_xmp(&embedded), Some(gpano().to_xmp().as_bytes()));
+        assert_eq!(GPano::read(&embedded), Some(gpano()));
+        let decoded = image::load_from_memory(&embedded).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (8, 4));
+    }
+
+    #[test]
+    fn images_without_xmp() {
+        let bytes = encode_image(image(), ExportFormat::Png).unwrap();
+        assert_eq!(GPano::read(&bytes), None);
+        let bytes = encode_image(image(), ExportFormat::Jpeg { quality: 90 }).unwrap();
+        assert_eq!(GPano::read(&bytes), None);
+        assert_eq!(GPano::read(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn element_form() {
+        assert_eq!(GPano::from_xmp(&gpano().to_xmp()), Some(gpano()));
+    }
+
+    #[test]
+    fn attribute_form() {
+        let xmp = "<rdf:Description rdf:about=\"\" \
+                   GPano:ProjectionType=\"equirectangular\" \
+                   GPano:FullPanoWidthPixels=\"4000\" \
+                   GPano:FullPanoHeightPixels=\"2000\" \
+                   GPano:CroppedAreaLeftPixels=\"1000\" \
+                   GPano:CroppedAreaImageWidthPixels=\"2000\"/>";
+        let gpano = GPano::from_xmp(xmp).unwrap();
+        assert_eq!((gpano.full_width, gpano.full_height), (4000, 2000));
+        assert_eq!((gpano.cropped_left, gpano.cropped_top), (1000, 0));
+        assert_eq!((gpano.cropped_width, gpano.cropped_height), (2000, 2000));
+        // looks at the center of the image
+        assert_eq!(gpano.initial_heading, 180.0);
+        assert_eq!(gpano.initial_pitch, 0.0);
+        assert_eq!(gpano.initial_fov, DEFAULT_FOV);
+    }
+
+    #[test]
+    fn other_projections() {
+        let xmp = "<GPano:ProjectionType>cylindrical</GPano:ProjectionType>\
+                   <GPano:FullPanoWidthPixels>4000</GPano:FullPanoWidthPixels>\
+                   <GPano:FullPanoHeightPixels>2000</GPano:FullPanoHeightPixels>";
+        assert_eq!(GPano::from_xmp(xmp), None);
+    }
+
+    #[test]
+    fn zero_or_negative_sizes() {
+        let xmp = |width: &str, cropped: &str, left: &str| {
+            format!(
+                "GPano:FullPanoWidthPixels=\"{}\" GPano:FullPanoHeightPixels=\"2000\" \
+                 GPano:CroppedAreaImageWidthPixels=\"{}\" GPano:CroppedAreaLeftPixels=\"{}\"",
+                width, cropped, left
+            )
+        };
+        assert_eq!(GPano::from_xmp(&xmp("0", "100", "0")), None);
+        assert_eq!(GPano::from_xmp(&xmp("-4000", "100", "0")), None);
+        // the full width is used instead of the missing cropped width
+        let gpano = GPano::from_xmp(&xmp("4000", "0", "-10")).unwrap();
+        assert_eq!((gpano.cropped_left, gpano.cropped_width), (0, 4000));
+        let gpano = GPano::from_xmp(&xmp("4000", "-100", "0")).unwrap();
+        assert_eq!(gpano.cropped_width, 4000);
+    }
+
+    #[test]
+    fn cropped_rect_is_scaled() {
+        let gpano = GPano {
+            full_width: 4000,
+            full_height: 2000,
+            cropped_left: 3000,
+            cropped_top: 500,
+            cropped_width: 2000,
+            cropped_height: 1000,
+            initial_heading: 0.0,
+            initial_pitch: 0.0,
+            initial_fov: DEFAULT_FOV,
+        };
+        assert_eq!(gpano.cropped_rect(2000, 1000), (1500, 250, 1000, 500));
+        assert_eq!(gpano.cropped_rect(4000, 2000), (3000, 500, 2000, 1000));
+        // the left edge wraps around and the bottom is clamped
+        let gpano = GPano {
+            cropped_left: 4000,
+            cropped_top: 1800,
+            ..gpano
+        };
+        assert_eq!(gpano.cropped_rect(2000, 1000), (0, 900, 1000, 100));
+    }
+}
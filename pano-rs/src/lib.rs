@@ -1,6 +1,20 @@
+pub mod adjustment;
+pub mod animation;
 pub mod app;
+pub mod brush_preset;
+pub mod cpu;
+pub mod cube_face_convention;
+pub mod error;
 pub mod experimental;
 pub mod file_io;
+pub mod gpano;
+pub mod pixel_format;
+pub mod project;
+pub mod recovery;
+pub mod sphere;
+pub mod stereo;
+pub mod storage;
+pub mod text;
 pub mod wasm_bind;
 pub mod webgl_utils;
 
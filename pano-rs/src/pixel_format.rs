@@ -0,0 +1,150 @@
+use js_sys::{Float32Array, Object, Uint8Array};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+
+use crate::file_io::{linear_to_srgb, srgb_to_linear};
+use crate::WebGl2RenderingContext;
+
+// how the layers of a document are stored, the alpha is straight in both.
+// 8 bit layers hold srgb colors, float layers hold linear colors which may exceed 1.0.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum PixelFormat {
+    #[default]
+    Rgba8,
+    // half floats in the textures, transferred as 32 bit floats
+    Rgba16F,
+}
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 2] = [PixelFormat::Rgba8, PixelFormat::Rgba16F];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8 => "8bit",
+            PixelFormat::Rgba16F => "浮動小数点 (HDR)",
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        *self == PixelFormat::Rgba16F
+    }
+
+    // bytes of a pixel in the transferred pixels
+    pub fn pixel_size(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Rgba16F => 16,
+        }
+    }
+
+    pub fn internal_format(&self) -> u32 {
+        match self {
+            PixelFormat::Rgba8 => WebGl2RenderingContext::RGBA8,
+            PixelFormat::Rgba16F => WebGl2RenderingContext::RGBA16F,
+        }
+    }
+
+    pub fn data_type(&self) -> u32 {
+        match self {
+            PixelFormat::Rgba8 => WebGl2RenderingContext::UNSIGNED_BYTE,
+            PixelFormat::Rgba16F => WebGl2RenderingContext::FLOAT,
+        }
+    }
+
+    // values of the channels as the shaders see them
+    pub fn to_f32(&self, pixels: &[u8]) -> Vec<f32> {
+        match self {
+            PixelFormat::Rgba8 => pixels.iter().map(|&value| value as f32 / 255.0).collect(),
+            PixelFormat::Rgba16F => pixels
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect(),
+        }
+    }
+
+    pub fn f32_to_pixels(&self, values: &[f32]) -> Vec<u8> {
+        match self {
+            PixelFormat::Rgba8 => values
+                .iter()
+                .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+            PixelFormat::Rgba16F => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    // from unpremultiplied srgb RGBA8, e.g. a decoded image
+    pub fn rgba8_to_pixels(&self, pixels: &[u8]) -> Vec<u8> {
+        match self {
+            PixelFormat::Rgba8 => pixels.to_vec(),
+            PixelFormat::Rgba16F => {
+                let values: Vec<f32> = pixels
+                    .chunks_exact(4)
+                    .flat_map(|pixel| {
+                        [
+                            srgb_to_linear(pixel[0] as f32 / 255.0),
+                            srgb_to_linear(pixel[1] as f32 / 255.0),
+                            srgb_to_linear(pixel[2] as f32 / 255.0),
+                            pixel[3] as f32 / 255.0,
+                        ]
+                    })
+                    .collect();
+                self.f32_to_pixels(&values)
+            }
+        }
+    }
+
+    // to unpremultiplied srgb RGBA8, the colors above 1.0 are clipped
+    pub fn to_rgba8(&self, pixels: &[u8]) -> Vec<u8> {
+        match self {
+            PixelFormat::Rgba8 => pixels.to_vec(),
+            PixelFormat::Rgba16F => self
+                .to_f32(pixels)
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    [
+                        linear_to_srgb(pixel[0]),
+                        linear_to_srgb(pixel[1]),
+                        linear_to_srgb(pixel[2]),
+                        pixel[3].clamp(0.0, 1.0),
+                    ]
+                    .map(|value| (value * 255.0).round() as u8)
+                })
+                .collect(),
+        }
+    }
+
+    // `fill` is 0 or 255 for each channel, which is the same in both formats
+    pub fn fill_pixels(&self, fill: [u8; 4], count: usize) -> Vec<u8> {
+        self.rgba8_to_pixels(&fill).repeat(count)
+    }
+
+    // WebGL takes an array of the type matching `data_type`
+    pub fn to_array(&self, pixels: &[u8]) -> Object {
+        let array = Uint8Array::from(pixels);
+        match self {
+            PixelFormat::Rgba8 => array.into(),
+            PixelFormat::Rgba16F => Float32Array::new(&array.buffer()).into(),
+        }
+    }
+
+    // an array to read `count` pixels into
+    pub fn new_array(&self, count: usize) -> Object {
+        match self {
+            PixelFormat::Rgba8 => Uint8Array::new_with_length((count * 4) as u32).into(),
+            PixelFormat::Rgba16F => Float32Array::new_with_length((count * 4) as u32).into(),
+        }
+    }
+
+    // the pixels in an array of `to_array` or `new_array`
+    pub fn array_to_pixels(&self, array: &Object) -> Vec<u8> {
+        match self {
+            PixelFormat::Rgba8 => array.unchecked_ref::<Uint8Array>().to_vec(),
+            PixelFormat::Rgba16F => {
+                Uint8Array::new(&array.unchecked_ref::<Float32Array>().buffer()).to_vec()
+            }
+        }
+    }
+}
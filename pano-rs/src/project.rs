@@ -0,0 +1,332 @@
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::adjustment::Adjustment;
+use crate::brush_preset::BrushPresetLibrary;
+use crate::error::PanoError;
+use crate::file_io::{read_binary, write_binary};
+use crate::pixel_format::PixelFormat;
+use crate::stereo::StereoLayout;
+
+// A .pano project is a zip container holding `manifest.json` and one png per layer,
+// or one exr per layer for float documents. Stereo documents have another one per layer for the right eye.
+// 1: the first format
+// 2: adjustment layers, whose image is the mask
+// 3: float documents, whose layers are exr
+// 4: stereo documents, whose layers have an image per eye
+pub const PROJECT_FORMAT_VERSION: u32 = 4;
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewState {
+    pub rotation_x: f32,
+    pub rotation_y: f32,
+    pub fov: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GridSettings {
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LayerManifest {
+    pub name: String,
+    // path of the png or exr inside the container, of the left eye in stereo documents
+    pub file: String,
+    pub right_eye_file: Option<String>,
+    pub visible: bool,
+    pub opacity: f32,
+    pub locked: bool,
+    pub blend_mode: String,
+    // the image is the mask of the adjustment for adjustment layers
+    pub adjustment: Option<Adjustment>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProjectManifest {
+    pub version: u32,
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    pub stereo: StereoLayout,
+    pub view: ViewState,
+    pub grid: GridSettings,
+    // layers[0] is the bottom of the stack
+    pub layers: Vec<LayerManifest>,
+    pub active_layer: usize,
+    #[serde(default)]
+    pub brush_presets: Option<BrushPresetLibrary>,
+}
+
+pub struct Project {
+    pub manifest: ProjectManifest,
+    // png or exr encoded, in the same order as `manifest.layers`
+    pub layer_images: Vec<Vec<u8>>,
+    // of the layers with `right_eye_file`, in the same order
+    pub right_eye_images: Vec<Vec<u8>>,
+}
+
+impl Project {
+    pub async fn save(&self, path: &Path) -> Result<(), PanoError> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        let manifest = serde_json::to_string_pretty(&self.manifest)
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+        writer
+            .start_file(
+                MANIFEST_NAME,
+                FileOptions::default().compression_method(CompressionMethod::Deflated),
+            )
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+        writer
+            .write_all(manifest.as_bytes())
+            .map_err(|e| PanoError::Encode(e.to_string()))?;
+
+        let right_eye_files = self
+            .manifest
+            .layers
+            .iter()
+            .filter_map(|layer| layer.right_eye_file.as_ref());
+        let files = self
+            .manifest
+            .layers
+            .iter()
+            .map(|layer| &layer.file)
+            .zip(self.layer_images.iter())
+            .chain(right_eye_files.zip(self.right_eye_images.iter()));
+        for (file, image) in files {
+            // png is already compressed
+            writer
+                .start_file(
+                    file.as_str(),
+                    FileOptions::default().compression_method(CompressionMethod::Stored),
+                )
+                .map_err(|e| PanoError::Encode(e.to_string()))?;
+            writer
+                .write_all(image)
+                .map_err(|e| PanoError::Encode(e.to_string()))?;
+        }
+
+        let data = writer
+            .finish()
+            .map_err(|e| PanoError::Encode(e.to_string()))?
+            .into_inner();
+        write_binary(path, data).await?;
+        Ok(())
+    }
+
+    pub async fn load(path: &Path) -> Result<Self, PanoError> {
+        let mut archive = ZipArchive::new(Cursor::new(read_binary(path).await?))
+            .map_err(|e| PanoError::Decode(e.to_string()))?;
+
+        let mut manifest = String::new();
+        archive
+            .by_name(MANIFEST_NAME)
+            .map_err(|e| PanoError::Decode(e.to_string()))?
+            .read_to_string(&mut manifest)
+            .map_err(|e| PanoError::Decode(e.to_string()))?;
+        let manifest: serde_json::Value =
+            serde_json::from_str(&manifest).map_err(|e| PanoError::Decode(e.to_string()))?;
+        let manifest: ProjectManifest = serde_json::from_value(migrate(manifest)?)
+            .map_err(|e| PanoError::Decode(e.to_string()))?;
+
+        let mut read_file = |file: &str| {
+            let mut image = vec![];
+            archive
+                .by_name(file)
+                .map_err(|e| PanoError::Decode(e.to_string()))?
+                .read_to_end(&mut image)
+                .map_err(|e| PanoError::Decode(e.to_string()))?;
+            Ok::<_, PanoError>(image)
+        };
+        let mut layer_images = vec![];
+        let mut right_eye_images = vec![];
+        for layer in manifest.layers.iter() {
+            layer_images.push(read_file(&layer.file)?);
+            if let Some(file) = layer.right_eye_file.as_ref() {
+                right_eye_images.push(read_file(file)?);
+            }
+        }
+
+        Ok(Project {
+            manifest,
+            layer_images,
+            right_eye_images,
+        })
+    }
+}
+
+// Upgrades a manifest written by an older version step by step.
+// Manifests from a newer version are refused, their layers may not be read correctly.
+fn migrate(mut manifest: serde_json::Value) -> Result<serde_json::Value, PanoError> {
+    let version = manifest
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .ok_or_else(|| PanoError::Decode("project manifest has no version".to_string()))?
+        as u32;
+
+    if version > PROJECT_FORMAT_VERSION {
+        return Err(PanoError::UnsupportedFormat(format!(
+            "プロジェクトのバージョン {} (このアプリは {} まで)",
+            version, PROJECT_FORMAT_VERSION
+        )));
+    }
+
+    if version < 2 {
+        // no adjustment layers
+        for layer in layers_mut(&mut manifest)? {
+            layer.insert("adjustment".to_string(), serde_json::Value::Null);
+        }
+    }
+    if version < 3 {
+        // 8 bit documents
+        manifest["format"] = serde_json::to_value(PixelFormat::Rgba8)
+            .map_err(|e| PanoError::Decode(e.to_string()))?;
+    }
+    if version < 4 {
+        // mono documents, without the images of the right eye
+        manifest["stereo"] = serde_json::to_value(StereoLayout::Mono)
+            .map_err(|e| PanoError::Decode(e.to_string()))?;
+        for layer in layers_mut(&mut manifest)? {
+            layer.insert("right_eye_file".to_string(), serde_json::Value::Null);
+        }
+    }
+
+    manifest["version"] = PROJECT_FORMAT_VERSION.into();
+    Ok(manifest)
+}
+
+fn layers_mut(
+    manifest: &mut serde_json::Value,
+) -> Result<impl Iterator<Item = &mut serde_json::Map<String, serde_json::Value>>, PanoError> {
+    let layers = manifest
+        .get_mut("layers")
+        .and_then(|layers| layers.as_array_mut())
+        .ok_or_else(|| PanoError::Decode("project manifest has no layers".to_string()))?;
+    Ok(layers.iter_mut().filter_map(|layer| layer.as_object_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::storage::{set_storage, storage, MemoryStorage};
+
+    fn layer(name: &str) -> LayerManifest {
+        LayerManifest {
+            name: name.to_string(),
+            file: format!("{}.png", name),
+            right_eye_file: None,
+            visible: true,
+            opacity: 0.5,
+            locked: false,
+            blend_mode: "normal".to_string(),
+            adjustment: None,
+        }
+    }
+
+    fn project() -> Project {
+        let mut stereo_layer = layer("stereo");
+        stereo_layer.right_eye_file = Some("stereo_right.png".to_string());
+        Project {
+            manifest: ProjectManifest {
+                version: PROJECT_FORMAT_VERSION,
+                width: 64,
+                height: 32,
+                format: PixelFormat::Rgba16F,
+                stereo: StereoLayout::TopBottom,
+                view: ViewState {
+                    rotation_x: 10.0,
+                    rotation_y: 20.0,
+                    fov: 60.0,
+                },
+                grid: GridSettings { enabled: true },
+                layers: vec![layer("background"), stereo_layer],
+                active_layer: 1,
+                brush_presets: Some(BrushPresetLibrary::default()),
+            },
+            layer_images: vec![vec![1, 2, 3], vec![4, 5]],
+            right_eye_images: vec![vec![6]],
+        }
+    }
+
+    // a project with `manifest`, whose layers have no images
+    fn write_manifest(path: &Path, manifest: &serde_json::Value) {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file(MANIFEST_NAME, FileOptions::default())
+            .unwrap();
+        writer.write_all(manifest.to_string().as_bytes()).unwrap();
+        for layer in manifest["layers"].as_array().unwrap() {
+            writer
+                .start_file(layer["file"].as_str().unwrap(), FileOptions::default())
+                .unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+        block_on(storage().write(path, data)).unwrap();
+    }
+
+    fn v1_manifest(version: u32) -> serde_json::Value {
+        serde_json::json!({
+            "version": version,
+            "width": 64,
+            "height": 32,
+            "view": {"rotation_x": 0.0, "rotation_y": 0.0, "fov": 60.0},
+            "grid": {"enabled": false},
+            "layers": [{
+                "name": "background",
+                "file": "background.png",
+                "visible": true,
+                "opacity": 1.0,
+                "locked": false,
+                "blend_mode": "normal"
+            }],
+            "active_layer": 0
+        })
+    }
+
+    #[test]
+    fn save_and_load() {
+        set_storage(Rc::new(MemoryStorage::default()));
+        let project = project();
+        let path = Path::new("projects/a.pano");
+        block_on(project.save(path)).unwrap();
+        let loaded = block_on(Project::load(path)).unwrap();
+        assert_eq!(loaded.manifest, project.manifest);
+        assert_eq!(loaded.layer_images, project.layer_images);
+        assert_eq!(loaded.right_eye_images, project.right_eye_images);
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        set_storage(Rc::new(MemoryStorage::default()));
+        let path = Path::new("v1.pano");
+        write_manifest(path, &v1_manifest(1));
+        let manifest = block_on(Project::load(path)).unwrap().manifest;
+        assert_eq!(manifest.version, PROJECT_FORMAT_VERSION);
+        assert_eq!(manifest.format, PixelFormat::Rgba8);
+        assert_eq!(manifest.stereo, StereoLayout::Mono);
+        assert_eq!(manifest.layers[0].adjustment, None);
+        assert_eq!(manifest.layers[0].right_eye_file, None);
+        assert_eq!(manifest.brush_presets, None);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        set_storage(Rc::new(MemoryStorage::default()));
+        let path = Path::new("newer.pano");
+        write_manifest(path, &v1_manifest(PROJECT_FORMAT_VERSION + 1));
+        assert!(matches!(
+            block_on(Project::load(path)),
+            Err(PanoError::UnsupportedFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+
+use crate::error::PanoError;
+use crate::project::Project;
+use crate::storage::storage;
+
+// Every running session, e.g. each tab, holds the lock `pano.session.<id>` with the time of
+// its last heartbeat and writes its own snapshot `recovery-<id>.pano`.
+// On a clean shutdown the lock is replaced with `pano.closed.<id>`, localStorage is written
+// synchronously unlike the storage, and the snapshot is removed on the next start.
+// A lock whose heartbeat stopped belongs to a session which crashed.
+const SESSION_LOCK_PREFIX: &str = "pano.session.";
+const SESSION_CLOSED_PREFIX: &str = "pano.closed.";
+
+pub const AUTOSAVE_INTERVAL_MS: i32 = 60 * 1000;
+pub const HEARTBEAT_INTERVAL_MS: i32 = 5 * 1000;
+// the locks of the other running sessions are younger than this
+const STALE_LOCK_MS: f64 = 6.0 * HEARTBEAT_INTERVAL_MS as f64;
+
+thread_local! {
+    static SESSION_ID: String = format!("{:016x}", rand::random::<u64>());
+    // the session can not continue, its snapshot is offered on the next start
+    static ABANDONED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn session_id() -> String {
+    SESSION_ID.with(|id| id.clone())
+}
+
+fn lock_key(id: &str) -> String {
+    format!("{}{}", SESSION_LOCK_PREFIX, id)
+}
+
+fn closed_key(id: &str) -> String {
+    format!("{}{}", SESSION_CLOSED_PREFIX, id)
+}
+
+// none when localStorage is not available, e.g. in some privacy modes
+fn local_storage() -> Option<web_sys::Storage> {
+    let local_storage = web_sys::window()?.local_storage().ok().flatten();
+    if local_storage.is_none() {
+        crate::console_log!("localStorage is not available, crashes are not detected");
+    }
+    local_storage
+}
+
+// (key, value) of the items whose key starts with `prefix`, without the prefix
+fn items(local_storage: &web_sys::Storage, prefix: &str) -> Vec<(String, String)> {
+    let length = local_storage.length().unwrap_or(0);
+    (0..length)
+        .filter_map(|idx| local_storage.key(idx).ok().flatten())
+        .filter_map(|key| {
+            let value = local_storage.get_item(&key).ok().flatten()?;
+            Some((key.strip_prefix(prefix)?.to_string(), value))
+        })
+        .collect()
+}
+
+// the most recent of the sessions whose heartbeat stopped
+fn crashed_session(locks: &[(String, f64)], now: f64) -> Option<String> {
+    locks
+        .iter()
+        .filter(|(_, heartbeat)| now - heartbeat > STALE_LOCK_MS)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(id, _)| id.clone())
+}
+
+// returns the id of a session which did not shut down cleanly
+pub fn start_session() -> Option<String> {
+    let local_storage = local_storage()?;
+
+    for (id, _) in items(&local_storage, SESSION_CLOSED_PREFIX) {
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(err) = discard_recovery(&id).await {
+                crate::console_log!("failed to discard the recovery file: {}", err);
+            }
+        });
+    }
+
+    // unreadable heartbeats are treated as stale
+    let locks: Vec<(String, f64)> = items(&local_storage, SESSION_LOCK_PREFIX)
+        .into_iter()
+        .map(|(id, heartbeat)| (id, heartbeat.parse().unwrap_or(0.0)))
+        .collect();
+    heartbeat();
+    crashed_session(&locks, js_sys::Date::now())
+}
+
+pub fn heartbeat() {
+    if ABANDONED.with(|abandoned| abandoned.get()) {
+        return;
+    }
+    if let Some(local_storage) = local_storage() {
+        let now = js_sys::Date::now().to_string();
+        if let Err(err) = local_storage.set_item(&lock_key(&session_id()), &now) {
+            crate::console_log!("failed to write the session lock: {:?}", err);
+        }
+    }
+}
+
+pub fn end_session() {
+    if !ABANDONED.with(|abandoned| abandoned.get()) {
+        close_session(&session_id());
+    }
+}
+
+// e.g. after the WebGL context is lost, the lock is made stale at once like the one of a crash
+pub fn abandon_session() {
+    ABANDONED.with(|abandoned| abandoned.set(true));
+    if let Some(local_storage) = local_storage() {
+        if let Err(err) = local_storage.set_item(&lock_key(&session_id()), "0") {
+            crate::console_log!("failed to write the session lock: {:?}", err);
+        }
+    }
+}
+
+// the snapshot of a closed session is removed on the next start
+pub fn close_session(id: &str) {
+    if let Some(local_storage) = local_storage() {
+        if let Err(err) = local_storage
+            .set_item(&closed_key(id), "")
+            .and_then(|_| local_storage.remove_item(&lock_key(id)))
+        {
+            crate::console_log!("failed to close the session: {:?}", err);
+        }
+    }
+}
+
+async fn recovery_path(id: &str) -> Result<PathBuf, PanoError> {
+    storage()
+        .app_data_path(&format!("recovery-{}.pano", id))
+        .await
+}
+
+pub async fn has_recovery(id: &str) -> bool {
+    match recovery_path(id).await {
+        Ok(path) => storage().exists(&path).await,
+        Err(_) => false,
+    }
+}
+
+// writes to a temporary file first, so a crash while saving keeps the previous snapshot
+pub async fn save_recovery(project: &Project) -> Result<(), PanoError> {
+    let id = session_id();
+    let path = recovery_path(&id).await?;
+    let temp_path = storage()
+        .app_data_path(&format!("recovery-{}.pano.tmp", id))
+        .await?;
+    project.save(&temp_path).await?;
+    storage().rename(&temp_path, &path).await
+}
+
+pub async fn load_recovery(id: &str) -> Result<Project, PanoError> {
+    Project::load(&recovery_path(id).await?).await
+}
+
+// removes the snapshot and forgets the session
+pub async fn discard_recovery(id: &str) -> Result<(), PanoError> {
+    let path = recovery_path(id).await?;
+    let storage = storage();
+    if storage.exists(&path).await {
+        storage.remove(&path).await?;
+    }
+    if let Some(local_storage) = local_storage() {
+        local_storage
+            .remove_item(&lock_key(id))
+            .and_then(|_| local_storage.remove_item(&closed_key(id)))
+            .map_err(|err| PanoError::Io(format!("{:?}", err)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_latest_stale_lock_is_the_crashed_session() {
+        let now = 100.0 * STALE_LOCK_MS;
+        let locks = vec![
+            ("old".to_string(), 0.0),
+            ("crashed".to_string(), now - 2.0 * STALE_LOCK_MS),
+            ("running".to_string(), now - 1.0),
+        ];
+        assert_eq!(crashed_session(&locks, now), Some("crashed".to_string()));
+        assert_eq!(crashed_session(&locks[2..], now), None);
+    }
+}
@@ -0,0 +1,79 @@
+#version 300 es
+#define PI 3.1415926535897932384626
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+// composite of the layers below (premultiplied alpha), as large as the target
+uniform sampler2D below;
+// a tile of the layer texture, its alpha is the strength of the adjustment
+uniform sampler2D mask;
+// part of the panorama in `mask`, (u0, v0, u1, v1)
+uniform vec4 mask_rect;
+// see Adjustment::lut
+uniform sampler2D lut;
+uniform float lut_size;
+uniform float opacity;
+// true with drawing_canvas.vert, false with tile_view.vert
+uniform bool on_canvas;
+// rotation_y_mat * rotation_x_mat of the view, used when on_canvas
+uniform mat3 rotation;
+// true for float documents, whose colors are linear and may exceed 1.0
+uniform bool linear_colors;
+
+vec3 apply_lut(vec3 rgb) {
+    vec3 position = clamp(rgb, 0.0, 1.0) * (lut_size - 1.0);
+    float slice0 = floor(position.b);
+    float slice1 = min(slice0 + 1.0, lut_size - 1.0);
+    vec2 rg = (position.rg + 0.5) / vec2(lut_size * lut_size, lut_size);
+    vec3 color0 = texture(lut, rg + vec2(slice0 / lut_size, 0.0)).rgb;
+    vec3 color1 = texture(lut, rg + vec2(slice1 / lut_size, 0.0)).rgb;
+    return mix(color0, color1, position.b - slice0);
+}
+
+vec3 linear_to_srgb(vec3 rgb) {
+    return mix(rgb * 12.92, 1.055 * pow(rgb, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, rgb));
+}
+
+vec3 srgb_to_linear(vec3 rgb) {
+    return mix(rgb / 12.92, pow((rgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, rgb));
+}
+
+// the lut is made for srgb colors in 0.0 - 1.0, so linear colors are converted and
+// the ones above 1.0 are scaled down and up again to keep their ratios
+vec3 adjust(vec3 rgb) {
+    if (!linear_colors) {
+        return apply_lut(rgb);
+    }
+    float scale = max(1.0, max(rgb.r, max(rgb.g, rgb.b)));
+    rgb = linear_to_srgb(clamp(rgb / scale, 0.0, 1.0));
+    return srgb_to_linear(apply_lut(rgb)) * scale;
+}
+
+void main(void) {
+    vec2 mask_coords;
+    if (on_canvas) {
+        vec3 pt = rotation * normalize(vec3(fragment_position.x, -fragment_position.y, 1.0));
+        float elevation = asin(clamp(pt.y, -1.0 + 1e-10, 1.0 - 1e-10));
+        float azimuth = sign(pt.x) * acos(clamp(pt.z / length(pt.xz), -1.0 + 1e-10, 1.0 - 1e-10));
+        mask_coords = (vec2(azimuth / PI, elevation / PI * 2.0) + 1.0) / 2.0;
+    } else {
+        mask_coords = (fragment_position + 1.0) / 2.0;
+    }
+
+    if (any(lessThan(mask_coords, mask_rect.xy)) || any(greaterThan(mask_coords, mask_rect.zw))) {
+        discard;
+    }
+    mask_coords = (mask_coords - mask_rect.xy) / (mask_rect.zw - mask_rect.xy);
+
+    color = texelFetch(below, ivec2(gl_FragCoord.xy), 0);
+    if (color.a <= 0.0) {
+        return;
+    }
+    vec3 rgb = color.rgb / color.a;
+    float strength = texture(mask, mask_coords).a * opacity;
+    rgb = mix(rgb, adjust(rgb), strength);
+    color = vec4(rgb * color.a, color.a);
+}
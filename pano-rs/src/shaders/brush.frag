@@ -9,13 +9,17 @@ in vec2 brush_position;
 
 uniform int point_num;
 uniform float point_offset;
+uniform vec4 brush_color;
+uniform float hardness;
 
 void main(void) {
-    color = vec4(1.0, 0.0, 0.0, 0.0);
+    float alpha = 0.0;
+    float feather = max(0.5 * (1.0 - hardness), 1e-5);
 
     for(int i = 0; i < point_num; i++) {
-        if (length(vec2(brush_position.x - (1.0 - point_offset) - float(i), brush_position.y)) < 0.5 ) {
-            color = vec4(1.0, 0.0, 0.0, 1.0);
-        }
+        float dist = length(vec2(brush_position.x - (1.0 - point_offset) - float(i), brush_position.y));
+        alpha = max(alpha, clamp((0.5 - dist) / feather, 0.0, 1.0));
     }
+
+    color = vec4(brush_color.rgb, brush_color.a * alpha);
 }
@@ -45,13 +45,15 @@ mat3 rotation_z(float rot) {
     );
 }
 
+// the tile drawn into, in the coordinates of all_view.vert, (x0, y0, x1, y1)
+uniform vec4 target_rect;
 uniform vec3 start_position;
 uniform vec3 end_position;
+uniform float width;
 
 out vec2 brush_position;
 
 void main(void) {
-    float width = 0.02;
     vec3 x1 = start_position / length(start_position);
     vec3 x2 = end_position / length(end_position);
 
@@ -92,6 +94,7 @@ void main(void) {
     float elevation = asin(target_position.y);
     float azimuth = sign(target_position.x) * acos(target_position.z / length(target_position.xz));
 
-    gl_Position = vec4(azimuth / PI, -elevation / PI * 2.0, 0.0, 1.0);
+    vec2 position = vec2(azimuth / PI, -elevation / PI * 2.0);
+    gl_Position = vec4((position - target_rect.xy) / (target_rect.zw - target_rect.xy) * 2.0 - 1.0, 0.0, 1.0);
     brush_position = vec2((dist + 2.0) * (0.5 * POSITIONS[INDICES[gl_VertexID]].x + 0.5) - 1.0, POSITIONS[INDICES[gl_VertexID]].y);
 }
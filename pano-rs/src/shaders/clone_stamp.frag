@@ -0,0 +1,66 @@
+#version 300 es
+#define PI 3.1415926535897932384626
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform sampler2D brush_tex;
+// part of the stroke in `brush_tex`, (u0, v0, u1, v1)
+uniform vec4 brush_rect;
+uniform sampler2D source_tex;
+// part of the panorama in `source_tex`, (u0, v0, u1, v1)
+uniform vec4 source_rect;
+uniform sampler2D mask_tex;
+uniform float rotation_x;
+uniform float rotation_y;
+uniform mat3 clone_rotation;
+
+vec2 to_tex_coords(vec3 pt) {
+    float elevation = asin(clamp(pt.y, -1.0 + 1e-10, 1.0 - 1e-10));
+    float azimuth = sign(pt.x) * acos(clamp(pt.z / length(pt.xz), -1.0 + 1e-10, 1.0 - 1e-10));
+
+    vec2 tex_coords = vec2(azimuth / PI, elevation / PI * 2.0);
+    return (tex_coords + 1.0) / 2.0;
+}
+
+void main(void) {
+    float azimuth = fragment_position.x * PI;
+    float elevation = fragment_position.y * PI / 2.0;
+
+    vec3 pt;
+    pt.x = cos(elevation) * sin(azimuth);
+    pt.y = sin(elevation);
+    pt.z = cos(elevation) * cos(azimuth);
+
+    float rotation_x_ = rotation_x / 180.0 * PI;
+    float rotation_y_ = rotation_y / 180.0 * PI;
+    mat3 rotation_x_mat = mat3(
+        vec3(1, 0.0, 0.0),
+        vec3(0.0, cos(rotation_x_), -sin(rotation_x_)),
+        vec3(0.0, sin(rotation_x_), cos(rotation_x_))
+    );
+    mat3 rotation_y_mat = mat3(
+        vec3(cos(rotation_y_), 0.0, sin(rotation_y_)),
+        vec3(0.0, 1.0, 0.0),
+        vec3(-sin(rotation_y_), 0.0, cos(rotation_y_))
+    );
+    mat3 rotation = rotation_y_mat * rotation_x_mat;
+
+    // the brush stroke is drawn in view space, so only its alpha is used as a mask
+    vec2 brush_coords = to_tex_coords(pt * rotation);
+    if (any(lessThan(brush_coords, brush_rect.xy)) || any(greaterThan(brush_coords, brush_rect.zw))) {
+        discard;
+    }
+    float mask = texture(brush_tex, (brush_coords - brush_rect.xy) / (brush_rect.zw - brush_rect.xy)).a;
+    mask *= texture(mask_tex, (fragment_position + 1.0) / 2.0).r;
+
+    // the source is sampled on the sphere rotated from this point to the clone source
+    vec2 source_coords = to_tex_coords(clone_rotation * pt);
+    if (any(lessThan(source_coords, source_rect.xy)) || any(greaterThan(source_coords, source_rect.zw))) {
+        discard;
+    }
+    color = texture(source_tex, (source_coords - source_rect.xy) / (source_rect.zw - source_rect.xy));
+    color.a *= mask;
+}
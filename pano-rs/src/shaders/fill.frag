@@ -0,0 +1,14 @@
+#version 300 es
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform sampler2D mask_tex;
+uniform vec4 fill_color;
+
+void main(void) {
+    float mask = texture(mask_tex, (fragment_position + 1.0) / 2.0).r;
+    color = vec4(fill_color.rgb, fill_color.a * mask);
+}
@@ -0,0 +1,14 @@
+#version 300 es
+
+precision highp float;
+
+in vec2 tile_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float opacity;
+
+void main(void) {
+    color = texture(tex, tile_coords);
+    color = vec4(color.rgb * color.a, color.a) * opacity;
+}
@@ -7,6 +7,9 @@ in vec2 fragment_position;
 out vec4 color;
 
 uniform sampler2D tex;
+// part of the panorama in `tex`, (u0, v0, u1, v1)
+uniform vec4 source_rect;
+uniform sampler2D mask_tex;
 uniform float rotation_x;
 uniform float rotation_y;
 
@@ -40,5 +43,9 @@ void main(void) {
     vec2 tex_coords = vec2(azimuth / PI, elevation / PI * 2.0);
     tex_coords = (tex_coords + 1.0) / 2.0;
 
-    color = texture(tex, tex_coords);
+    if (any(lessThan(tex_coords, source_rect.xy)) || any(greaterThan(tex_coords, source_rect.zw))) {
+        discard;
+    }
+    color = texture(tex, (tex_coords - source_rect.xy) / (source_rect.zw - source_rect.xy));
+    color.a *= texture(mask_tex, (fragment_position + 1.0) / 2.0).r;
 }
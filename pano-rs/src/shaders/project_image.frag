@@ -0,0 +1,35 @@
+#version 300 es
+#define PI 3.1415926535897932384626
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform sampler2D mask_tex;
+// from the sphere to the space of the image plane (z = 1, y is down)
+uniform mat3 rotation;
+uniform vec2 half_size;
+
+void main(void) {
+    float azimuth = fragment_position.x * PI;
+    float elevation = fragment_position.y * PI / 2.0;
+
+    vec3 pt;
+    pt.x = cos(elevation) * sin(azimuth);
+    pt.y = sin(elevation);
+    pt.z = cos(elevation) * cos(azimuth);
+
+    pt = rotation * pt;
+    color = vec4(0.0);
+    if (pt.z <= 0.0) {
+        return;
+    }
+    vec2 tex_coords = (pt.xy / pt.z / half_size + 1.0) / 2.0;
+    if (any(lessThan(tex_coords, vec2(0.0))) || any(greaterThan(tex_coords, vec2(1.0)))) {
+        return;
+    }
+    color = texture(tex, tex_coords);
+    color.a *= texture(mask_tex, (fragment_position + 1.0) / 2.0).r;
+}
@@ -0,0 +1,29 @@
+#version 300 es
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float opacity;
+// from the view space to the space of the image plane (z = 1)
+uniform mat3 rotation;
+uniform vec2 center;
+uniform vec2 half_size;
+
+void main(void) {
+    // y is down, as in show_panorama.frag
+    vec3 pt = rotation * vec3(fragment_position.x, -fragment_position.y, 1.0);
+    color = vec4(0.0);
+    if (pt.z <= 0.0) {
+        return;
+    }
+    vec2 tex_coords = ((pt.xy / pt.z - center) / half_size + 1.0) / 2.0;
+    if (any(lessThan(tex_coords, vec2(0.0))) || any(greaterThan(tex_coords, vec2(1.0)))) {
+        return;
+    }
+    color = texture(tex, tex_coords);
+    // premultiplied, drawn with the normal blend mode
+    color = vec4(color.rgb * color.a, color.a) * opacity;
+}
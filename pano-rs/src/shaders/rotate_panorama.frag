@@ -0,0 +1,37 @@
+#version 300 es
+#define PI 3.1415926535897932384626
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform sampler2D tex;
+// part of the panorama in `tex`, (u0, v0, u1, v1)
+uniform vec4 source_rect;
+// carries a direction of the output to the direction sampled from `tex`
+uniform mat3 rotation;
+
+vec2 to_tex_coords(vec3 pt) {
+    float elevation = asin(clamp(pt.y, -1.0 + 1e-10, 1.0 - 1e-10));
+    float azimuth = sign(pt.x) * acos(clamp(pt.z / length(pt.xz), -1.0 + 1e-10, 1.0 - 1e-10));
+
+    vec2 tex_coords = vec2(azimuth / PI, elevation / PI * 2.0);
+    return (tex_coords + 1.0) / 2.0;
+}
+
+void main(void) {
+    float azimuth = fragment_position.x * PI;
+    float elevation = fragment_position.y * PI / 2.0;
+
+    vec3 pt;
+    pt.x = cos(elevation) * sin(azimuth);
+    pt.y = sin(elevation);
+    pt.z = cos(elevation) * cos(azimuth);
+
+    vec2 tex_coords = to_tex_coords(rotation * pt);
+    if (any(lessThan(tex_coords, source_rect.xy)) || any(greaterThan(tex_coords, source_rect.zw))) {
+        discard;
+    }
+    color = texture(tex, (tex_coords - source_rect.xy) / (source_rect.zw - source_rect.xy));
+}
@@ -0,0 +1,25 @@
+#version 300 es
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+// a tile of the layer
+uniform sampler2D tex;
+// part of the panorama in `tex`, (u0, v0, u1, v1)
+uniform vec4 tile_rect;
+// the picked color
+uniform vec4 target_color;
+uniform float tolerance;
+
+void main(void) {
+    vec2 tex_coords = (fragment_position + 1.0) / 2.0;
+    if (any(lessThan(tex_coords, tile_rect.xy)) || any(greaterThan(tex_coords, tile_rect.zw))) {
+        discard;
+    }
+    tex_coords = (tex_coords - tile_rect.xy) / (tile_rect.zw - tile_rect.xy);
+    vec4 diff = abs(texture(tex, tex_coords) - target_color);
+    float dist = max(max(diff.r, diff.g), max(diff.b, diff.a));
+    color = dist <= tolerance ? vec4(1.0) : vec4(0.0, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 300 es
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform sampler2D mask_tex;
+
+void main(void) {
+    float mask = texture(mask_tex, (fragment_position + 1.0) / 2.0).r;
+    color = vec4(vec3(1.0 - mask), 1.0);
+}
@@ -0,0 +1,64 @@
+#version 300 es
+#define PI 3.1415926535897932384626
+#define MAX_POINTS 256
+
+precision highp float;
+
+in vec2 fragment_position;
+out vec4 color;
+
+uniform float rotation_x;
+uniform float rotation_y;
+uniform float fov;
+// polygon on the canvas, (-1, -1) is the top left corner
+uniform vec2 points[MAX_POINTS];
+uniform int point_count;
+
+void main(void) {
+    float azimuth = fragment_position.x * PI;
+    float elevation = fragment_position.y * PI / 2.0;
+
+    vec3 pt;
+    pt.x = cos(elevation) * sin(azimuth);
+    pt.y = sin(elevation);
+    pt.z = cos(elevation) * cos(azimuth);
+
+    float rotation_x_ = rotation_x / 180.0 * PI;
+    float rotation_y_ = rotation_y / 180.0 * PI;
+    mat3 rotation_x_mat = mat3(
+        vec3(1, 0.0, 0.0),
+        vec3(0.0, cos(rotation_x_), -sin(rotation_x_)),
+        vec3(0.0, sin(rotation_x_), cos(rotation_x_))
+    );
+    mat3 rotation_y_mat = mat3(
+        vec3(cos(rotation_y_), 0.0, sin(rotation_y_)),
+        vec3(0.0, 1.0, 0.0),
+        vec3(-sin(rotation_y_), 0.0, cos(rotation_y_))
+    );
+    mat3 rotation = rotation_y_mat * rotation_x_mat;
+    pt = pt * rotation;
+
+    color = vec4(0.0, 0.0, 0.0, 1.0);
+    // behind the camera
+    if (pt.z <= 0.0) {
+        return;
+    }
+    vec2 screen = pt.xy / pt.z / tan(fov / 2.0 / 180.0 * PI);
+
+    // even-odd rule
+    bool inside = false;
+    for (int i = 0; i < MAX_POINTS; i++) {
+        if (i >= point_count) {
+            break;
+        }
+        vec2 a = points[i];
+        vec2 b = points[i == point_count - 1 ? 0 : i + 1];
+        if ((a.y > screen.y) != (b.y > screen.y)
+            && screen.x < (b.x - a.x) * (screen.y - a.y) / (b.y - a.y) + a.x) {
+            inside = !inside;
+        }
+    }
+    if (inside) {
+        color = vec4(1.0);
+    }
+}
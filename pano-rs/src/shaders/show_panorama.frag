@@ -7,8 +7,28 @@ in vec2 fragment_position;
 out vec4 color;
 
 uniform sampler2D tex;
+// part of the panorama in `tex`, (u0, v0, u1, v1)
+uniform vec4 tile_rect;
 uniform float rotation_x;
 uniform float rotation_y;
+uniform float opacity;
+
+// see DisplaySettings
+uniform float exposure;
+uniform int tone_mapping;
+uniform float gamma;
+
+vec3 display_color(vec3 rgb) {
+    rgb *= exp2(exposure);
+    if (tone_mapping == 1) {
+        // Reinhard
+        rgb = rgb / (1.0 + rgb);
+    } else if (tone_mapping == 2) {
+        // Narkowicz's fit of the ACES filmic curve
+        rgb = clamp((rgb * (2.51 * rgb + 0.03)) / (rgb * (2.43 * rgb + 0.59) + 0.14), 0.0, 1.0);
+    }
+    return pow(max(rgb, 0.0), vec3(1.0 / gamma));
+}
 
 void main(void) {
     vec3 pt = vec3(fragment_position.x, -fragment_position.y, 1.0);
@@ -35,5 +55,11 @@ void main(void) {
     vec2 tex_coords = vec2(azimuth / PI, elevation / PI * 2.0);
     tex_coords = (tex_coords + 1.0) / 2.0;
 
-    color = texture(tex, tex_coords);
+    if (any(lessThan(tex_coords, tile_rect.xy)) || any(greaterThan(tex_coords, tile_rect.zw))) {
+        discard;
+    }
+    color = texture(tex, (tex_coords - tile_rect.xy) / (tile_rect.zw - tile_rect.xy));
+    color.rgb = display_color(color.rgb);
+    // premultiplied alpha for the layer blend modes
+    color = vec4(color.rgb * color.a, color.a) * opacity;
 }
@@ -0,0 +1,32 @@
+#version 300 es
+
+precision highp float;
+
+out vec4 color;
+
+// premultiplied alpha, as large as the target
+uniform sampler2D tex;
+
+// see DisplaySettings
+uniform float exposure;
+uniform int tone_mapping;
+uniform float gamma;
+
+vec3 display_color(vec3 rgb) {
+    rgb *= exp2(exposure);
+    if (tone_mapping == 1) {
+        // Reinhard
+        rgb = rgb / (1.0 + rgb);
+    } else if (tone_mapping == 2) {
+        // Narkowicz's fit of the ACES filmic curve
+        rgb = clamp((rgb * (2.51 * rgb + 0.03)) / (rgb * (2.43 * rgb + 0.59) + 0.14), 0.0, 1.0);
+    }
+    return pow(max(rgb, 0.0), vec3(1.0 / gamma));
+}
+
+void main(void) {
+    color = texelFetch(tex, ivec2(gl_FragCoord.xy), 0);
+    if (color.a > 0.0) {
+        color.rgb = display_color(color.rgb / color.a) * color.a;
+    }
+}
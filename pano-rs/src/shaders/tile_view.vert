@@ -0,0 +1,27 @@
+#version 300 es
+
+const vec2[4] POSITIONS = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(-1.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, -1.0)
+);
+const int[6] INDICES = int[](
+    0, 1, 2,
+    2, 3, 0
+);
+
+// the tile in the coordinates of all_view.vert, (x0, y0, x1, y1)
+uniform vec4 target_rect;
+
+// the same as all_view.vert, over the tile only
+out vec2 fragment_position;
+// texture coordinates in the tile
+out vec2 tile_coords;
+
+void main(void) {
+    vec2 position = POSITIONS[INDICES[gl_VertexID]];
+    gl_Position = vec4(position, 0.0, 1.0);
+    tile_coords = (position + 1.0) / 2.0;
+    fragment_position = mix(target_rect.xy, target_rect.zw, tile_coords);
+}
@@ -0,0 +1,290 @@
+// Rotation helpers that mirror the conventions used in the shaders.
+// Matrices are row-major; use `to_gl_matrix` before passing them as uniforms.
+
+pub type Vec3 = (f32, f32, f32);
+pub type Mat3 = [[f32; 3]; 3];
+
+pub const IDENTITY: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+pub fn normalize(v: Vec3) -> Vec3 {
+    let length = (v.0 * v.0 + v.1 * v.1 + v.2 * v.2).sqrt();
+    (v.0 / length, v.1 / length, v.2 / length)
+}
+
+pub fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+pub fn mul_vec(m: &Mat3, v: Vec3) -> Vec3 {
+    (
+        m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+        m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+        m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+    )
+}
+
+pub fn mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+    }
+    m
+}
+
+pub fn transpose(m: &Mat3) -> Mat3 {
+    [
+        [m[0][0], m[1][0], m[2][0]],
+        [m[0][1], m[1][1], m[2][1]],
+        [m[0][2], m[1][2], m[2][2]],
+    ]
+}
+
+// column-major, as expected by uniformMatrix3fv without transpose
+pub fn to_gl_matrix(m: &Mat3) -> [f32; 9] {
+    [
+        m[0][0], m[1][0], m[2][0], m[0][1], m[1][1], m[2][1], m[0][2], m[1][2], m[2][2],
+    ]
+}
+
+// same as `rotation_x_mat` in the shaders (degree)
+pub fn rotation_x(degree: f32) -> Mat3 {
+    let (s, c) = (degree / 180.0 * std::f32::consts::PI).sin_cos();
+    [[1.0, 0.0, 0.0], [0.0, c, s], [0.0, -s, c]]
+}
+
+// same as `rotation_y_mat` in the shaders (degree)
+pub fn rotation_y(degree: f32) -> Mat3 {
+    let (s, c) = (degree / 180.0 * std::f32::consts::PI).sin_cos();
+    [[c, 0.0, -s], [0.0, 1.0, 0.0], [s, 0.0, c]]
+}
+
+// same as `rotation_z` in brush.vert (degree)
+pub fn rotation_z(degree: f32) -> Mat3 {
+    let (s, c) = (degree / 180.0 * std::f32::consts::PI).sin_cos();
+    [[c, s, 0.0], [-s, c, 0.0], [0.0, 0.0, 1.0]]
+}
+
+// rotation of the whole panorama by yaw (around y), pitch (around x) and roll (around z) in degree
+pub fn yaw_pitch_roll(yaw: f32, pitch: f32, roll: f32) -> Mat3 {
+    mul(
+        &self::rotation_y(yaw),
+        &mul(&self::rotation_x(pitch), &self::rotation_z(roll)),
+    )
+}
+
+// `rotation_y_mat * rotation_x_mat` of show_panorama.frag
+pub fn view_rotation(rotation_x: f32, rotation_y: f32) -> Mat3 {
+    mul(&self::rotation_y(rotation_y), &self::rotation_x(rotation_x))
+}
+
+// rotation around `axis` by `angle` (radian)
+pub fn axis_angle(axis: Vec3, angle: f32) -> Mat3 {
+    let (x, y, z) = normalize(axis);
+    let (s, c) = angle.sin_cos();
+    let t = 1.0 - c;
+    [
+        [t * x * x + c, t * x * y - s * z, t * x * z + s * y],
+        [t * x * y + s * z, t * y * y + c, t * y * z - s * x],
+        [t * x * z - s * y, t * y * z + s * x, t * z * z + c],
+    ]
+}
+
+// shortest rotation which carries `from` onto `to`
+pub fn rotation_between(from: Vec3, to: Vec3) -> Mat3 {
+    let from = normalize(from);
+    let to = normalize(to);
+    let axis = cross(from, to);
+    let cos = dot(from, to).clamp(-1.0, 1.0);
+    if dot(axis, axis) < 1e-12 {
+        if cos > 0.0 {
+            return IDENTITY;
+        }
+        // opposite directions: any perpendicular axis works
+        let helper = if from.0.abs() < 0.9 {
+            (1.0, 0.0, 0.0)
+        } else {
+            (0.0, 1.0, 0.0)
+        };
+        return axis_angle(cross(from, helper), std::f32::consts::PI);
+    }
+    axis_angle(axis, cos.acos())
+}
+
+// direction on the panorama sphere under the canvas point (offset_x, offset_y)
+pub fn screen_to_direction(
+    offset_x: f32,
+    offset_y: f32,
+    canvas_size: f32,
+    fov: f32,
+    rotation_x: f32,
+    rotation_y: f32,
+) -> Vec3 {
+    let factor = (fov / 2.0 / 180.0 * std::f32::consts::PI).tan();
+    let local = normalize((
+        (2.0 * offset_x / canvas_size - 1.0) * factor,
+        (2.0 * offset_y / canvas_size - 1.0) * factor,
+        1.0,
+    ));
+    mul_vec(&view_rotation(rotation_x, rotation_y), local)
+}
+
+// texture coordinates (0.0 - 1.0) of a direction in the equirectangular textures
+pub fn direction_to_tex_coords(direction: Vec3) -> (f32, f32) {
+    let (x, y, z) = normalize(direction);
+    let azimuth = x.atan2(z);
+    let elevation = y.clamp(-1.0, 1.0).asin();
+    (
+        (azimuth / std::f32::consts::PI + 1.0) / 2.0,
+        (elevation / std::f32::consts::FRAC_PI_2 + 1.0) / 2.0,
+    )
+}
+
+// rotation which puts the great circle through `a` and `b` on the horizon
+pub fn level_horizon(a: Vec3, b: Vec3) -> Mat3 {
+    let mut normal = normalize(cross(a, b));
+    if normal.1 < 0.0 {
+        normal = (-normal.0, -normal.1, -normal.2);
+    }
+    rotation_between(normal, (0.0, 1.0, 0.0))
+}
+
+// rotation which makes the great circle through `a` and `b` vertical, keeping its heading
+pub fn level_vertical(a: Vec3, b: Vec3) -> Mat3 {
+    let normal = normalize(cross(a, b));
+    if normal.0 * normal.0 + normal.2 * normal.2 < 1e-12 {
+        // the points are on the horizon, any heading is vertical
+        return IDENTITY;
+    }
+    rotation_between(normal, normalize((normal.0, 0.0, normal.2)))
+}
+
+// frame whose z axis is `direction` and whose y axis is the down direction of the view
+// projected on the tangent plane, i.e. a plane at `direction` facing the camera
+pub fn tangent_frame(direction: Vec3, rotation_x: f32, rotation_y: f32) -> Mat3 {
+    let z = normalize(direction);
+    let view = view_rotation(rotation_x, rotation_y);
+    let down = (view[0][1], view[1][1], view[2][1]);
+    let d = dot(down, z);
+    let y = normalize((down.0 - d * z.0, down.1 - d * z.1, down.2 - d * z.2));
+    let x = cross(y, z);
+    // columns are the axes
+    [[x.0, y.0, z.0], [x.1, y.1, z.1], [x.2, y.2, z.2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_vec_eq(a: Vec3, b: Vec3) {
+        let d = (a.0 - b.0, a.1 - b.1, a.2 - b.2);
+        assert!(dot(d, d).sqrt() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_mat_eq(a: &Mat3, b: &Mat3) {
+        for i in 0..3 {
+            assert_vec_eq((a[i][0], a[i][1], a[i][2]), (b[i][0], b[i][1], b[i][2]));
+        }
+    }
+
+    #[test]
+    fn rotations_are_orthonormal() {
+        for m in [
+            rotation_x(30.0),
+            rotation_y(-75.0),
+            rotation_z(120.0),
+            yaw_pitch_roll(10.0, 20.0, 30.0),
+            axis_angle((1.0, 2.0, 3.0), 1.0),
+        ] {
+            assert_mat_eq(&mul(&m, &transpose(&m)), &IDENTITY);
+        }
+    }
+
+    #[test]
+    fn matrix_helpers_follow_the_shader_conventions() {
+        assert_mat_eq(&yaw_pitch_roll(0.0, 0.0, 0.0), &IDENTITY);
+        assert_mat_eq(&yaw_pitch_roll(40.0, 0.0, 0.0), &rotation_y(40.0));
+        assert_mat_eq(&view_rotation(20.0, 40.0), &yaw_pitch_roll(40.0, 20.0, 0.0));
+        // the shaders rotate by the negated angle of `axis_angle`
+        assert_mat_eq(
+            &rotation_y(90.0),
+            &axis_angle((0.0, 1.0, 0.0), -std::f32::consts::FRAC_PI_2),
+        );
+        let m = yaw_pitch_roll(10.0, 20.0, 30.0);
+        let gl = to_gl_matrix(&m);
+        for (i, row) in m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                assert_eq!(gl[j * 3 + i], *value);
+            }
+        }
+        let v = (0.3, -0.2, 0.9);
+        assert_vec_eq(
+            mul_vec(&mul(&rotation_x(30.0), &rotation_y(60.0)), v),
+            mul_vec(&rotation_x(30.0), mul_vec(&rotation_y(60.0), v)),
+        );
+    }
+
+    #[test]
+    fn rotation_between_maps_from_onto_to() {
+        let directions = [
+            (1.0, 0.0, 0.0),
+            (0.0, 1.0, 0.0),
+            (0.0, 0.0, -2.0),
+            (0.3, -0.5, 0.8),
+            (-1.0, 0.0, 0.0),
+        ];
+        for from in directions {
+            for to in directions {
+                let m = rotation_between(from, to);
+                assert_vec_eq(mul_vec(&m, normalize(from)), normalize(to));
+                assert_mat_eq(&mul(&m, &transpose(&m)), &IDENTITY);
+            }
+        }
+    }
+
+    #[test]
+    fn level_horizon_removes_pitch_and_roll() {
+        let tilt = yaw_pitch_roll(25.0, 12.0, -8.0);
+        let a = mul_vec(&tilt, (1.0, 0.0, 0.0));
+        let b = mul_vec(&tilt, normalize((1.0, 0.0, 1.0)));
+        let leveled = mul(&level_horizon(a, b), &tilt);
+        // only a yaw is left, the up direction is kept
+        assert_vec_eq(mul_vec(&leveled, (0.0, 1.0, 0.0)), (0.0, 1.0, 0.0));
+        assert!(mul_vec(&level_horizon(a, b), a).1.abs() < 1e-4);
+        assert!(mul_vec(&level_horizon(b, a), b).1.abs() < 1e-4);
+    }
+
+    #[test]
+    fn level_vertical_keeps_the_heading() {
+        // a vertical line at the heading of 30 degree, rolled by 10 degree
+        let tilt = mul(&rotation_y(30.0), &rotation_z(10.0));
+        let a = mul_vec(&tilt, normalize((0.0, 1.0, 1.0)));
+        let b = mul_vec(&tilt, normalize((0.0, -1.0, 1.0)));
+        let m = level_vertical(a, b);
+        let normal = normalize(cross(mul_vec(&m, a), mul_vec(&m, b)));
+        assert!(normal.1.abs() < 1e-4);
+        let before = normalize(cross(a, b));
+        assert_vec_eq(
+            normalize((normal.0, 0.0, normal.2)),
+            normalize((before.0, 0.0, before.2)),
+        );
+        assert_mat_eq(&level_vertical((1.0, 0.0, 0.0), (0.0, 0.0, 1.0)), &IDENTITY);
+    }
+
+    #[test]
+    fn tex_coords_of_the_axes() {
+        let (u, v) = direction_to_tex_coords((0.0, 0.0, 1.0));
+        assert_vec_eq((u, v, 0.0), (0.5, 0.5, 0.0));
+        assert!((direction_to_tex_coords((0.0, 1.0, 0.0)).1 - 1.0).abs() < 1e-4);
+        assert!((direction_to_tex_coords((1.0, 0.0, 0.0)).0 - 0.75).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+// how the panoramas of the two eyes are packed in one image on import and export.
+// stereo documents hold a texture per eye in every layer.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum StereoLayout {
+    #[default]
+    Mono,
+    // the left eye above the right eye
+    TopBottom,
+    // the left eye on the left of the right eye
+    SideBySide,
+}
+
+impl StereoLayout {
+    pub const ALL: [StereoLayout; 3] = [
+        StereoLayout::Mono,
+        StereoLayout::TopBottom,
+        StereoLayout::SideBySide,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StereoLayout::Mono => "なし",
+            StereoLayout::TopBottom => "上下 (トップボトム)",
+            StereoLayout::SideBySide => "左右 (サイドバイサイド)",
+        }
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        *self != StereoLayout::Mono
+    }
+
+    // size of the packed image of eyes of `width` x `height`
+    pub fn packed_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            StereoLayout::Mono => (width, height),
+            StereoLayout::TopBottom => (width, height * 2),
+            StereoLayout::SideBySide => (width * 2, height),
+        }
+    }
+
+    // (x, y, width, height) of `eye` in a packed image of `width` x `height`
+    pub fn eye_rect(&self, eye: Eye, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let second = eye == Eye::Right;
+        match self {
+            StereoLayout::Mono => (0, 0, width, height),
+            StereoLayout::TopBottom => {
+                let height = height / 2;
+                (0, if second { height } else { 0 }, width, height)
+            }
+            StereoLayout::SideBySide => {
+                let width = width / 2;
+                (if second { width } else { 0 }, 0, width, height)
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub const ALL: [Eye; 2] = [Eye::Left, Eye::Right];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Eye::Left => "左目",
+            Eye::Right => "右目",
+        }
+    }
+
+    pub fn other(&self) -> Eye {
+        match self {
+            Eye::Left => Eye::Right,
+            Eye::Right => Eye::Left,
+        }
+    }
+
+    // red for the left eye and cyan for the right eye in the anaglyph, (r, g, b, a) of colorMask
+    pub fn anaglyph_mask(&self) -> [bool; 4] {
+        match self {
+            Eye::Left => [true, false, false, true],
+            Eye::Right => [false, true, true, false],
+        }
+    }
+
+    // degree added to rotation_y to put a stroke of this eye on the other eye, positive
+    // disparities shift the stroke to the left in the right eye, which brings it closer
+    pub fn other_eye_rotation(&self, disparity: f32) -> f32 {
+        match self {
+            Eye::Left => disparity,
+            Eye::Right => -disparity,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum StereoPaintMode {
+    CurrentEye,
+    BothEyes,
+}
+
+impl StereoPaintMode {
+    pub const ALL: [StereoPaintMode; 2] = [StereoPaintMode::CurrentEye, StereoPaintMode::BothEyes];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StereoPaintMode::CurrentEye => "表示中の目",
+            StereoPaintMode::BothEyes => "両目",
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use js_sys::Uint8Array;
+use wasm_bindgen::JsValue;
+
+use crate::error::PanoError;
+
+// where the files are read and written. the app uses the file system of Electron or, in the
+// standalone browser build, IndexedDB. native builds (e.g. pano-cli) use std::fs and tests can
+// use the memory.
+// the methods are async as some backends are, e.g. the IPC of Electron.
+pub trait Storage {
+    fn read<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<u8>, PanoError>>;
+
+    fn write<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>>;
+
+    // names of the files and directories in `path`, sorted
+    fn list_dir<'a>(&'a self, path: &'a Path)
+        -> LocalBoxFuture<'a, Result<Vec<String>, PanoError>>;
+
+    fn exists<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool>;
+
+    // false for a missing path
+    fn is_dir<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool>;
+
+    // replaces `to` when it exists
+    fn rename<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>>;
+
+    fn remove<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<(), PanoError>>;
+
+    // where the app keeps its own file `name`, e.g. the brush presets and the recovery snapshot
+    fn app_data_path<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LocalBoxFuture<'a, Result<PathBuf, PanoError>> {
+        Box::pin(async move { Ok(PathBuf::from(name)) })
+    }
+}
+
+thread_local! {
+    static STORAGE: RefCell<Rc<dyn Storage>> = RefCell::new(default_storage());
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_storage() -> Rc<dyn Storage> {
+    if crate::wasm_bind::is_electron() {
+        Rc::new(ElectronStorage)
+    } else {
+        Rc::new(BrowserStorage)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_storage() -> Rc<dyn Storage> {
+    Rc::new(NativeStorage)
+}
+
+// the storage file_io reads and writes
+pub fn storage() -> Rc<dyn Storage> {
+    STORAGE.with(|storage| storage.borrow().clone())
+}
+
+pub fn set_storage(storage: Rc<dyn Storage>) {
+    STORAGE.with(|current| *current.borrow_mut() = storage);
+}
+
+fn io_error(path: &Path, error: impl std::fmt::Debug) -> PanoError {
+    PanoError::Io(format!("{}: {:?}", path.display(), error))
+}
+
+fn path_str(path: &Path) -> Result<&str, PanoError> {
+    path.to_str()
+        .ok_or_else(|| PanoError::Io(format!("{}", path.display())))
+}
+
+// node's fs through ../fs.js
+pub struct ElectronStorage;
+
+impl Storage for ElectronStorage {
+    fn read<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<u8>, PanoError>> {
+        Box::pin(async move {
+            let buffer =
+                crate::wasm_bind::read_file(path_str(path)?).map_err(|e| io_error(path, e))?;
+            Ok(Uint8Array::new_with_byte_offset_and_length(
+                &buffer.buffer(),
+                buffer.byte_offset(),
+                buffer.length(),
+            )
+            .to_vec())
+        })
+    }
+
+    fn write<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            let array = Uint8Array::new_with_length(data.len() as u32);
+            array.copy_from(data.as_slice());
+            crate::wasm_bind::write_file(path_str(path)?, &array).map_err(|e| io_error(path, e))
+        })
+    }
+
+    fn list_dir<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>, PanoError>> {
+        Box::pin(async move {
+            let names =
+                crate::wasm_bind::read_dir(path_str(path)?).map_err(|e| io_error(path, e))?;
+            let mut names: Vec<String> = names.iter().filter_map(|name| name.as_string()).collect();
+            names.sort();
+            Ok(names)
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move { path.to_str().is_some_and(crate::wasm_bind::exists) })
+    }
+
+    fn is_dir<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move {
+            match path.to_str() {
+                Some(path) => crate::wasm_bind::is_directory(path).await.unwrap_or(false),
+                None => false,
+            }
+        })
+    }
+
+    fn rename<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            crate::wasm_bind::rename_file(path_str(from)?, path_str(to)?)
+                .map_err(|e| io_error(from, e))
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            crate::wasm_bind::remove_file(path_str(path)?).map_err(|e| io_error(path, e))
+        })
+    }
+
+    // under the userData of Electron instead of the working directory
+    fn app_data_path<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LocalBoxFuture<'a, Result<PathBuf, PanoError>> {
+        Box::pin(async move {
+            let dir = crate::wasm_bind::user_data_path()
+                .await
+                .map_err(|e| PanoError::Io(format!("userData: {:?}", e)))?;
+            Ok(Path::new(&dir).join(name))
+        })
+    }
+}
+
+// IndexedDB and the memory of the standalone browser build through ../browser_fs.js
+pub struct BrowserStorage;
+
+async fn await_promise(path: &Path, promise: js_sys::Promise) -> Result<JsValue, PanoError> {
+    wasm_bindgen_futures::JsFuture::from(promise)
+        .await
+        .map_err(|e| io_error(path, e))
+}
+
+impl Storage for BrowserStorage {
+    fn read<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<u8>, PanoError>> {
+        Box::pin(async move {
+            let data =
+                await_promise(path, crate::wasm_bind::browser_read_file(path_str(path)?)).await?;
+            Ok(Uint8Array::new(&data).to_vec())
+        })
+    }
+
+    fn write<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            let array = Uint8Array::new_with_length(data.len() as u32);
+            array.copy_from(data.as_slice());
+            let promise = crate::wasm_bind::browser_write_file(path_str(path)?, &array);
+            await_promise(path, promise).await?;
+            Ok(())
+        })
+    }
+
+    fn list_dir<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>, PanoError>> {
+        Box::pin(async move {
+            let names =
+                await_promise(path, crate::wasm_bind::browser_read_dir(path_str(path)?)).await?;
+            let mut names: Vec<String> = js_sys::Array::from(&names)
+                .iter()
+                .filter_map(|name| name.as_string())
+                .collect();
+            names.sort();
+            Ok(names)
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move {
+            match path.to_str() {
+                Some(path_str) => await_promise(path, crate::wasm_bind::browser_exists(path_str))
+                    .await
+                    .is_ok_and(|exists| exists.is_truthy()),
+                None => false,
+            }
+        })
+    }
+
+    fn is_dir<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move {
+            match path.to_str() {
+                Some(path_str) => {
+                    await_promise(path, crate::wasm_bind::browser_is_directory(path_str))
+                        .await
+                        .is_ok_and(|is_dir| is_dir.is_truthy())
+                }
+                None => false,
+            }
+        })
+    }
+
+    fn rename<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            let promise = crate::wasm_bind::browser_rename(path_str(from)?, path_str(to)?);
+            await_promise(from, promise).await?;
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            await_promise(path, crate::wasm_bind::browser_remove_file(path_str(path)?)).await?;
+            Ok(())
+        })
+    }
+}
+
+// files in the memory, the directories are the parents of the files
+#[derive(Default)]
+pub struct MemoryStorage {
+    files: RefCell<BTreeMap<PathBuf, Vec<u8>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn read<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<u8>, PanoError>> {
+        Box::pin(async move {
+            self.files
+                .borrow()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io_error(path, "not found"))
+        })
+    }
+
+    fn write<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            self.files.borrow_mut().insert(path.to_path_buf(), data);
+            Ok(())
+        })
+    }
+
+    fn list_dir<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>, PanoError>> {
+        Box::pin(async move {
+            let mut names: Vec<String> = self
+                .files
+                .borrow()
+                .keys()
+                .filter_map(|file| file.strip_prefix(path).ok())
+                .filter_map(|rest| rest.iter().next())
+                .map(|name| name.to_string_lossy().to_string())
+                .collect();
+            names.dedup();
+            Ok(names)
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move {
+            self.files
+                .borrow()
+                .keys()
+                .any(|file| file.starts_with(path))
+        })
+    }
+
+    fn is_dir<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move {
+            self.files
+                .borrow()
+                .keys()
+                .any(|file| file.starts_with(path) && file != path)
+        })
+    }
+
+    fn rename<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            let mut files = self.files.borrow_mut();
+            let data = files
+                .remove(from)
+                .ok_or_else(|| io_error(from, "not found"))?;
+            files.insert(to.to_path_buf(), data);
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move {
+            self.files
+                .borrow_mut()
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| io_error(path, "not found"))
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct NativeStorage;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Storage for NativeStorage {
+    fn read<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<u8>, PanoError>> {
+        Box::pin(async move { std::fs::read(path).map_err(|e| io_error(path, e)) })
+    }
+
+    fn write<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move { std::fs::write(path, data).map_err(|e| io_error(path, e)) })
+    }
+
+    fn list_dir<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>, PanoError>> {
+        Box::pin(async move {
+            let mut names = vec![];
+            for entry in std::fs::read_dir(path).map_err(|e| io_error(path, e))? {
+                let entry = entry.map_err(|e| io_error(path, e))?;
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+            names.sort();
+            Ok(names)
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move { path.exists() })
+    }
+
+    fn is_dir<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, bool> {
+        Box::pin(async move { path.is_dir() })
+    }
+
+    fn rename<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move { std::fs::rename(from, to).map_err(|e| io_error(from, e)) })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<(), PanoError>> {
+        Box::pin(async move { std::fs::remove_file(path).map_err(|e| io_error(path, e)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn memory_storage_keeps_directories_of_files() {
+        let storage = MemoryStorage::default();
+        block_on(async {
+            storage
+                .write(Path::new("dir/b.png"), vec![2])
+                .await
+                .unwrap();
+            storage
+                .write(Path::new("dir/a/c.png"), vec![3])
+                .await
+                .unwrap();
+            assert_eq!(
+                storage.list_dir(Path::new("dir")).await.unwrap(),
+                vec!["a", "b.png"]
+            );
+            assert!(storage.is_dir(Path::new("dir/a")).await);
+            assert!(!storage.is_dir(Path::new("dir/b.png")).await);
+            assert!(!storage.exists(Path::new("dir/d.png")).await);
+
+            storage
+                .rename(Path::new("dir/b.png"), Path::new("dir/a/c.png"))
+                .await
+                .unwrap();
+            assert_eq!(
+                storage.read(Path::new("dir/a/c.png")).await.unwrap(),
+                vec![2]
+            );
+            assert!(storage.read(Path::new("dir/b.png")).await.is_err());
+            storage.remove(Path::new("dir/a/c.png")).await.unwrap();
+            assert!(!storage.exists(Path::new("dir")).await);
+        });
+    }
+
+    #[test]
+    fn native_storage_reads_what_it_writes() {
+        let dir = std::env::temp_dir().join(format!("pano-storage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = NativeStorage;
+        block_on(async {
+            let path = dir.join("a.bin");
+            let renamed = dir.join("b.bin");
+            storage.write(&path, vec![1, 2, 3]).await.unwrap();
+            storage.rename(&path, &renamed).await.unwrap();
+            assert!(!storage.exists(&path).await);
+            assert_eq!(storage.read(&renamed).await.unwrap(), vec![1, 2, 3]);
+            assert_eq!(storage.list_dir(&dir).await.unwrap(), vec!["b.bin"]);
+            assert!(storage.is_dir(&dir).await);
+            storage.remove(&renamed).await.unwrap();
+            assert!(storage.read(&renamed).await.is_err());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
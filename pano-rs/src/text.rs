@@ -0,0 +1,64 @@
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+
+// DejaVu Sans, see fonts/LICENSE-DejaVu.txt
+const FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+// transparent margin, so that the edges are not blurred by the texture filtering
+const PADDING: f32 = 2.0;
+
+// rasterizes `text` (lines separated by '\n') with the bundled font,
+// None when nothing is drawn, e.g. for an empty text
+pub fn rasterize_text(text: &str, size: f32, color: [f32; 3]) -> Option<image::RgbaImage> {
+    let font = FontRef::try_from_slice(FONT).unwrap();
+    let font = font.as_scaled(PxScale::from(size));
+    let line_height = font.height() + font.line_gap();
+
+    let mut glyphs = vec![];
+    let mut width: f32 = 0.0;
+    let lines: Vec<&str> = text.lines().collect();
+    for (row, line) in lines.iter().enumerate() {
+        let mut x = PADDING;
+        let mut prev = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(prev) = prev {
+                x += font.kern(prev, id);
+            }
+            glyphs.push(id.with_scale_and_position(
+                font.scale(),
+                point(x, PADDING + font.ascent() + row as f32 * line_height),
+            ));
+            x += font.h_advance(id);
+            prev = Some(id);
+        }
+        width = width.max(x - PADDING);
+    }
+    let height = line_height * lines.len() as f32;
+    if width < 1.0 || height < 1.0 {
+        return None;
+    }
+    let width = width + 2.0 * PADDING;
+    let height = height + 2.0 * PADDING;
+
+    let mut image = image::RgbaImage::new(width.ceil() as u32, height.ceil() as u32);
+    let rgb = [
+        (color[0] * 255.0).round() as u8,
+        (color[1] * 255.0).round() as u8,
+        (color[2] * 255.0).round() as u8,
+    ];
+    for glyph in glyphs {
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let x = x as i32 + bounds.min.x as i32;
+                let y = y as i32 + bounds.min.y as i32;
+                if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+                    return;
+                }
+                let pixel = image.get_pixel_mut(x as u32, y as u32);
+                let alpha = ((coverage.min(1.0) * 255.0).round() as u8).max(pixel[3]);
+                *pixel = image::Rgba([rgb[0], rgb[1], rgb[2], alpha]);
+            });
+        }
+    }
+    Some(image)
+}
@@ -1,5 +1,3 @@
-use std::sync::{Arc, RwLock};
-
 use js_sys::ArrayBuffer;
 use wasm_bindgen::prelude::*;
 
@@ -32,25 +30,77 @@ extern "C" {
     #[wasm_bindgen(js_name = writeFileSync, catch)]
     pub fn write_file(path: &str, data: &Uint8Array) -> Result<(), JsValue>;
 
+    #[wasm_bindgen(js_name = existsSync)]
+    pub fn exists(path: &str) -> bool;
+
+    #[wasm_bindgen(js_name = renameSync, catch)]
+    pub fn rename_file(from: &str, to: &str) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(js_name = unlinkSync, catch)]
+    pub fn remove_file(path: &str) -> Result<(), JsValue>;
+
     #[wasm_bindgen(js_name = is_directory, catch)]
-    pub fn is_directory_(path: &str) -> Result<JsValue, JsValue>;
+    fn is_directory_(path: &str) -> Result<js_sys::Promise, JsValue>;
+
+    #[wasm_bindgen(js_name = readdirSync, catch)]
+    pub fn read_dir(path: &str) -> Result<js_sys::Array, JsValue>;
+
+    #[wasm_bindgen(js_name = user_data_path, catch)]
+    fn user_data_path_() -> Result<js_sys::Promise, JsValue>;
+
+    // false in the standalone browser build
+    #[wasm_bindgen(js_name = isElectron)]
+    pub fn is_electron() -> bool;
+}
+
+// the files of the standalone browser build, the functions return promises
+#[wasm_bindgen(raw_module = "../browser_fs.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = readFile)]
+    pub fn browser_read_file(path: &str) -> js_sys::Promise;
+
+    #[wasm_bindgen(js_name = writeFile)]
+    pub fn browser_write_file(path: &str, data: &Uint8Array) -> js_sys::Promise;
+
+    #[wasm_bindgen(js_name = readDir)]
+    pub fn browser_read_dir(path: &str) -> js_sys::Promise;
+
+    #[wasm_bindgen(js_name = exists)]
+    pub fn browser_exists(path: &str) -> js_sys::Promise;
+
+    #[wasm_bindgen(js_name = isDirectory)]
+    pub fn browser_is_directory(path: &str) -> js_sys::Promise;
+
+    #[wasm_bindgen(js_name = rename)]
+    pub fn browser_rename(from: &str, to: &str) -> js_sys::Promise;
+
+    #[wasm_bindgen(js_name = removeFile)]
+    pub fn browser_remove_file(path: &str) -> js_sys::Promise;
+}
+
+// false for a missing path
+pub async fn is_directory(path: &str) -> Result<bool, JsValue> {
+    let ret = wasm_bindgen_futures::JsFuture::from(is_directory_(path)?).await?;
+    Ok(ret.as_bool().unwrap_or(false))
 }
 
-pub fn is_directory(path: &str) -> bool {
-    let promise: js_sys::Promise = is_directory_(path).unwrap().into();
-    let ret: Arc<RwLock<bool>> = Arc::new(RwLock::new(false));
-    let ret_cloned = ret.clone();
-    wasm_bindgen_futures::spawn_local(async move {
-        let ret_or_undefined = wasm_bindgen_futures::JsFuture::from(promise).await.unwrap();
-        if let Some(ret_) = ret_or_undefined.as_bool() {
-            *ret_cloned.write().unwrap() = ret_;
-        } else {
-            *ret_cloned.write().unwrap() = false;
-        }
-        crate::console_log!("ret_cloned {:?}", ret_cloned);
+// app.getPath("userData") of Electron
+pub async fn user_data_path() -> Result<String, JsValue> {
+    let ret = wasm_bindgen_futures::JsFuture::from(user_data_path_()?).await?;
+    ret.as_string()
+        .ok_or_else(|| JsValue::from_str("userData is not a string"))
+}
+
+// lets the browser handle events and repaint during a long task
+pub async fn yield_now() -> Result<(), JsValue> {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        web_sys::window()
+            .unwrap()
+            .set_timeout_with_callback(&resolve)
+            .unwrap();
     });
-    crate::console_log!("ret {:?}", ret);
-    return *ret.clone().read().unwrap();
+    wasm_bindgen_futures::JsFuture::from(promise).await?;
+    Ok(())
 }
 
 #[wasm_bindgen(raw_module = "../dialog.js")]
@@ -58,11 +108,37 @@ extern "C" {
     #[wasm_bindgen(js_name = showOpenDirectoryDialog, catch)]
     pub fn show_open_directory_dialog() -> Result<JsValue, JsValue>;
 
-    #[wasm_bindgen(js_name = showOpenPngDialog, catch)]
-    pub fn show_open_png_dialog() -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = showOpenImageDialog, catch)]
+    pub fn show_open_image_dialog() -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_name = showSaveImageDialog, catch)]
+    pub fn show_save_image_dialog() -> Result<JsValue, JsValue>;
 
-    #[wasm_bindgen(js_name = showSavePngDialog, catch)]
-    pub fn show_save_png_dialog() -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = showSaveAnimationDialog, catch)]
+    pub fn show_save_animation_dialog() -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_name = showOpenProjectDialog, catch)]
+    pub fn show_open_project_dialog() -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_name = showSaveProjectDialog, catch)]
+    pub fn show_save_project_dialog() -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_name = showOpenJsonDialog, catch)]
+    pub fn show_open_json_dialog() -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_name = showSaveJsonDialog, catch)]
+    pub fn show_save_json_dialog() -> Result<JsValue, JsValue>;
+}
+
+#[wasm_bindgen(raw_module = "../image.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = encodeWebp, catch)]
+    pub fn encode_webp(
+        data: &Uint8Array,
+        width: u32,
+        height: u32,
+        quality: f32,
+    ) -> Result<Uint8Array, JsValue>;
 }
 
 #[wasm_bindgen(raw_module = "../electron_on.js")]
@@ -71,6 +147,16 @@ extern "C" {
     pub fn set_on_click_export_png(func: &js_sys::Function);
     #[wasm_bindgen(js_name = set_on_click_import_png)]
     pub fn set_on_click_import_png(func: &js_sys::Function);
+    #[wasm_bindgen(js_name = set_on_click_save_project)]
+    pub fn set_on_click_save_project(func: &js_sys::Function);
+    #[wasm_bindgen(js_name = set_on_click_open_project)]
+    pub fn set_on_click_open_project(func: &js_sys::Function);
+    #[wasm_bindgen(js_name = set_on_click_undo)]
+    pub fn set_on_click_undo(func: &js_sys::Function);
+    #[wasm_bindgen(js_name = set_on_click_redo)]
+    pub fn set_on_click_redo(func: &js_sys::Function);
+    #[wasm_bindgen(js_name = set_on_drop_file)]
+    pub fn set_on_drop_file(func: &js_sys::Function);
 }
 
 #[macro_export]
@@ -5,18 +5,20 @@ use std::path::Path;
 
 use web_sys::{WebGlProgram, WebGlShader, WebGlUniformLocation};
 
+use crate::error::PanoError;
 use crate::file_io::read_binary;
 use crate::WebGl2RenderingContext;
 
-pub fn read_shader(
+pub async fn read_shader(
     path: &Path,
     context: &WebGl2RenderingContext,
     shader_type: u32,
-) -> Result<WebGlShader, String> {
+) -> Result<WebGlShader, PanoError> {
+    let source = read_binary(path).await?;
     compile_shader(
         context,
         shader_type,
-        std::str::from_utf8(read_binary(path).as_slice()).unwrap(),
+        std::str::from_utf8(source.as_slice()).map_err(|e| PanoError::Decode(e.to_string()))?,
     )
 }
 
@@ -24,10 +26,10 @@ pub fn compile_shader(
     context: &WebGl2RenderingContext,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
+) -> Result<WebGlShader, PanoError> {
     let shader = context
         .create_shader(shader_type)
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+        .ok_or_else(|| PanoError::Gl(String::from("Unable to create shader object")))?;
     context.shader_source(&shader, source);
     context.compile_shader(&shader);
 
@@ -38,9 +40,11 @@ pub fn compile_shader(
     {
         Ok(shader)
     } else {
-        Err(context
-            .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unknown error creating shader")))
+        Err(PanoError::Gl(
+            context
+                .get_shader_info_log(&shader)
+                .unwrap_or_else(|| String::from("Unknown error creating shader")),
+        ))
     }
 }
 
@@ -48,10 +52,10 @@ pub fn link_program(
     context: &WebGl2RenderingContext,
     vert_shader: &WebGlShader,
     frag_shader: &WebGlShader,
-) -> Result<WebGlProgram, String> {
+) -> Result<WebGlProgram, PanoError> {
     let program = context
         .create_program()
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+        .ok_or_else(|| PanoError::Gl(String::from("Unable to create shader object")))?;
 
     context.attach_shader(&program, vert_shader);
     context.attach_shader(&program, frag_shader);
@@ -64,9 +68,11 @@ pub fn link_program(
     {
         Ok(program)
     } else {
-        Err(context
-            .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("Unknown error creating program object")))
+        Err(PanoError::Gl(
+            context
+                .get_program_info_log(&program)
+                .unwrap_or_else(|| String::from("Unknown error creating program object")),
+        ))
     }
 }
 
@@ -74,13 +80,13 @@ pub fn get_uniform_locations(
     context: &WebGl2RenderingContext,
     program: &WebGlProgram,
     keys: Vec<String>,
-) -> Result<HashMap<String, WebGlUniformLocation>, String> {
+) -> Result<HashMap<String, WebGlUniformLocation>, PanoError> {
     let mut locations: HashMap<String, WebGlUniformLocation> = HashMap::new();
     for key in keys {
-        locations.insert(
-            key.clone(),
-            context.get_uniform_location(program, &key).unwrap(),
-        );
+        let location = context
+            .get_uniform_location(program, &key)
+            .ok_or_else(|| PanoError::Gl(format!("uniform `{}` is not found", key)))?;
+        locations.insert(key, location);
     }
     Ok(locations)
 }
@@ -0,0 +1,87 @@
+import { addOpenedFile, readDir } from './browser_fs.js';
+
+// the dialogs of the standalone browser build. the opened files are in the memory and
+// the saved files are downloaded, except the projects which are kept in IndexedDB.
+// the paths follow browser_fs.js.
+
+const PROJECT_DIR = 'projects';
+
+export const imageAccept = '.png,.jpg,.jpeg,.webp,.tif,.tiff,.hdr,.exr';
+
+// resolves undefined when canceled
+function pickFiles(accept, directory) {
+    return new Promise((resolve) => {
+        const input = document.createElement('input');
+        input.type = 'file';
+        input.accept = accept;
+        input.webkitdirectory = directory;
+        input.addEventListener('change', () => resolve(Array.from(input.files)));
+        input.addEventListener('cancel', () => resolve(undefined));
+        input.click();
+    });
+}
+
+export async function openFile(accept) {
+    const files = await pickFiles(accept, false);
+    if (files === undefined || files.length === 0) {
+        return undefined;
+    }
+    return addOpenedFile(files[0]);
+}
+
+// the files keep the folders under the picked one
+export async function openDirectory() {
+    const files = await pickFiles('', true);
+    if (files === undefined || files.length === 0) {
+        return undefined;
+    }
+    for (const file of files) {
+        await addOpenedFile(file);
+    }
+    return 'opened/' + files[0].webkitRelativePath.split('/')[0];
+}
+
+export function saveFile(defaultName) {
+    const name = prompt('ファイル名', defaultName);
+    if (!name) {
+        return Promise.resolve(undefined);
+    }
+    return Promise.resolve('downloads/' + name);
+}
+
+export function saveProject() {
+    const name = prompt('プロジェクト名', 'untitled');
+    if (!name) {
+        return Promise.resolve(undefined);
+    }
+    return Promise.resolve(PROJECT_DIR + '/' + name.replace(/\.pano$/, '') + '.pano');
+}
+
+// a project in IndexedDB, or a .pano file
+export async function openProject() {
+    const names = (await readDir(PROJECT_DIR)).sort();
+    return new Promise((resolve) => {
+        const dialog = document.createElement('dialog');
+        const close = (path) => {
+            dialog.close();
+            dialog.remove();
+            resolve(path);
+        };
+        const button = (label, onclick) => {
+            const button = document.createElement('button');
+            button.textContent = label;
+            button.onclick = onclick;
+            return button;
+        };
+        dialog.append('プロジェクトを開く');
+        for (const name of names) {
+            dialog.append(document.createElement('br'));
+            dialog.append(button(name.replace(/\.pano$/, ''), () => close(PROJECT_DIR + '/' + name)));
+        }
+        dialog.append(document.createElement('br'));
+        dialog.append(button('ファイルから開く', async () => close(await openFile('.pano'))));
+        dialog.append(button('キャンセル', () => close(undefined)));
+        document.body.append(dialog);
+        dialog.showModal();
+    });
+}
@@ -0,0 +1,124 @@
+// the files of the standalone browser build, which has no fs of node.
+//   opened/...    files picked with <input type=file> or dropped, in the memory
+//   downloads/... the browser downloads the written files, which are also kept in the memory
+//   otherwise     documents in IndexedDB, e.g. projects, the recovery snapshot and brush presets
+// files written under opened/ are downloaded too, e.g. the outputs of the cube face conversion.
+
+const DB_NAME = 'pano';
+const STORE_NAME = 'files';
+
+const memoryFiles = new Map();
+
+function isMemoryPath(path) {
+    return path.startsWith('opened/') || path.startsWith('downloads/');
+}
+
+function basename(path) {
+    return path.substring(path.lastIndexOf('/') + 1);
+}
+
+// "./a/b" and "a/b" are the same file
+function normalize(path) {
+    return path.replace(/^\.\//, '').replace(/\/+$/, '');
+}
+
+let db = undefined;
+
+function openDb() {
+    if (db === undefined) {
+        db = new Promise((resolve, reject) => {
+            const request = indexedDB.open(DB_NAME, 1);
+            request.onupgradeneeded = () => request.result.createObjectStore(STORE_NAME);
+            request.onsuccess = () => resolve(request.result);
+            request.onerror = () => reject(request.error);
+        });
+    }
+    return db;
+}
+
+async function transact(mode, f) {
+    const db = await openDb();
+    return new Promise((resolve, reject) => {
+        const request = f(db.transaction(STORE_NAME, mode).objectStore(STORE_NAME));
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    });
+}
+
+async function allPaths() {
+    const keys = await transact('readonly', (store) => store.getAllKeys());
+    return keys.concat(Array.from(memoryFiles.keys()));
+}
+
+function download(path, data) {
+    const url = URL.createObjectURL(new Blob([data]));
+    const a = document.createElement('a');
+    a.href = url;
+    a.download = basename(path);
+    a.click();
+    setTimeout(() => URL.revokeObjectURL(url), 1000);
+}
+
+// keeps a picked or dropped file, and returns its path
+export async function addOpenedFile(file) {
+    const path = 'opened/' + (file.webkitRelativePath || file.name);
+    memoryFiles.set(path, new Uint8Array(await file.arrayBuffer()));
+    return path;
+}
+
+export async function readFile(path) {
+    path = normalize(path);
+    const data = isMemoryPath(path)
+        ? memoryFiles.get(path)
+        : await transact('readonly', (store) => store.get(path));
+    if (data === undefined) {
+        throw new Error('no such file: ' + path);
+    }
+    return data;
+}
+
+export async function writeFile(path, data) {
+    path = normalize(path);
+    if (isMemoryPath(path)) {
+        memoryFiles.set(path, data);
+        download(path, data);
+    } else {
+        await transact('readwrite', (store) => store.put(data, path));
+    }
+}
+
+export async function readDir(path) {
+    const prefix = normalize(path) + '/';
+    const names = new Set();
+    for (const file of await allPaths()) {
+        if (file.startsWith(prefix)) {
+            names.add(file.substring(prefix.length).split('/')[0]);
+        }
+    }
+    return Array.from(names);
+}
+
+export async function exists(path) {
+    path = normalize(path);
+    const prefix = path + '/';
+    return (await allPaths()).some((file) => file === path || file.startsWith(prefix));
+}
+
+export async function isDirectory(path) {
+    const prefix = normalize(path) + '/';
+    return (await allPaths()).some((file) => file.startsWith(prefix));
+}
+
+export async function rename(from, to) {
+    await writeFile(to, await readFile(from));
+    await removeFile(from);
+}
+
+export async function removeFile(path) {
+    path = normalize(path);
+    if (isMemoryPath(path)) {
+        memoryFiles.delete(path);
+    } else {
+        await transact('readwrite', (store) => store.delete(path));
+    }
+}
@@ -1,6 +1,13 @@
-const ipcRenderer = window.requires.ipcRenderer;
+import { isElectron } from './fs.js';
+import * as browser from './browser_dialog.js';
+
+// the standalone browser build has no ipc, its dialogs are in browser_dialog.js
+const ipcRenderer = window.requires?.ipcRenderer;
 
 export function showOpenDirectoryDialog() {
+    if (!isElectron()) {
+        return browser.openDirectory();
+    }
     return ipcRenderer.invoke('showOpenDirectoryDialog')
         .then((data) => {
             if (data !== undefined) {
@@ -13,8 +20,82 @@ export function showOpenDirectoryDialog() {
         });
 }
 
-export function showOpenPngDialog() {
-    return ipcRenderer.invoke('showOpenPngDialog')
+export function showOpenImageDialog() {
+    if (!isElectron()) {
+        return browser.openFile(browser.imageAccept);
+    }
+    return ipcRenderer.invoke('showOpenImageDialog')
+        .then((data) => {
+            if (data !== undefined) {
+                return data[0];
+            }
+            return data;
+        })
+        .catch((err) => {
+            alert(err);
+        });
+}
+
+export function showSaveImageDialog() {
+    if (!isElectron()) {
+        return browser.saveFile('panorama.png');
+    }
+    return ipcRenderer.invoke('showSaveImageDialog')
+        .then((data) => {
+            return data;
+        })
+        .catch((err) => {
+            alert(err);
+        });
+}
+
+export function showSaveAnimationDialog() {
+    if (!isElectron()) {
+        return browser.saveFile('animation.png');
+    }
+    return ipcRenderer.invoke('showSaveAnimationDialog')
+        .then((data) => {
+            return data;
+        })
+        .catch((err) => {
+            alert(err);
+        });
+}
+
+export function showOpenJsonDialog() {
+    if (!isElectron()) {
+        return browser.openFile('.json');
+    }
+    return ipcRenderer.invoke('showOpenJsonDialog')
+        .then((data) => {
+            if (data !== undefined) {
+                return data[0];
+            }
+            return data;
+        })
+        .catch((err) => {
+            alert(err);
+        });
+}
+
+export function showSaveJsonDialog() {
+    if (!isElectron()) {
+        return browser.saveFile('brush_presets.json');
+    }
+    return ipcRenderer.invoke('showSaveJsonDialog')
+        .then((data) => {
+            return data;
+        })
+        .catch((err) => {
+            alert(err);
+        });
+}
+
+export function showOpenProjectDialog() {
+    if (!isElectron()) {
+        return browser.openProject();
+    }
+    return ipcRenderer.invoke('showOpenProjectDialog')
         .then((data) => {
             if (data !== undefined) {
                 return data[0];
@@ -26,8 +107,11 @@ export function showOpenPngDialog() {
         });
 }
 
-export function showSavePngDialog() {
-    return ipcRenderer.invoke('showSavePngDialog')
+export function showSaveProjectDialog() {
+    if (!isElectron()) {
+        return browser.saveProject();
+    }
+    return ipcRenderer.invoke('showSaveProjectDialog')
         .then((data) => {
             return data;
         })
@@ -21,3 +21,63 @@ export function on_click_import_png() {
 export function set_on_click_import_png(func) {
     on_click_import_png_ = func;
 }
+
+let on_click_save_project_ = undefined;
+
+export function on_click_save_project() {
+    if (on_click_save_project_ !== undefined) {
+        on_click_save_project_();
+    }
+}
+
+export function set_on_click_save_project(func) {
+    on_click_save_project_ = func;
+}
+
+let on_click_open_project_ = undefined;
+
+export function on_click_open_project() {
+    if (on_click_open_project_ !== undefined) {
+        on_click_open_project_();
+    }
+}
+
+export function set_on_click_open_project(func) {
+    on_click_open_project_ = func;
+}
+
+let on_click_undo_ = undefined;
+
+export function on_click_undo() {
+    if (on_click_undo_ !== undefined) {
+        on_click_undo_();
+    }
+}
+
+export function set_on_click_undo(func) {
+    on_click_undo_ = func;
+}
+
+let on_click_redo_ = undefined;
+
+export function on_click_redo() {
+    if (on_click_redo_ !== undefined) {
+        on_click_redo_();
+    }
+}
+
+export function set_on_click_redo(func) {
+    on_click_redo_ = func;
+}
+
+let on_drop_file_ = undefined;
+
+export function on_drop_file(path) {
+    if (on_drop_file_ !== undefined) {
+        on_drop_file_(path);
+    }
+}
+
+export function set_on_drop_file(func) {
+    on_drop_file_ = func;
+}
@@ -1,15 +1,23 @@
-const fs = window.requires.fs;
-export const readFileSync = fs.readFileSync;
-export const writeFileSync = fs.writeFileSync;
+// undefined in the standalone browser build, see browser_fs.js
+const fs = window.requires?.fs;
+export const readFileSync = fs?.readFileSync;
+export const writeFileSync = fs?.writeFileSync;
+export const existsSync = fs?.existsSync;
+export const renameSync = fs?.renameSync;
+export const unlinkSync = fs?.unlinkSync;
+export const readdirSync = fs?.readdirSync;
 
-const ipcRenderer = window.requires.ipcRenderer;
+const ipcRenderer = window.requires?.ipcRenderer;
+
+export function isElectron() {
+    return window.requires !== undefined;
+}
 
 export function is_directory(path) {
-    return ipcRenderer.invoke('is_directory', path)
-        .then((ret) => {
-            return ret;
-        })
-        .catch((err) => {
-            alert(err);
-        });
+    return ipcRenderer.invoke('is_directory', path);
+}
+
+// app.getPath('userData') of Electron, where the app keeps its own files
+export function user_data_path() {
+    return ipcRenderer.invoke('get_user_data_path');
 }
@@ -0,0 +1,19 @@
+// encodes rgba pixels with the canvas of Chromium, the image crate has no webp encoder
+export function encodeWebp(data, width, height, quality) {
+    const canvas = document.createElement('canvas');
+    canvas.width = width;
+    canvas.height = height;
+    const context = canvas.getContext('2d');
+    const pixels = new Uint8ClampedArray(data.buffer, data.byteOffset, data.length);
+    context.putImageData(new ImageData(pixels, width, height), 0, 0);
+    const url = canvas.toDataURL('image/webp', quality);
+    if (!url.startsWith('data:image/webp')) {
+        throw new Error('webp is not supported');
+    }
+    const binary = atob(url.substring(url.indexOf(',') + 1));
+    const bytes = new Uint8Array(binary.length);
+    for (let i = 0; i < binary.length; i++) {
+        bytes[i] = binary.charCodeAt(i);
+    }
+    return bytes;
+}
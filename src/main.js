@@ -7,6 +7,19 @@ const template = Menu.buildFromTemplate([
     {
       label: "ファイル",
       submenu: [
+        {
+          label: 'プロジェクトを開く',
+          click:()=>{
+            win.webContents.send("open_project", {});
+          },
+        },
+        {
+          label: 'プロジェクトを保存',
+          click:()=>{
+            win.webContents.send("save_project", {});
+          },
+        },
+        { type:'separator' },
         {
           label:'import',
           submenu: [
@@ -36,8 +49,23 @@ const template = Menu.buildFromTemplate([
     {
       label: "編集",
       submenu: [
-        { role:'undo',  label:'元に戻す' },
-        { role:'redo',  label:'やり直す' },
+        {
+          label: '元に戻す',
+          accelerator: 'CmdOrCtrl+Z',
+          // the shortcut is handled by the keydown listener of the canvas
+          registerAccelerator: false,
+          click:()=>{
+            win.webContents.send("undo", {});
+          },
+        },
+        {
+          label: 'やり直す',
+          accelerator: 'CmdOrCtrl+Shift+Z',
+          registerAccelerator: false,
+          click:()=>{
+            win.webContents.send("redo", {});
+          },
+        },
         { type:'separator' },
         { role:'copy',  label:'コピー' },
         { role:'paste', label:'貼り付け' },
@@ -85,32 +113,108 @@ ipcMain.handle('showOpenDirectoryDialog', async (event) => {
   return filename;
 });
 
-ipcMain.handle('showOpenPngDialog', async (event) => {
+const imageFilters = [
+    {name: 'png file', extensions: ['png']},
+    {name: 'jpeg file', extensions: ['jpg', 'jpeg']},
+    {name: 'webp file', extensions: ['webp']},
+    {name: 'tiff file', extensions: ['tif', 'tiff']},
+    {name: 'radiance hdr file', extensions: ['hdr']},
+    {name: 'openexr file', extensions: ['exr']}
+];
+
+ipcMain.handle('showOpenImageDialog', async (event) => {
   let filename = dialog.showOpenDialogSync(null, {
       properties: ['openFile'],
-      title: 'Select a png image',
+      title: 'Select an image',
       defaultPath: '.',
       filters: [
-          {name: 'png file', extensions: ['png']}
+          {name: 'image file', extensions: ['png', 'jpg', 'jpeg', 'webp', 'tif', 'tiff', 'hdr', 'exr']},
+          ...imageFilters
       ]
   });
   return filename;
 });
 
-ipcMain.handle('showSavePngDialog', async (event) => {
+ipcMain.handle('showSaveImageDialog', async (event) => {
+  let filename = dialog.showSaveDialogSync(null, {
+      properties: ['showOverwriteConfirmation'],
+      title: 'Select an image',
+      defaultPath: '.',
+      filters: imageFilters
+  });
+  return filename;
+});
+
+ipcMain.handle('showSaveAnimationDialog', async (event) => {
   let filename = dialog.showSaveDialogSync(null, {
       properties: ['showOverwriteConfirmation'],
-      title: 'Select a png image',
+      title: 'Select an animation',
+      defaultPath: '.',
+      filters: [
+          {name: 'animated png file', extensions: ['png', 'apng']},
+          {name: 'gif file', extensions: ['gif']}
+      ]
+  });
+  return filename;
+});
+
+ipcMain.handle('showOpenProjectDialog', async (event) => {
+  let filename = dialog.showOpenDialogSync(null, {
+      properties: ['openFile'],
+      title: 'Select a pano project',
       defaultPath: '.',
       filters: [
-          {name: 'png file', extensions: ['png']}
+          {name: 'pano project', extensions: ['pano']}
+      ]
+  });
+  return filename;
+});
+
+ipcMain.handle('showSaveProjectDialog', async (event) => {
+  let filename = dialog.showSaveDialogSync(null, {
+      properties: ['showOverwriteConfirmation'],
+      title: 'Select a pano project',
+      defaultPath: '.',
+      filters: [
+          {name: 'pano project', extensions: ['pano']}
+      ]
+  });
+  return filename;
+});
+
+ipcMain.handle('showOpenJsonDialog', async (event) => {
+  let filename = dialog.showOpenDialogSync(null, {
+      properties: ['openFile'],
+      title: 'Select a json file',
+      defaultPath: '.',
+      filters: [
+          {name: 'json file', extensions: ['json']}
+      ]
+  });
+  return filename;
+});
+
+ipcMain.handle('showSaveJsonDialog', async (event) => {
+  let filename = dialog.showSaveDialogSync(null, {
+      properties: ['showOverwriteConfirmation'],
+      title: 'Select a json file',
+      defaultPath: '.',
+      filters: [
+          {name: 'json file', extensions: ['json']}
       ]
   });
   return filename;
 });
 
 ipcMain.handle('is_directory', async(event, path) => {
-  let stats = fs.statSync(path);
-  let ret = stats.isDirectory();
-  return ret;
+  // false for a missing path
+  try {
+    return fs.statSync(path).isDirectory();
+  } catch (err) {
+    return false;
+  }
+});
+
+ipcMain.handle('get_user_data_path', async (event) => {
+  return app.getPath('userData');
 });
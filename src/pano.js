@@ -1,18 +1,52 @@
 import init from "./pkg/pano.js";
 import * as wasm from './pkg/pano.js';
-import { on_click_export_png, on_click_import_png } from './electron_on.js';
+import { on_click_export_png, on_click_import_png, on_click_save_project, on_click_open_project, on_click_undo, on_click_redo, on_drop_file } from './electron_on.js';
+import { isElectron } from './fs.js';
+import { addOpenedFile } from './browser_fs.js';
 
 // https://github.com/anderejd/electron-wasm-rust-example
 async function run() {
 	await init('./pkg/pano_bg.wasm');
     wasm.start();
 
+    // a dropped image is imported as a layer, a dropped project is opened
+    document.addEventListener("dragover", (event) => {
+        event.preventDefault();
+    });
+    document.addEventListener("drop", async (event) => {
+        event.preventDefault();
+        for (const file of event.dataTransfer.files) {
+            // electron tells the path of the file
+            on_drop_file(isElectron() ? file.path : await addOpenedFile(file));
+        }
+    });
+
+    if (!isElectron()) {
+        // the standalone browser build, which has no menu of electron
+        if ('serviceWorker' in navigator && location.protocol.startsWith('http')) {
+            navigator.serviceWorker.register('./service_worker.js');
+        }
+        return;
+    }
+
     window.api.on("export_png", (event, arg) => {
         on_click_export_png();
     });
     window.api.on("import_png", (event, arg) => {
         on_click_import_png();
     });
+    window.api.on("save_project", (event, arg) => {
+        on_click_save_project();
+    });
+    window.api.on("open_project", (event, arg) => {
+        on_click_open_project();
+    });
+    window.api.on("undo", (event, arg) => {
+        on_click_undo();
+    });
+    window.api.on("redo", (event, arg) => {
+        on_click_redo();
+    });
     /*
     var xrApp = new wasm.XrApp();
     xrApp.init()
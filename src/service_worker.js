@@ -0,0 +1,18 @@
+// lets the standalone browser build start offline as a PWA.
+// the network is tried first so that a new build is used, and the cache when offline.
+const CACHE_NAME = 'pano';
+
+self.addEventListener('fetch', (event) => {
+    if (event.request.method !== 'GET') {
+        return;
+    }
+    event.respondWith(
+        fetch(event.request)
+            .then((response) => {
+                const copy = response.clone();
+                caches.open(CACHE_NAME).then((cache) => cache.put(event.request, copy));
+                return response;
+            })
+            .catch(() => caches.match(event.request))
+    );
+});